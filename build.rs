@@ -0,0 +1,232 @@
+//! Generates `shaders.rs`, which embeds the SPIR-V of every shader in
+//! `src/shaders` and of the permutations listed in `src/shaders/variants.toml`
+//! into the engine. With the `build-shaders` feature they are compiled here,
+//! otherwise the prebuilt files `src/shaders/compile.sh` writes are embedded,
+//! and the build fails if one is older than its shader.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+#[cfg(not(feature = "build-shaders"))]
+use std::time::{Duration, SystemTime};
+#[cfg(feature = "build-shaders")]
+use shaderc_build::{CompileOptions, Compiler, IncludeType, ResolvedInclude, ShaderKind};
+
+/// Defines of one permutation, sorted by name.
+type Defines = Vec<(String, String)>;
+
+/// How much older than its shader a prebuilt file may be. A fresh checkout
+/// writes the shaders and their SPIR-V in no particular order.
+#[cfg(not(feature = "build-shaders"))]
+const STALE_SLACK: Duration = Duration::from_secs(2);
+
+fn main() {
+    let shader_dir = Path::new(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("src/shaders");
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    println!("cargo:rerun-if-changed={}", shader_dir.display());
+
+    let variants = read_variants(&shader_dir.join("variants.toml"));
+    let mut sources = std::fs::read_dir(&shader_dir)
+        .unwrap_or_else(|e| fail(&format!("Failed to list `{}`: {}", shader_dir.display(), e)))
+        .map(|entry| {
+            entry
+                .unwrap_or_else(|e| fail(&format!("Failed to list `{}`: {}", shader_dir.display(), e)))
+                .path()
+        })
+        .filter(|path| is_shader(path))
+        .collect::<Vec<_>>();
+    sources.sort();
+    for name in variants.keys() {
+        if !sources.iter().any(|path| path.file_name().unwrap().to_string_lossy() == *name) {
+            fail(&format!("`variants.toml` lists `{}`, which is not a shader in `{}`.", name, shader_dir.display()));
+        }
+    }
+
+    #[cfg(feature = "build-shaders")]
+    let compiler = Compiler::new().unwrap_or_else(|| fail("Failed to create the shader compiler."));
+    let mut errors = Vec::new();
+    let mut generated = String::from("&[\n");
+    for (index, path) in sources.iter().enumerate() {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let permutations = variants.get(&name).map(permutations).unwrap_or_default();
+        for (variant, defines) in std::iter::once(Vec::new()).chain(permutations).enumerate() {
+            #[cfg(feature = "build-shaders")]
+            let spirv = compile(&compiler, path, &shader_dir, &defines);
+            #[cfg(not(feature = "build-shaders"))]
+            let spirv = prebuilt(path, &defines);
+            match spirv {
+                Ok(spirv) => {
+                    let file = out_dir.join(format!("shader_{}_{}.spv", index, variant));
+                    std::fs::write(&file, spirv)
+                        .unwrap_or_else(|e| fail(&format!("Failed to write `{}`: {}", file.display(), e)));
+                    let defines = defines
+                        .iter()
+                        .map(|(name, value)| format!("({:?}, {:?})", name, value))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    writeln!(
+                        generated,
+                        "    EmbeddedShader {{ name: {:?}, defines: &[{}], spirv: include_bytes!({:?}) }},",
+                        name,
+                        defines,
+                        file.display(),
+                    ).unwrap();
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+    }
+    generated.push_str("]\n");
+
+    if !errors.is_empty() {
+        fail(&errors.join("\n\n"));
+    }
+    let file = out_dir.join("shaders.rs");
+    std::fs::write(&file, generated).unwrap_or_else(|e| fail(&format!("Failed to write `{}`: {}", file.display(), e)));
+}
+
+/// Prints `message` and stops the build without a backtrace.
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+fn is_shader(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("vert" | "frag" | "comp" | "geom" | "tesc" | "tese"),
+    )
+}
+
+/// Reads the SPIR-V `compile.sh` writes for `path`, `x.frag` compiled with
+/// `-DA=1 -DB=0` is read from `x_frag.A=1.B=0.spv`.
+#[cfg(not(feature = "build-shaders"))]
+fn prebuilt(path: &Path, defines: &Defines) -> Result<Vec<u8>, String> {
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    let stage = path.extension().unwrap().to_string_lossy();
+    let mut name = format!("{}_{}", stem, stage);
+    for (define, value) in defines {
+        write!(name, ".{}={}", define, value).unwrap();
+    }
+    let file = path.with_file_name(name + ".spv");
+    let variant = if defines.is_empty() { String::new() } else { format!(" with {:?}", defines) };
+    let spirv = std::fs::read(&file).map_err(|e| format!(
+        "Failed to read `{}`, the SPIR-V of `{}`{}. Run `src/shaders/compile.sh` or enable the `build-shaders` feature: {}",
+        file.display(),
+        path.display(),
+        variant,
+        e,
+    ))?;
+
+    let built = modified(&file)?;
+    for source in sources(path)? {
+        if modified(&source)? > built + STALE_SLACK {
+            return Err(format!(
+                "`{}`, the SPIR-V of `{}`{}, is older than `{}`. Run `src/shaders/compile.sh` or enable the `build-shaders` feature.",
+                file.display(),
+                path.display(),
+                variant,
+                source.display(),
+            ));
+        }
+    }
+    Ok(spirv)
+}
+
+#[cfg(not(feature = "build-shaders"))]
+fn modified(path: &Path) -> Result<SystemTime, String> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|e| format!("Failed to read the modification time of `{}`: {}", path.display(), e))
+}
+
+/// `path` and every file it includes, directly or not.
+#[cfg(not(feature = "build-shaders"))]
+fn sources(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut sources = vec![path.to_path_buf()];
+    let mut next = 0;
+    while let Some(source) = sources.get(next).cloned() {
+        next += 1;
+        let text = std::fs::read_to_string(&source)
+            .map_err(|e| format!("Failed to read `{}`: {}", source.display(), e))?;
+        for line in text.lines() {
+            let Some(name) = line.trim().strip_prefix("#include") else { continue };
+            let name = name.trim().trim_matches(|c| c == '"' || c == '<' || c == '>');
+            let include = source.parent().unwrap().join(name);
+            if !sources.contains(&include) {
+                sources.push(include);
+            }
+        }
+    }
+    Ok(sources)
+}
+
+#[cfg(feature = "build-shaders")]
+fn shader_kind(path: &Path) -> Option<ShaderKind> {
+    match path.extension()?.to_str()? {
+        "vert" => Some(ShaderKind::Vertex),
+        "frag" => Some(ShaderKind::Fragment),
+        "comp" => Some(ShaderKind::Compute),
+        "geom" => Some(ShaderKind::Geometry),
+        "tesc" => Some(ShaderKind::TessControl),
+        "tese" => Some(ShaderKind::TessEvaluation),
+        _ => None,
+    }
+}
+
+/// Each shader maps to its defines, each with the values to compile.
+fn read_variants(path: &Path) -> BTreeMap<String, BTreeMap<String, Vec<String>>> {
+    if !path.is_file() {
+        return BTreeMap::new();
+    }
+    let text = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| fail(&format!("Failed to read `{}`: {}", path.display(), e)));
+    toml::from_str(&text).unwrap_or_else(|e| fail(&format!("Invalid `{}`: {}", path.display(), e)))
+}
+
+/// Every combination of one value per define.
+fn permutations(axes: &BTreeMap<String, Vec<String>>) -> Vec<Defines> {
+    axes.iter().fold(vec![Vec::new()], |combinations, (name, values)| {
+        combinations
+            .iter()
+            .flat_map(|defines| values.iter().map(move |value| {
+                let mut defines = defines.clone();
+                defines.push((name.clone(), value.clone()));
+                defines
+            }))
+            .collect()
+    })
+}
+
+#[cfg(feature = "build-shaders")]
+fn compile(compiler: &Compiler, path: &Path, shader_dir: &Path, defines: &Defines) -> Result<Vec<u8>, String> {
+    let source = std::fs::read_to_string(path).map_err(|e| format!("Failed to read `{}`: {}", path.display(), e))?;
+    let mut options = CompileOptions::new().ok_or("Failed to create shader compile options.")?;
+    for (name, value) in defines {
+        options.add_macro_definition(name, Some(value));
+    }
+    options.set_include_callback(|name, include_type, requesting, _depth| {
+        let resolved = match include_type {
+            IncludeType::Relative => Path::new(requesting).parent().unwrap_or(shader_dir).join(name),
+            IncludeType::Standard => shader_dir.join(name),
+        };
+        let content = std::fs::read_to_string(&resolved)
+            .map_err(|e| format!("Failed to include `{}`: {}", resolved.display(), e))?;
+        Ok(ResolvedInclude { resolved_name: resolved.to_string_lossy().into_owned(), content })
+    });
+
+    let kind = shader_kind(path).unwrap();
+    let artifact = compiler
+        .compile_into_spirv(&source, kind, &path.to_string_lossy(), "main", Some(&options))
+        .map_err(|e| if defines.is_empty() {
+            format!("Failed to compile `{}`:\n{}", path.display(), e)
+        } else {
+            format!("Failed to compile `{}` with {:?}:\n{}", path.display(), defines, e)
+        })?;
+    if artifact.get_num_warnings() > 0 {
+        for line in artifact.get_warning_messages().lines() {
+            println!("cargo:warning={}", line);
+        }
+    }
+    Ok(artifact.as_binary_u8().to_vec())
+}
@@ -0,0 +1,25 @@
+//! Replaces the forward shaders with a toon shader. Compile it first:
+//!
+//! glslc examples/shaders/toon.vert -o examples/shaders/toon_vert.spv
+//! glslc examples/shaders/toon.frag -o examples/shaders/toon_frag.spv
+//! cargo run --example custom_shaders
+
+use anyhow::Result;
+use elingine::{Context, EngineBuilder, Game, Mat4, Material, SceneObject};
+
+struct Toon;
+
+impl Game for Toon {
+    fn init(&mut self, ctx: &mut Context) -> Result<()> {
+        ctx.objects.push(SceneObject::new(ctx.meshes[0], Mat4::from_scale(1.0), Material::default()));
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    pretty_env_logger::init();
+    EngineBuilder::new()
+        .title("Custom shaders")
+        .forward_shaders("examples/shaders/toon_vert.spv", "examples/shaders/toon_frag.spv")
+        .run(Toon)
+}
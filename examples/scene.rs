@@ -0,0 +1,89 @@
+//! Many instances of one model with different materials, lit by moving
+//! point lights. The point lights need the deferred path. Another copy of
+//! the model is streamed in after startup and appears above the grid.
+//!
+//! cargo run --example scene -- --deferred
+
+use anyhow::Result;
+use cgmath::{point3, vec3, vec4, Rad};
+use elingine::{
+    AlphaMode, Context, DebugStyle, EngineBuilder, FrameTime, Game, Mat4, Material, PointLight, SceneObject,
+    UploadTicket,
+};
+
+const GRID: i32 = 3;
+const SPACING: f32 = 2.5;
+
+#[derive(Default)]
+struct Scene {
+    /// Light orbit angle at the latest and the previous tick.
+    angle: f32,
+    previous_angle: f32,
+    /// The streamed model, until it is uploaded.
+    streamed: Option<UploadTicket>,
+}
+
+impl Game for Scene {
+    fn init(&mut self, ctx: &mut Context) -> Result<()> {
+        let mesh = ctx.meshes[0];
+        for x in -GRID..=GRID {
+            for y in -GRID..=GRID {
+                let transform = Mat4::from_translation(vec3(x as f32 * SPACING, y as f32 * SPACING, 0.0))
+                    * Mat4::from_angle_z(Rad((x * 7 + y * 3) as f32));
+                let hue = (x + GRID) as f32 / (2 * GRID) as f32;
+                let alpha_mode = match (x + y).rem_euclid(4) {
+                    0 => AlphaMode::Blend,
+                    1 => AlphaMode::Mask { cutoff: 0.5 },
+                    _ => AlphaMode::Opaque,
+                };
+                let base_color = vec4(hue, 1.0 - hue, 0.5, if alpha_mode == AlphaMode::Blend { 0.5 } else { 1.0 });
+                ctx.objects.push(SceneObject::new(mesh, transform, Material { base_color, alpha_mode }));
+            }
+        }
+
+        self.streamed = Some(ctx.uploads.load_model("resources/viking_room.obj"));
+
+        ctx.point_lights.clear();
+        for _ in 0..4 {
+            ctx.point_lights.push(PointLight::new(vec3(0.0, 0.0, 1.0), 4.0, vec3(1.0, 1.0, 1.0), 2.0));
+        }
+
+        let extent = GRID as f32 * SPACING;
+        ctx.camera.look_at(point3(extent * 1.5, extent * 1.5, extent), point3(0.0, 0.0, 0.0));
+        Ok(())
+    }
+
+    fn fixed_update(&mut self, _ctx: &mut Context, dt: f32) {
+        self.previous_angle = self.angle;
+        self.angle += dt * 0.5;
+    }
+
+    fn update(&mut self, ctx: &mut Context, frame: FrameTime) {
+        if let Some(mesh) = self.streamed.and_then(|ticket| ctx.uploads.mesh(ticket)) {
+            let transform = Mat4::from_translation(vec3(0.0, 0.0, 3.0)) * Mat4::from_scale(2.0);
+            ctx.objects.push(SceneObject::new(mesh, transform, Material::default()));
+            self.streamed = None;
+        }
+
+        let angle = self.previous_angle + (self.angle - self.previous_angle) * frame.alpha;
+        let count = ctx.point_lights.len();
+        let colors = [vec3(1.0, 0.3, 0.2), vec3(0.2, 1.0, 0.3), vec3(0.2, 0.4, 1.0), vec3(1.0, 0.9, 0.4)];
+        for (i, light) in ctx.point_lights.iter_mut().enumerate() {
+            let phase = angle + i as f32 / count as f32 * std::f32::consts::TAU;
+            let radius = GRID as f32 * SPACING * 0.6;
+            let position = vec3(phase.cos() * radius, phase.sin() * radius, 1.0);
+            *light = PointLight::new(position, 4.0, colors[i % colors.len()], 2.0);
+
+            let color = colors[i % colors.len()].extend(1.0);
+            ctx.debug_draw.sphere(position, 0.1, DebugStyle::new(color).overlay());
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    pretty_env_logger::init();
+    EngineBuilder::new()
+        .title("Scene")
+        .args(std::env::args())?
+        .run(Scene::default())
+}
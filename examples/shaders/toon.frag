@@ -0,0 +1,27 @@
+#version 450
+
+layout(location = 0) in vec3 fragNormal;
+layout(location = 1) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+layout(binding = 1) uniform sampler2D texSampler;
+
+layout(push_constant) uniform ObjectPushConstants {
+    mat4 model;
+    vec4 baseColor;
+    float alphaCutoff;
+} object;
+
+const vec3 LIGHT = normalize(vec3(0.4, 0.3, 1.0));
+
+// Quantizes simple diffuse lighting into three bands.
+void main() {
+    vec4 albedo = texture(texSampler, fragTexCoord) * object.baseColor;
+    if (albedo.a < object.alphaCutoff) {
+        discard;
+    }
+    float diffuse = max(dot(normalize(fragNormal), LIGHT), 0.0);
+    float band = diffuse > 0.6 ? 1.0 : diffuse > 0.2 ? 0.6 : 0.3;
+    outColor = vec4(albedo.rgb * band, albedo.a);
+}
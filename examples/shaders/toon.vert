@@ -0,0 +1,28 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+layout(push_constant) uniform ObjectPushConstants {
+    mat4 model;
+    vec4 baseColor;
+    float alphaCutoff;
+} object;
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inColor;
+layout(location = 2) in vec2 inTexCoord;
+layout(location = 3) in vec3 inNormal;
+
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out vec2 fragTexCoord;
+
+void main() {
+    mat4 model = ubo.model * object.model;
+    gl_Position = ubo.proj * ubo.view * model * vec4(inPosition, 1.0);
+    fragNormal = transpose(inverse(mat3(model))) * inNormal;
+    fragTexCoord = inTexCoord;
+}
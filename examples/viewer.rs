@@ -0,0 +1,36 @@
+//! Shows a single OBJ model with an orbit camera.
+//!
+//! cargo run --example viewer -- [model.obj] [texture.png] [--config=engine.toml] [--msaa=4] [--deferred]
+
+use anyhow::Result;
+use cgmath::{vec3, vec4};
+use elingine::{Context, DebugStyle, EngineBuilder, FrameTime, Game, Mat4, Material, SceneObject};
+
+struct Viewer;
+
+impl Game for Viewer {
+    fn init(&mut self, ctx: &mut Context) -> Result<()> {
+        ctx.objects.push(SceneObject::new(ctx.meshes[0], Mat4::from_scale(1.0), Material::default()));
+        Ok(())
+    }
+
+    fn update(&mut self, ctx: &mut Context, _frame: FrameTime) {
+        let grid = DebugStyle::new(vec4(0.5, 0.5, 0.5, 0.5));
+        ctx.debug_draw.grid(vec3(0.0, 0.0, 0.0), 4.0, 8, grid);
+        ctx.debug_draw.axes(Mat4::from_scale(1.0), 0.5, false);
+    }
+}
+
+fn main() -> Result<()> {
+    pretty_env_logger::init();
+    let paths = std::env::args().skip(1).filter(|a| !a.starts_with("--")).collect::<Vec<_>>();
+
+    let mut builder = EngineBuilder::new().title("Viewer").args(std::env::args())?;
+    if let Some(model) = paths.first() {
+        builder = builder.model(model);
+    }
+    if let Some(texture) = paths.get(1) {
+        builder = builder.texture(texture);
+    }
+    builder.run(Viewer)
+}
@@ -1,108 +1,43 @@
 use vulkanalia::{vk, Device, Instance};
+use crate::memory_util::{ResourceKind, Strategy};
 use crate::render_app::AppData;
-use anyhow::{anyhow, Result};
-use vulkanalia::vk::{DeviceV1_0, Handle, HasBuilder, InstanceV1_0};
+use crate::resource_util::{Buffer, Memory};
+use anyhow::Result;
+use vulkanalia::vk::{DeviceV1_0, HasBuilder};
 
+/// Creates a buffer bound to memory from `data.allocator`. Staging buffers,
+/// whose only usage is `TRANSFER_SRC`, come from linear blocks.
 pub unsafe fn create_buffer(
-    instance: &Instance,
+    _instance: &Instance,
     device: &Device,
     data: &AppData,
     size: vk::DeviceSize,
     usage: vk::BufferUsageFlags,
     properties: vk::MemoryPropertyFlags,
-) -> Result<(vk::Buffer, vk::DeviceMemory)> {
+) -> Result<(Buffer, Memory)> {
     let buffer_info = vk::BufferCreateInfo::builder()
         .size(size)
         .usage(usage)
         .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
-    let buffer = device.create_buffer(&buffer_info, None)?;
+    let buffer = data.deletion_queue.own(device.create_buffer(&buffer_info, None)?);
 
-    let requirements = device.get_buffer_memory_requirements(buffer);
+    let requirements = device.get_buffer_memory_requirements(*buffer);
 
-    let memory_info = vk::MemoryAllocateInfo::builder()
-        .allocation_size(requirements.size)
-        .memory_type_index(get_memory_type_index(
-            instance,
-            data,
-            properties,
-            requirements,
-        )?);
+    let strategy = if usage == vk::BufferUsageFlags::TRANSFER_SRC {
+        Strategy::Linear
+    } else {
+        Strategy::FreeList
+    };
+    let buffer_memory = data.deletion_queue.own(data.allocator.allocate(
+        device,
+        requirements,
+        properties,
+        ResourceKind::Buffer,
+        strategy,
+    )?);
 
-    let buffer_memory = device.allocate_memory(&memory_info, None)?;
-
-    device.bind_buffer_memory(buffer, buffer_memory, 0)?;
+    device.bind_buffer_memory(*buffer, buffer_memory.memory, buffer_memory.offset)?;
 
     Ok((buffer, buffer_memory))
 }
-
-pub unsafe fn get_memory_type_index(
-    instance: &Instance,
-    data: &AppData,
-    properties: vk::MemoryPropertyFlags,
-    requirements: vk::MemoryRequirements,
-) -> Result<u32> {
-    let memory = instance.get_physical_device_memory_properties(data.physical_device);
-    (0..memory.memory_type_count)
-        .find(|i| {
-            let suitable = (requirements.memory_type_bits & (1 << i)) != 0;
-            let memory_type = memory.memory_types[*i as usize];
-            suitable && memory_type.property_flags.contains(properties)
-        })
-        .ok_or_else(|| anyhow!("Failed to find suitable memory type."))
-}
-
-pub unsafe fn copy_buffer(
-    device: &Device,
-    data: &AppData,
-    source: vk::Buffer,
-    destination: vk::Buffer,
-    size: vk::DeviceSize,
-) -> Result<()> {
-    let command_buffer = begin_single_time_commands(device, data)?;
-
-    let regions = vk::BufferCopy::builder().size(size);
-    device.cmd_copy_buffer(command_buffer, source, destination, &[regions]);
-    end_single_time_commands(device, data, command_buffer)?;
-
-    Ok(())
-}
-
-
-pub unsafe fn begin_single_time_commands(
-    device: &Device,
-    data: &AppData,
-) -> Result<vk::CommandBuffer> {
-    let info = vk::CommandBufferAllocateInfo::builder()
-        .level(vk::CommandBufferLevel::PRIMARY)
-        .command_pool(data.command_pool)
-        .command_buffer_count(1);
-
-    let command_buffer = device.allocate_command_buffers(&info)?[0];
-
-    let info = vk::CommandBufferBeginInfo::builder()
-        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-
-    device.begin_command_buffer(command_buffer, &info)?;
-
-    Ok(command_buffer)
-}
-
-pub unsafe fn end_single_time_commands(
-    device: &Device,
-    data: &AppData,
-    command_buffer: vk::CommandBuffer,
-) -> Result<()> {
-    device.end_command_buffer(command_buffer)?;
-
-    let command_buffers = &[command_buffer];
-    let info = vk::SubmitInfo::builder()
-        .command_buffers(command_buffers);
-
-    device.queue_submit(data.graphics_queue, &[info], vk::Fence::null())?;
-    device.queue_wait_idle(data.graphics_queue)?;
-
-    device.free_command_buffers(data.command_pool, &[command_buffer]);
-
-    Ok(())
-}
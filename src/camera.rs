@@ -0,0 +1,259 @@
+use cgmath::{point3, vec3, Deg, EuclideanSpace, InnerSpace, Point3, Rad};
+use vulkanalia::vk;
+use crate::input::Input;
+use crate::transforms::{Mat4, Vec3};
+
+/// The world is Z-up.
+const UP: Vec3 = Vec3::new(0.0, 0.0, 1.0);
+/// Keeps the view direction away from the poles where `look_to_rh` degenerates.
+const MAX_PITCH: f32 = 1.55;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    Perspective { fovy: Deg<f32> },
+    /// `height` is the visible extent in world units, the width follows
+    /// from the aspect ratio.
+    Orthographic { height: f32 },
+}
+
+/// A viewpoint with yaw and pitch in radians. Yaw is measured from +X
+/// towards +Y, pitch upwards from the XY plane.
+#[derive(Copy, Clone, Debug)]
+pub struct Camera {
+    pub position: Point3<f32>,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub projection: Projection,
+    pub near: f32,
+    pub far: f32,
+    /// Maps the far plane to depth 0 and the near plane to 1 for better
+    /// precision. Fixed once the pipelines are created, as it flips their
+    /// depth compare ops and the depth clear value.
+    pub reverse_z: bool,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        let mut camera = Self {
+            position: point3(0.0, 0.0, 0.0),
+            yaw: 0.0,
+            pitch: 0.0,
+            projection: Projection::Perspective { fovy: Deg(45.0) },
+            near: 0.1,
+            far: 100.0,
+            reverse_z: false,
+        };
+        camera.look_at(point3(2.0, 2.0, 2.0), point3(0.0, 0.0, 0.0));
+        camera
+    }
+}
+
+impl Camera {
+    pub fn look_at(&mut self, eye: Point3<f32>, target: Point3<f32>) {
+        self.position = eye;
+        let direction = (target - eye).normalize();
+        self.yaw = direction.y.atan2(direction.x);
+        self.pitch = direction.z.asin().clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        vec3(cos_pitch * cos_yaw, cos_pitch * sin_yaw, sin_pitch)
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.forward().cross(UP).normalize()
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        Mat4::look_to_rh(self.position, self.forward(), UP)
+    }
+
+    /// Projection into Vulkan clip space: Y down and depth from 0 to 1, or
+    /// from 1 to 0 with `reverse_z`.
+    pub fn projection_matrix(&self, aspect: f32) -> Mat4 {
+        let z_scale = if self.reverse_z { -0.5 } else { 0.5 };
+        let correction = Mat4::new(
+            1.0,  0.0,     0.0, 0.0,
+            // We're also flipping the Y-axis with this line's `-1.0`.
+            0.0, -1.0,     0.0, 0.0,
+            0.0,  0.0, z_scale, 0.0,
+            0.0,  0.0,     0.5, 1.0,
+        );
+
+        let projection = match self.projection {
+            Projection::Perspective { fovy } => cgmath::perspective(fovy, aspect, self.near, self.far),
+            Projection::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * aspect;
+                cgmath::ortho(-half_width, half_width, -half_height, half_height, self.near, self.far)
+            }
+        };
+        correction * projection
+    }
+
+    /// Switches between perspective and orthographic projection, keeping
+    /// roughly the same framing at `distance` from the camera.
+    pub fn toggle_projection(&mut self, distance: f32) {
+        self.projection = match self.projection {
+            Projection::Perspective { fovy } => Projection::Orthographic {
+                height: 2.0 * distance * (Rad::from(fovy).0 / 2.0).tan(),
+            },
+            Projection::Orthographic { .. } => Projection::Perspective { fovy: Deg(45.0) },
+        };
+    }
+
+    /// The depth the depth buffer is cleared to.
+    pub fn far_depth(&self) -> f32 {
+        if self.reverse_z { 0.0 } else { 1.0 }
+    }
+
+    /// `op` as written for a conventional depth range, adjusted for `reverse_z`.
+    pub fn depth_compare_op(&self, op: vk::CompareOp) -> vk::CompareOp {
+        if !self.reverse_z {
+            return op;
+        }
+        match op {
+            vk::CompareOp::LESS => vk::CompareOp::GREATER,
+            vk::CompareOp::LESS_OR_EQUAL => vk::CompareOp::GREATER_OR_EQUAL,
+            vk::CompareOp::GREATER => vk::CompareOp::LESS,
+            vk::CompareOp::GREATER_OR_EQUAL => vk::CompareOp::LESS_OR_EQUAL,
+            op => op,
+        }
+    }
+}
+
+/// Free flying camera driven by the `move_*`, `sprint` and `look*` actions.
+/// The cursor is grabbed while `look` is held.
+#[derive(Clone, Debug)]
+pub struct FlyController {
+    /// Units per second.
+    pub speed: f32,
+    /// Radians per pixel of mouse movement.
+    pub sensitivity: f32,
+    /// Radians per second at full stick deflection.
+    pub stick_rate: f32,
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        Self { speed: 2.0, sensitivity: 0.003, stick_rate: 2.5 }
+    }
+}
+
+impl FlyController {
+    fn update(&mut self, camera: &mut Camera, input: &Input, dt: f32) {
+        let mut yaw = -input.axis("look_right", "look_left") * self.stick_rate * dt;
+        let mut pitch = input.axis("look_up", "look_down") * self.stick_rate * dt;
+        if input.action_held("look") {
+            yaw -= input.mouse_delta.x * self.sensitivity;
+            pitch -= input.mouse_delta.y * self.sensitivity;
+        }
+        camera.yaw += yaw;
+        camera.pitch = (camera.pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+
+        let movement = camera.forward() * input.axis("move_forward", "move_back")
+            + camera.right() * input.axis("move_right", "move_left")
+            + UP * input.axis("move_up", "move_down");
+        if movement.magnitude2() > 0.0 {
+            let boost = if input.action_held("sprint") { 4.0 } else { 1.0 };
+            // Keep analog input analog, but don't move faster diagonally.
+            let movement = if movement.magnitude2() > 1.0 { movement.normalize() } else { movement };
+            camera.position += movement * self.speed * boost * dt;
+        }
+    }
+}
+
+/// Arcball style camera around a target: `orbit_rotate` and `orbit_pan`
+/// drag with the mouse, the wheel zooms.
+#[derive(Clone, Debug)]
+pub struct OrbitController {
+    pub target: Point3<f32>,
+    pub distance: f32,
+    /// Radians per pixel of mouse movement.
+    pub sensitivity: f32,
+}
+
+impl OrbitController {
+    /// Orbits `target` from where `camera` currently is.
+    pub fn new(camera: &Camera, target: Point3<f32>) -> Self {
+        Self {
+            target,
+            distance: (camera.position - target).magnitude().max(0.1),
+            sensitivity: 0.005,
+        }
+    }
+
+    fn update(&mut self, camera: &mut Camera, input: &Input, _dt: f32) {
+        let delta = input.mouse_delta;
+        if input.action_held("orbit_rotate") {
+            camera.yaw -= delta.x * self.sensitivity;
+            camera.pitch = (camera.pitch + delta.y * self.sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+        } else if input.action_held("orbit_pan") {
+            let up = camera.right().cross(camera.forward());
+            let scale = self.distance * self.sensitivity * 0.2;
+            self.target += (camera.right() * -delta.x + up * delta.y) * scale;
+        }
+
+        let zoom = 0.9f32.powf(input.wheel);
+        self.distance = (self.distance * zoom).max(0.1);
+        if let Projection::Orthographic { height } = &mut camera.projection {
+            *height *= zoom;
+        }
+
+        camera.position = self.target - camera.forward() * self.distance;
+    }
+}
+
+/// The controller currently driving the camera.
+#[derive(Clone, Debug)]
+pub enum CameraController {
+    Fly(FlyController),
+    Orbit(OrbitController),
+}
+
+impl CameraController {
+    pub fn update(&mut self, camera: &mut Camera, input: &Input, dt: f32) {
+        match self {
+            CameraController::Fly(controller) => controller.update(camera, input, dt),
+            CameraController::Orbit(controller) => controller.update(camera, input, dt),
+        }
+    }
+
+    /// Whether the controller wants the cursor hidden and locked, e.g. for mouse look.
+    pub fn wants_cursor_grab(&self, input: &Input) -> bool {
+        match self {
+            CameraController::Fly(_) => input.action_held("look"),
+            CameraController::Orbit(_) => false,
+        }
+    }
+
+    /// Distance to what the camera is looking at, used to keep the framing
+    /// when switching projections.
+    pub fn focus_distance(&self) -> f32 {
+        match self {
+            CameraController::Fly(_) => 3.0,
+            CameraController::Orbit(controller) => controller.distance,
+        }
+    }
+
+    /// Switches between fly and orbit mode without moving the camera. The
+    /// orbit target is placed in front of the camera.
+    pub fn toggle(&mut self, camera: &Camera) {
+        let distance = self.focus_distance();
+        *self = match self {
+            CameraController::Fly(_) => {
+                let target = camera.position + camera.forward() * distance;
+                CameraController::Orbit(OrbitController::new(camera, target))
+            }
+            CameraController::Orbit(_) => CameraController::Fly(FlyController::default()),
+        };
+    }
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        CameraController::Orbit(OrbitController::new(&Camera::default(), Point3::origin()))
+    }
+}
@@ -1,66 +1,262 @@
+use std::mem::size_of;
 use vulkanalia::{vk, Device};
 use vulkanalia::vk::{DeviceV1_0, HasBuilder};
 use crate::render_app::AppData;
-use crate::vertexbuffer_util::{};
+use crate::vertexbuffer_util::geometry_buffers;
+use crate::deferred_util::RenderPath;
+use crate::lights::MAX_POINT_LIGHTS;
+use crate::sky_util::SkyPushConstants;
+use crate::scene::{opaque_objects, transparent_objects, AlphaMode, ObjectPushConstants, SceneObject};
+use crate::transforms::Mat4;
+use crate::debug_draw::record_debug_draw;
+use crate::compute::record_compute;
+use crate::render_graph::Import;
 
+/// Allocates one command buffer per frame in flight. They are recorded every
+/// frame by `update_command_buffer`.
 pub unsafe fn create_command_buffers(device: &Device, data: &mut AppData) -> anyhow::Result<()> {
     let allocate_info = vk::CommandBufferAllocateInfo::builder()
-        .command_pool(data.command_pool)
+        .command_pool(*data.command_pool)
         .level(vk::CommandBufferLevel::PRIMARY)
-        .command_buffer_count(data.framebuffers.len() as u32);
+        .command_buffer_count(data.config.frames_in_flight as u32);
 
     data.command_buffers = device.allocate_command_buffers(&allocate_info)?;
 
-    for (i, command_buffer) in data.command_buffers.iter().enumerate() {
-        let inheritance = vk::CommandBufferInheritanceInfo::builder();
+    Ok(())
+}
+
+/// Records frame `frame` into swapchain image `image_index`. Transparent
+/// objects are sorted back to front relative to `view`, so this runs every
+/// frame. The caller must have waited for the frame's fence.
+pub unsafe fn update_command_buffer(
+    device: &Device,
+    data: &AppData,
+    frame: usize,
+    image_index: usize,
+    view: Mat4,
+) -> anyhow::Result<()> {
+    let command_buffer = &data.command_buffers[frame];
+    device.reset_command_buffer(*command_buffer, vk::CommandBufferResetFlags::empty())?;
 
-        let info = vk::CommandBufferBeginInfo::builder()
-            .flags(vk::CommandBufferUsageFlags::empty()) // Optional.
-            .inheritance_info(&inheritance);             // Optional.
+    let inheritance = vk::CommandBufferInheritanceInfo::builder();
 
-        device.begin_command_buffer(*command_buffer, &info)?;
+    let info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+        .inheritance_info(&inheritance);             // Optional.
 
-        let render_area = vk::Rect2D::builder()
-            .offset(vk::Offset2D::default())
-            .extent(data.swapchain_extent);
+    device.begin_command_buffer(*command_buffer, &info)?;
 
-        let color_clear_value = vk::ClearValue {
-            color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0], }, };
+    record_compute(device, data, *command_buffer);
+
+    // The render graph of the dynamic rendering path transitions the
+    // attachments itself, otherwise the render pass does.
+    if data.dynamic_rendering {
+        let swapchain = Import::Image(
+            data.swapchain_target,
+            data.swapchain_images[image_index],
+            *data.swapchain_image_views[image_index],
+        );
+        data.render_graph.execute(device, data, *command_buffer, frame, image_index, view, &[swapchain])?;
+    } else {
+        begin_render_pass(device, data, *command_buffer, image_index);
+        record_scene(device, data, *command_buffer, frame, view);
+        device.cmd_end_render_pass(*command_buffer);
+    }
 
-        let depth_clear_value = vk::ClearValue {
-            depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0, }, };
+    device.end_command_buffer(*command_buffer)?;
 
-        let clear_values = &[color_clear_value, depth_clear_value];
+    Ok(())
+}
 
-        let info = vk::RenderPassBeginInfo::builder()
-            .render_pass(data.render_pass)
-            .framebuffer(data.framebuffers[i])
-            .render_area(render_area)
-            .clear_values(clear_values);
+/// Records the draws of the scene inside the render pass or dynamic
+/// rendering scope of the frame.
+pub unsafe fn record_scene(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    frame: usize,
+    view: Mat4,
+) {
+    // A debug view replaces the shading of every object. The deferred path
+    // still fills the G-buffer so debug views can test against its depth.
+    let debug_pipeline = data.debug_pipelines.get(data.debug_view);
 
-        device.cmd_begin_render_pass(
-            *command_buffer, &info, vk::SubpassContents::INLINE);
-        device.cmd_bind_pipeline(
-            *command_buffer, vk::PipelineBindPoint::GRAPHICS, data.pipeline);
-        device.cmd_bind_vertex_buffers(*command_buffer, 0, &[data.vertex_buffer], &[0]);
-        device.cmd_bind_index_buffer(*command_buffer, data.index_buffer, 0, vk::IndexType::UINT32);
-
-        device.cmd_bind_descriptor_sets(
-            *command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            data.pipeline_layout,
+    // Opaque and cutout objects, into the swapchain image or the G-buffer.
+    if debug_pipeline.is_none() || data.render_path == RenderPath::Deferred {
+        for object in opaque_objects(&data.objects) {
+            let pipeline = match object.material.alpha_mode {
+                AlphaMode::Mask { .. } => *data.cutout_pipeline,
+                _ => *data.pipeline,
+            };
+            record_object(device, data, command_buffer, frame, pipeline, object);
+        }
+    }
+
+    if data.render_path == RenderPath::Deferred {
+        device.cmd_next_subpass(command_buffer, vk::SubpassContents::INLINE);
+        if debug_pipeline.is_none() {
+            record_lighting(device, data, command_buffer, frame);
+        }
+    }
+
+    if let Some(pipeline) = debug_pipeline {
+        for object in &data.objects {
+            record_object(device, data, command_buffer, frame, pipeline, object);
+        }
+    } else {
+        if data.sky.enabled() {
+            record_sky(device, data, command_buffer, frame);
+        }
+
+        for object in &transparent_objects(&data.objects, &view) {
+            record_object(device, data, command_buffer, frame, *data.transparent_pipeline, object);
+        }
+    }
+
+    record_debug_draw(device, data, command_buffer, frame);
+}
+
+unsafe fn begin_render_pass(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+) {
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(data.swapchain_extent);
+
+    let color_clear_value = vk::ClearValue {
+        color: vk::ClearColorValue { float32: data.sky.clear_color, }, };
+
+    let depth_clear_value = vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue { depth: data.camera.far_depth(), stencil: 0, }, };
+
+    let gbuffer_clear_value = vk::ClearValue {
+        color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 0.0], }, };
+
+    let clear_values = match data.render_path {
+        RenderPath::Forward => vec![color_clear_value, depth_clear_value],
+        RenderPath::Deferred => vec![
+            color_clear_value,
+            depth_clear_value,
+            gbuffer_clear_value,
+            gbuffer_clear_value,
+            gbuffer_clear_value,
+        ],
+    };
+
+    let info = vk::RenderPassBeginInfo::builder()
+        .render_pass(*data.render_pass)
+        .framebuffer(*data.framebuffers[image_index])
+        .render_area(render_area)
+        .clear_values(&clear_values);
+
+    device.cmd_begin_render_pass(
+        command_buffer, &info, vk::SubpassContents::INLINE);
+}
+
+unsafe fn record_object(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    frame: usize,
+    pipeline: vk::Pipeline,
+    object: &SceneObject,
+) {
+    device.cmd_bind_pipeline(
+        command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+    let (vertex_buffer, index_buffer) = geometry_buffers(data, object.geometry);
+    device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+    device.cmd_bind_index_buffer(command_buffer, index_buffer, 0, vk::IndexType::UINT32);
+
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *data.pipeline_layout,
+        0,
+        &[data.descriptor_sets[frame]],
+        &[],
+    );
+
+    if let Some(range) = data.object_interface.push_constants {
+        let push_constants = ObjectPushConstants::new(object);
+        device.cmd_push_constants(
+            command_buffer,
+            *data.pipeline_layout,
+            range.stage_flags,
             0,
-            &[data.descriptor_sets[i]],
-            &[],
+            std::slice::from_raw_parts(
+                &push_constants as *const ObjectPushConstants as *const u8,
+                size_of::<ObjectPushConstants>(),
+            ),
         );
+    }
 
-        device.cmd_draw_indexed(*command_buffer, data.indices.len()/*INDICES.len()*/ as u32, 1, 0, 0, 0);
+    device.cmd_draw_indexed(command_buffer, object.index_count, 1, object.first_index, 0, 0);
+}
 
-        //device.cmd_draw(*command_buffer, VERTICES.len() as u32, 1, 0, 0);
+/// Shades the G-buffer in the lighting subpass of the deferred render pass:
+/// one full-screen triangle for ambient and directional light, then one quad
+/// per point light volume.
+unsafe fn record_lighting(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    frame: usize,
+) {
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *data.deferred.lighting_pipeline_layout,
+        0,
+        &[data.deferred.descriptor_sets[frame]],
+        &[],
+    );
 
-        device.cmd_end_render_pass(*command_buffer);
-        device.end_command_buffer(*command_buffer)?;
+    device.cmd_bind_pipeline(
+        command_buffer, vk::PipelineBindPoint::GRAPHICS, *data.deferred.lighting_pipeline);
+    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+    let light_count = data.point_lights.len().min(MAX_POINT_LIGHTS) as u32;
+    if light_count > 0 {
+        device.cmd_bind_pipeline(
+            command_buffer, vk::PipelineBindPoint::GRAPHICS, *data.deferred.light_volume_pipeline);
+        device.cmd_draw(command_buffer, 4, light_count, 0, 0);
     }
+}
 
-    Ok(())
+/// Draws the sky cube behind everything already in the depth buffer.
+unsafe fn record_sky(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    frame: usize,
+) {
+    let push_constants = SkyPushConstants {
+        sun_direction: -data.sun.direction,
+        sun_color: data.sun.color,
+    };
+
+    device.cmd_bind_pipeline(
+        command_buffer, vk::PipelineBindPoint::GRAPHICS, *data.sky.pipeline);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *data.sky.pipeline_layout,
+        0,
+        &[data.sky.descriptor_sets[frame]],
+        &[],
+    );
+    device.cmd_push_constants(
+        command_buffer,
+        *data.sky.pipeline_layout,
+        vk::ShaderStageFlags::FRAGMENT,
+        0,
+        std::slice::from_raw_parts(
+            &push_constants as *const SkyPushConstants as *const u8,
+            size_of::<SkyPushConstants>(),
+        ),
+    );
+    device.cmd_draw(command_buffer, 36, 1, 0, 0);
 }
@@ -12,9 +12,9 @@ pub unsafe fn create_command_pool(
     let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
 
     let info = vk::CommandPoolCreateInfo::builder()
-        .flags(vk::CommandPoolCreateFlags::empty()) // Optional.
+        .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER) // Frame command buffers are re-recorded.
         .queue_family_index(indices.graphics);
-    data.command_pool = device.create_command_pool(&info, None)?;
+    data.command_pool = data.deletion_queue.own(device.create_command_pool(&info, None)?);
 
     Ok(())
 }
@@ -30,7 +30,27 @@ pub unsafe fn create_transient_command_pool(
     let info = vk::CommandPoolCreateInfo::builder()
         .flags(vk::CommandPoolCreateFlags::TRANSIENT) // Optional.
         .queue_family_index(indices.graphics);
-    data.transient_command_pool = device.create_command_pool(&info, None)?;
+    data.transient_command_pool = data.deletion_queue.own(device.create_command_pool(&info, None)?);
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Creates the pool for upload command buffers on the transfer queue, if
+/// the device has a separate transfer family.
+pub unsafe fn create_transfer_command_pool(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> anyhow::Result<()> {
+
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+
+    if let Some(transfer) = indices.transfer {
+        let info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(transfer);
+        data.transfer_command_pool = data.deletion_queue.own(device.create_command_pool(&info, None)?);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,649 @@
+use std::collections::HashMap;
+use std::mem::size_of_val;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Result};
+use bytemuck::Pod;
+use log::info;
+use vulkanalia::{vk, Device, Instance};
+use vulkanalia::vk::{DeviceV1_0, Handle, HasBuilder, InstanceV1_0};
+use crate::image_util::{transition_layouts, LayoutTransition};
+use crate::memory_util::{ResourceKind, Strategy};
+use crate::pipeline_util::{create_compute_pipeline, SpecializationConstants};
+use crate::queue_family_indices::QueueFamilyIndices;
+use crate::render_app::AppData;
+use crate::resource_util::{
+    Buffer, CommandPool, DescriptorPool, DescriptorSetLayout, Image, ImageView, Memory, Pipeline, PipelineLayout,
+    Semaphore,
+};
+use crate::shader_compiler::ShaderUser;
+use crate::shader_module_util::load_shader_file;
+use crate::spirv_reflect::{reflect, ReflectedBinding, ShaderInterface};
+use crate::sync_util::Timeline;
+
+/// Push constant bytes every device supports.
+const MAX_PUSH_CONSTANT_SIZE: u32 = 128;
+
+/// Texel format of a storage image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StorageFormat {
+    /// `rgba8` in GLSL.
+    Rgba8,
+    /// `rgba16f` in GLSL.
+    Rgba16Float,
+    /// `r32f` in GLSL.
+    R32Float,
+}
+
+impl StorageFormat {
+    fn to_vk(self) -> vk::Format {
+        match self {
+            StorageFormat::Rgba8 => vk::Format::R8G8B8A8_UNORM,
+            StorageFormat::Rgba16Float => vk::Format::R16G16B16A16_SFLOAT,
+            StorageFormat::R32Float => vk::Format::R32_SFLOAT,
+        }
+    }
+}
+
+/// A buffer or image compute passes read and write, created once at startup.
+#[derive(Clone, Debug)]
+pub(crate) enum StorageDesc {
+    /// Zero padded when `contents` is shorter than `size`.
+    Buffer { size: u64, contents: Vec<u8> },
+    /// Kept in `GENERAL` layout, with undefined contents at startup.
+    Image { width: u32, height: u32, format: StorageFormat },
+}
+
+/// A compute shader and the storage resources it binds, in binding order
+/// of descriptor set 0. Buffers are `buffer` blocks and images are `image2D`
+/// uniforms, see `EngineBuilder::compute_pass`.
+#[derive(Clone, Debug)]
+pub(crate) struct ComputePassDesc {
+    pub shader: PathBuf,
+    pub bindings: Vec<String>,
+    pub push_constant_size: u32,
+    pub constants: SpecializationConstants,
+}
+
+#[derive(Debug, Default)]
+struct StorageResource {
+    buffer: Buffer,
+    /// Only held to outlive `view`.
+    _image: Image,
+    view: ImageView,
+    /// Bound to `buffer` or `_image`.
+    _memory: Memory,
+}
+
+#[derive(Debug, Default)]
+struct ComputePass {
+    /// Source of the pipeline, reloaded when it changes.
+    shader: PathBuf,
+    /// The layout `set` was allocated with.
+    _set_layout: DescriptorSetLayout,
+    set: vk::DescriptorSet,
+    layout: PipelineLayout,
+    pipeline: Pipeline,
+    push_constant_size: u32,
+    constants: SpecializationConstants,
+    /// What `layout` provides, reloaded shaders are checked against it.
+    interface: ShaderInterface,
+}
+
+#[derive(Clone, Debug)]
+struct Dispatch {
+    pass: usize,
+    groups: [u32; 3],
+    push_constants: Vec<u8>,
+}
+
+/// Runs the compute passes added with `EngineBuilder::compute_pass`.
+/// Dispatches queued during a frame run before it is drawn, in order, each
+/// seeing the writes of the ones before it.
+///
+/// With `EngineConfig::async_compute` and a device that has a separate
+/// compute queue family, they run on that queue instead and the frame
+/// waits for them before its vertex stage.
+#[derive(Debug, Default)]
+pub struct Compute {
+    names: HashMap<String, usize>,
+    dispatches: Vec<Dispatch>,
+
+    passes: Vec<ComputePass>,
+    resources: Vec<StorageResource>,
+    descriptor_pool: DescriptorPool,
+    /// The async compute queue, null when dispatches are recorded into the
+    /// frame's command buffer.
+    queue: vk::Queue,
+    command_pool: CommandPool,
+    /// One per frame in flight, for the async compute queue.
+    command_buffers: Vec<vk::CommandBuffer>,
+    semaphores: Vec<Semaphore>,
+    /// Signaled by the graphics submission of each frame, the next frame's
+    /// dispatches wait for it before overwriting what the frame read.
+    released: Vec<Semaphore>,
+    /// Replaces `semaphores` with timeline semaphores, signaled with the
+    /// frame's value. The frame timeline replaces `released`.
+    timeline: Timeline,
+}
+
+impl Compute {
+    /// Queues `pass` with `groups` work groups for this frame.
+    pub fn dispatch(&mut self, pass: &str, groups: [u32; 3]) -> Result<()> {
+        self.dispatch_with(pass, groups, &())
+    }
+
+    /// Queues `pass` with `push_constants`, which must match the size given
+    /// to `EngineBuilder::compute_pass`.
+    pub fn dispatch_with<T: Pod>(&mut self, pass: &str, groups: [u32; 3], push_constants: &T) -> Result<()> {
+        let index = *self.names.get(pass).ok_or_else(|| anyhow!("Unknown compute pass `{}`.", pass))?;
+        let size = size_of_val(push_constants);
+        if size != self.passes[index].push_constant_size as usize {
+            return Err(anyhow!(
+                "Compute pass `{}` takes {} bytes of push constants, got {}.",
+                pass,
+                self.passes[index].push_constant_size,
+                size,
+            ));
+        }
+
+        let bytes = bytemuck::bytes_of(push_constants).to_vec();
+        self.dispatches.push(Dispatch { pass: index, groups, push_constants: bytes });
+        Ok(())
+    }
+
+    /// Work groups of `local_size` invocations needed to cover `items`.
+    pub fn groups(items: u32, local_size: u32) -> u32 {
+        items.div_ceil(local_size)
+    }
+
+    /// Whether dispatches run on a separate compute queue.
+    pub fn is_async(&self) -> bool {
+        !self.command_buffers.is_empty()
+    }
+
+    /// The semaphore the graphics submission of `frame` signals for the
+    /// next frame's dispatches, if any.
+    pub(crate) fn released_semaphore(&self, frame: usize) -> Option<vk::Semaphore> {
+        self.released.get(frame).map(|semaphore| **semaphore)
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        self.dispatches.clear();
+    }
+}
+
+/// Creates the storage resources and compute pipelines. Initial buffer
+/// contents go through the uploader, the caller waits for it.
+pub(crate) unsafe fn create_compute(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    storage: &[(String, StorageDesc)],
+    passes: &[(String, ComputePassDesc)],
+) -> Result<()> {
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+    let async_family = indices.compute.filter(|_| data.config.async_compute && !passes.is_empty());
+
+    // Resources touched by several queue families are shared between them
+    // rather than transferred back and forth every frame.
+    let mut families = vec![indices.graphics];
+    families.extend(indices.transfer);
+    families.extend(async_family);
+    families.sort_unstable();
+    families.dedup();
+
+    let mut resources = Vec::with_capacity(storage.len());
+    let mut names = HashMap::new();
+    for (name, desc) in storage {
+        if names.insert(name.as_str(), resources.len()).is_some() {
+            return Err(anyhow!("Storage resource `{}` was added twice.", name));
+        }
+        resources.push(match desc {
+            StorageDesc::Buffer { size, contents } => create_storage_buffer(device, data, &families, *size, contents)?,
+            StorageDesc::Image { width, height, format } => {
+                create_storage_image(instance, device, data, &families, *width, *height, *format)?
+            }
+        });
+    }
+
+    // Descriptors
+
+    let mut buffer_count = 0;
+    let mut image_count = 0;
+    for (_, pass) in passes {
+        for binding in &pass.bindings {
+            match storage.iter().find(|(name, _)| name == binding) {
+                Some((_, StorageDesc::Buffer { .. })) => buffer_count += 1,
+                Some((_, StorageDesc::Image { .. })) => image_count += 1,
+                None => return Err(anyhow!("Unknown storage resource `{}`.", binding)),
+            }
+        }
+    }
+
+    // A pool needs at least one size, even if no pass binds anything.
+    let mut pool_sizes = vec![vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(buffer_count.max(1))
+        .build()];
+    if image_count > 0 {
+        pool_sizes.push(vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::STORAGE_IMAGE)
+            .descriptor_count(image_count)
+            .build());
+    }
+    let descriptor_pool = if passes.is_empty() {
+        DescriptorPool::default()
+    } else {
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(passes.len() as u32);
+        data.deletion_queue.own(device.create_descriptor_pool(&info, None)?)
+    };
+
+    // Passes
+
+    let mut compute = Compute { descriptor_pool, ..Default::default() };
+    for (name, desc) in passes {
+        if desc.push_constant_size > MAX_PUSH_CONSTANT_SIZE {
+            return Err(anyhow!(
+                "Compute pass `{}` uses {} bytes of push constants, at most {} are supported.",
+                name,
+                desc.push_constant_size,
+                MAX_PUSH_CONSTANT_SIZE,
+            ));
+        }
+        let bound = desc.bindings.iter().map(|binding| names[binding.as_str()]).collect::<Vec<_>>();
+        let shader = load_shader_file(data, &desc.shader, ShaderUser::Compute(compute.passes.len()))?;
+        let descriptor_pool = *compute.descriptor_pool;
+        let pass = create_compute_pass(device, data, desc, &shader, &bound, storage, &resources, descriptor_pool)?;
+        compute.names.insert(name.clone(), compute.passes.len());
+        compute.passes.push(pass);
+    }
+    compute.resources = resources;
+
+    // Async compute
+
+    if let Some(family) = async_family {
+        info!("Dispatching compute on queue family {}.", family);
+        let info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+            .queue_family_index(family);
+        compute.command_pool = data.deletion_queue.own(device.create_command_pool(&info, None)?);
+
+        let info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(*compute.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(data.config.frames_in_flight as u32);
+        compute.command_buffers = device.allocate_command_buffers(&info)?;
+
+        if data.timeline_semaphores {
+            compute.timeline = Timeline::new(device, &data.deletion_queue)?;
+        } else {
+            for _ in 0..data.config.frames_in_flight {
+                let semaphore = device.create_semaphore(&vk::SemaphoreCreateInfo::builder(), None)?;
+                compute.semaphores.push(data.deletion_queue.own(semaphore));
+                let semaphore = device.create_semaphore(&vk::SemaphoreCreateInfo::builder(), None)?;
+                compute.released.push(data.deletion_queue.own(semaphore));
+            }
+        }
+        compute.queue = data.compute_queue;
+    }
+
+    data.compute = compute;
+    Ok(())
+}
+
+/// Also called on reload, so it takes the parts of `Compute` it needs
+/// instead of the whole.
+#[allow(clippy::too_many_arguments)]
+unsafe fn create_compute_pass(
+    device: &Device,
+    data: &AppData,
+    desc: &ComputePassDesc,
+    shader: &[u8],
+    bound: &[usize],
+    storage: &[(String, StorageDesc)],
+    resources: &[StorageResource],
+    descriptor_pool: vk::DescriptorPool,
+) -> Result<ComputePass> {
+    let descriptor_type = |index: usize| match storage[index].1 {
+        StorageDesc::Buffer { .. } => vk::DescriptorType::STORAGE_BUFFER,
+        StorageDesc::Image { .. } => vk::DescriptorType::STORAGE_IMAGE,
+    };
+
+    // The layout follows the bindings the game declared, the shader has to
+    // fit it.
+    let mut interface = ShaderInterface::default();
+    for (binding, &index) in bound.iter().enumerate() {
+        interface.bindings.insert((0, binding as u32), ReflectedBinding {
+            descriptor_type: descriptor_type(index),
+            count: 1,
+            stages: vk::ShaderStageFlags::COMPUTE,
+        });
+    }
+    if desc.push_constant_size > 0 {
+        interface.push_constants = Some(vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: desc.push_constant_size,
+        });
+    }
+    check_shader(&interface, &desc.shader, shader)?;
+
+    let bindings = interface.set_layout_bindings(0);
+    let info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .bindings(&bindings);
+    let set_layout = data.deletion_queue.own(device.create_descriptor_set_layout(&info, None)?);
+
+    let set_layouts = &[*set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(set_layouts);
+    let set = device.allocate_descriptor_sets(&info)?[0];
+
+    for (binding, &index) in bound.iter().enumerate() {
+        let resource = &resources[index];
+        let buffer_info = &[vk::DescriptorBufferInfo::builder()
+            .buffer(*resource.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE as u64)];
+        let image_info = &[vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(*resource.view)];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(binding as u32)
+            .dst_array_element(0)
+            .descriptor_type(descriptor_type(index));
+        let write = match storage[index].1 {
+            StorageDesc::Buffer { .. } => write.buffer_info(buffer_info),
+            StorageDesc::Image { .. } => write.image_info(image_info),
+        };
+        device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    let push_constant_ranges = interface.push_constants.as_slice();
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+    let layout = data.deletion_queue.own(device.create_pipeline_layout(&layout_info, None)?);
+
+    let pipeline = create_compute_pipeline(device, data, shader, *layout, &desc.constants)?;
+
+    Ok(ComputePass {
+        shader: desc.shader.clone(),
+        _set_layout: set_layout,
+        set,
+        layout,
+        pipeline,
+        push_constant_size: desc.push_constant_size,
+        constants: desc.constants.clone(),
+        interface,
+    })
+}
+
+/// Fails unless the layout described by `interface` provides everything the
+/// compute shader loaded from `path` uses.
+fn check_shader(interface: &ShaderInterface, path: &Path, shader: &[u8]) -> Result<()> {
+    interface
+        .check_provides(&reflect(shader)?)
+        .map_err(|e| anyhow!("Compute shader `{}` doesn't fit the bindings of its pass: {}", path.display(), e))
+}
+
+/// Rebuilds the pipeline of compute pass `index` after its shader changed,
+/// keeping its layout and descriptor set.
+pub(crate) unsafe fn reload_compute_pass(device: &Device, data: &mut AppData, index: usize) -> Result<()> {
+    let path = data.compute.passes[index].shader.clone();
+    let shader = load_shader_file(data, &path, ShaderUser::Compute(index))?;
+    let pass = &data.compute.passes[index];
+    check_shader(&pass.interface, &path, &shader)?;
+    let pipeline = create_compute_pipeline(device, data, &shader, *pass.layout, &pass.constants)?;
+    data.compute.passes[index].pipeline = pipeline;
+    Ok(())
+}
+
+fn sharing_mode(families: &[u32]) -> vk::SharingMode {
+    if families.len() > 1 {
+        vk::SharingMode::CONCURRENT
+    } else {
+        vk::SharingMode::EXCLUSIVE
+    }
+}
+
+unsafe fn create_storage_buffer(
+    device: &Device,
+    data: &mut AppData,
+    families: &[u32],
+    size: u64,
+    contents: &[u8],
+) -> Result<StorageResource> {
+    let info = vk::BufferCreateInfo::builder()
+        .size(size)
+        .usage(vk::BufferUsageFlags::STORAGE_BUFFER
+            | vk::BufferUsageFlags::VERTEX_BUFFER
+            | vk::BufferUsageFlags::TRANSFER_DST)
+        .sharing_mode(sharing_mode(families))
+        .queue_family_indices(families);
+    let buffer = data.deletion_queue.own(device.create_buffer(&info, None)?);
+
+    let requirements = device.get_buffer_memory_requirements(*buffer);
+    let memory = data.deletion_queue.own(data.allocator.allocate(
+        device,
+        requirements,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        ResourceKind::Buffer,
+        Strategy::FreeList,
+    )?);
+    device.bind_buffer_memory(*buffer, memory.memory, memory.offset)?;
+
+    // Shared by every family, so the copy needs no ownership transfer.
+    let mut contents = contents.to_vec();
+    contents.resize(size as usize, 0);
+    let (source, offset) = data.uploader.stage(device, &contents)?;
+    let region = vk::BufferCopy::builder()
+        .src_offset(offset)
+        .dst_offset(0)
+        .size(size);
+    let command_buffer = data.uploader.command_buffer(device)?;
+    device.cmd_copy_buffer(command_buffer, source, *buffer, &[region]);
+
+    Ok(StorageResource { buffer, _memory: memory, ..Default::default() })
+}
+
+unsafe fn create_storage_image(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    families: &[u32],
+    width: u32,
+    height: u32,
+    format: StorageFormat,
+) -> Result<StorageResource> {
+    let format = format.to_vk();
+    if !instance
+        .get_physical_device_format_properties(data.physical_device, format)
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::STORAGE_IMAGE)
+    {
+        return Err(anyhow!("{:?} is not supported for storage images.", format));
+    }
+
+    let info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::_2D)
+        .extent(vk::Extent3D { width, height, depth: 1 })
+        .mip_levels(1)
+        .array_layers(1)
+        .format(format)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC)
+        .samples(vk::SampleCountFlags::_1)
+        .sharing_mode(sharing_mode(families))
+        .queue_family_indices(families);
+    let image = data.deletion_queue.own(device.create_image(&info, None)?);
+
+    let requirements = device.get_image_memory_requirements(*image);
+    let memory = data.deletion_queue.own(data.allocator.allocate(
+        device,
+        requirements,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        ResourceKind::Image,
+        Strategy::FreeList,
+    )?);
+    device.bind_image_memory(*image, memory.memory, memory.offset)?;
+
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    transition_layouts(device, data.uploader.graphics_command_buffer(device)?, &[
+        LayoutTransition::new(*image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL),
+    ])?;
+
+    let info = vk::ImageViewCreateInfo::builder()
+        .image(*image)
+        .view_type(vk::ImageViewType::_2D)
+        .format(format)
+        .subresource_range(subresource_range);
+    let view = data.deletion_queue.own(device.create_image_view(&info, None)?);
+
+    Ok(StorageResource { _image: image, view, _memory: memory, ..Default::default() })
+}
+
+/// Records the queued dispatches. Later dispatches and `dst_stage` see
+/// their writes.
+unsafe fn record_dispatches(
+    device: &Device,
+    compute: &Compute,
+    command_buffer: vk::CommandBuffer,
+    dst_stage: vk::PipelineStageFlags,
+) {
+    let barrier = |src_stage, dst_stage| {
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::SHADER_WRITE);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+    };
+
+    // Also orders this frame's dispatches after those of earlier frames.
+    barrier(vk::PipelineStageFlags::COMPUTE_SHADER, vk::PipelineStageFlags::COMPUTE_SHADER);
+    for (i, dispatch) in compute.dispatches.iter().enumerate() {
+        if i > 0 {
+            barrier(vk::PipelineStageFlags::COMPUTE_SHADER, vk::PipelineStageFlags::COMPUTE_SHADER);
+        }
+        let pass = &compute.passes[dispatch.pass];
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, *pass.pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            *pass.layout,
+            0,
+            &[pass.set],
+            &[],
+        );
+        if !dispatch.push_constants.is_empty() {
+            device.cmd_push_constants(
+                command_buffer,
+                *pass.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                &dispatch.push_constants,
+            );
+        }
+        let [x, y, z] = dispatch.groups;
+        device.cmd_dispatch(command_buffer, x, y, z);
+    }
+    if !dst_stage.is_empty() {
+        barrier(vk::PipelineStageFlags::COMPUTE_SHADER, dst_stage);
+    }
+}
+
+/// Records the frame's dispatches ahead of its render pass, unless they run
+/// on the async compute queue.
+pub(crate) unsafe fn record_compute(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer) {
+    if data.compute.dispatches.is_empty() || data.compute.is_async() {
+        return;
+    }
+    let dst_stage = vk::PipelineStageFlags::VERTEX_INPUT
+        | vk::PipelineStageFlags::VERTEX_SHADER
+        | vk::PipelineStageFlags::FRAGMENT_SHADER;
+    record_dispatches(device, &data.compute, command_buffer, dst_stage);
+}
+
+/// Submits the frame's dispatches to the async compute queue. Returns the
+/// semaphore the frame has to wait on and its timeline value, if anything
+/// was submitted. With timeline semaphores the submission signals `value`.
+///
+/// The dispatches wait for the previous frame's graphics work, which may
+/// still read the storage resources they write. Without timeline semaphores
+/// that frame signaled a binary semaphore, which is waited on even when
+/// nothing is dispatched so it can be signaled again.
+pub(crate) unsafe fn submit_compute(
+    device: &Device,
+    data: &AppData,
+    frame: usize,
+    value: u64,
+) -> Result<Option<(vk::Semaphore, u64)>> {
+    let compute = &data.compute;
+    if !compute.is_async() || (compute.dispatches.is_empty() && data.timeline_semaphores) {
+        return Ok(None);
+    }
+
+    // The frame's fence covers this buffer, the graphics submission that
+    // signals it waits for the compute submission.
+    let command_buffer = compute.command_buffers[frame];
+    if !compute.dispatches.is_empty() {
+        device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+        let info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(command_buffer, &info)?;
+        // The semaphore makes the writes visible to the frame.
+        record_dispatches(device, compute, command_buffer, vk::PipelineStageFlags::empty());
+        device.end_command_buffer(command_buffer)?;
+    }
+
+    let command_buffers: &[vk::CommandBuffer] = if compute.dispatches.is_empty() { &[] } else { &[command_buffer] };
+    let wait_stages = &[vk::PipelineStageFlags::COMPUTE_SHADER];
+    if data.timeline_semaphores {
+        // Frame `n` signals `n + 1` on the frame timeline, so the previous
+        // frame has finished at `value - 1`.
+        let wait_semaphores = &[data.frame_timeline.semaphore()];
+        let wait_values = &[value - 1];
+        let signal_semaphores = &[compute.timeline.semaphore()];
+        let signal_values = &[value];
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(wait_values)
+            .signal_semaphore_values(signal_values);
+        let info = vk::SubmitInfo::builder()
+            .wait_semaphores(wait_semaphores)
+            .wait_dst_stage_mask(wait_stages)
+            .command_buffers(command_buffers)
+            .signal_semaphores(signal_semaphores)
+            .push_next(&mut timeline_info);
+        device.queue_submit(compute.queue, &[info], vk::Fence::null())?;
+        return Ok(Some((compute.timeline.semaphore(), value)));
+    }
+
+    // The first frame has no previous frame to wait for.
+    let previous = (frame + compute.released.len() - 1) % compute.released.len();
+    let wait_semaphores: &[vk::Semaphore] = if value > 1 { &[*compute.released[previous]] } else { &[] };
+    let signal_semaphores = &[*compute.semaphores[frame]];
+    let info = vk::SubmitInfo::builder()
+        .wait_semaphores(wait_semaphores)
+        .wait_dst_stage_mask(&wait_stages[..wait_semaphores.len()])
+        .command_buffers(command_buffers)
+        .signal_semaphores(signal_semaphores);
+    device.queue_submit(compute.queue, &[info], vk::Fence::null())?;
+    Ok(Some((*compute.semaphores[frame], 0)))
+}
@@ -0,0 +1,389 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use vulkanalia::vk;
+use crate::deferred_util::RenderPath;
+use crate::sky_util::SkyMode;
+
+/// Swapchain presentation, see `vk::PresentModeKHR`. Falls back to `Fifo`,
+/// which every device supports, when the requested mode is unavailable.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresentMode {
+    /// Vsync.
+    Fifo,
+    /// Vsync, but late frames are shown immediately.
+    FifoRelaxed,
+    /// Vsync without blocking, the newest frame wins.
+    Mailbox,
+    /// No vsync, may tear.
+    #[default]
+    Immediate,
+}
+
+impl PresentMode {
+    pub fn to_vk(self) -> vk::PresentModeKHR {
+        match self {
+            PresentMode::Fifo => vk::PresentModeKHR::FIFO,
+            PresentMode::FifoRelaxed => vk::PresentModeKHR::FIFO_RELAXED,
+            PresentMode::Mailbox => vk::PresentModeKHR::MAILBOX,
+            PresentMode::Immediate => vk::PresentModeKHR::IMMEDIATE,
+        }
+    }
+}
+
+impl FromStr for PresentMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fifo" => Ok(PresentMode::Fifo),
+            "fifo_relaxed" => Ok(PresentMode::FifoRelaxed),
+            "mailbox" => Ok(PresentMode::Mailbox),
+            "immediate" => Ok(PresentMode::Immediate),
+            _ => Err(anyhow!("Unknown present mode `{}`, expected fifo, fifo_relaxed, mailbox or immediate.", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    pub title: String,
+    /// Initial size in logical pixels.
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self { title: "Elingine".to_string(), width: 1024, height: 768 }
+    }
+}
+
+/// Startup settings of the engine. Layered from the defaults, the game's
+/// builder calls, an optional TOML file and the command line, in that order.
+/// See `src/resources/engine.toml` for the file format.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    pub window: WindowConfig,
+    /// Reported to the driver, defaults to the window title.
+    pub application_name: Option<String>,
+    pub present_mode: PresentMode,
+    /// Frames the CPU may record ahead of the GPU.
+    pub frames_in_flight: usize,
+    /// Enables the Khronos validation layer and the debug messenger.
+    pub validation: bool,
+    /// Samples per pixel of the forward path, 1 disables MSAA. Rounded down
+    /// to what the device supports.
+    pub msaa: u32,
+    /// Directory holding the engine's own assets: `shaders/` and the
+    /// defaults in `resources/`. Paths handed to `EngineBuilder` are not
+    /// affected, resolve them with `asset` if they live here too.
+    pub asset_root: PathBuf,
+    /// Part of the name of the GPU to use, case insensitive. The first
+    /// suitable device is used when no name matches.
+    pub preferred_gpu: Option<String>,
+    pub render_path: RenderPath,
+    pub sky: SkyMode,
+    pub clear_color: [f32; 4],
+    /// Start with an orthographic camera.
+    pub orthographic: bool,
+    /// See `Camera::reverse_z`.
+    pub reverse_z: bool,
+    /// Run compute passes on a separate queue family, if the device has one.
+    pub async_compute: bool,
+    /// Pace frames and uploads with timeline semaphores instead of fences.
+    /// Needs a Vulkan 1.2 device, others keep using fences.
+    pub timeline_semaphores: bool,
+    /// Render without render pass and framebuffer objects, using dynamic
+    /// rendering and `synchronization2`. Needs a Vulkan 1.3 device and the
+    /// forward path, otherwise render passes are used.
+    pub dynamic_rendering: bool,
+    /// File the pipeline cache is loaded from on startup and saved to on
+    /// shutdown, `None` keeps it in memory only. Caches written by another
+    /// device or driver are ignored.
+    pub pipeline_cache: Option<PathBuf>,
+    /// Recompile shaders when their sources change and rebuild the
+    /// pipelines using them. Needs the `runtime-shaders` feature.
+    pub hot_reload: bool,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            window: WindowConfig::default(),
+            application_name: None,
+            present_mode: PresentMode::default(),
+            frames_in_flight: 2,
+            validation: cfg!(debug_assertions),
+            msaa: 1,
+            asset_root: "src".into(),
+            preferred_gpu: None,
+            render_path: RenderPath::default(),
+            sky: SkyMode::default(),
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            orthographic: false,
+            reverse_z: false,
+            async_compute: false,
+            timeline_semaphores: false,
+            dynamic_rendering: false,
+            pipeline_cache: Some("pipeline_cache.bin".into()),
+            hot_reload: false,
+        }
+    }
+}
+
+impl EngineConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a config file, missing keys keep their defaults.
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::default().merge_file(path)
+    }
+
+    /// Overrides the keys set in the file at `path`, keeping the others.
+    pub fn merge_file(self, path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read config `{}`: {}", path.display(), e))?;
+        let overrides = toml::from_str::<toml::Table>(&text)
+            .map_err(|e| anyhow!("Failed to parse config `{}`: {}", path.display(), e))?;
+        let mut table = toml::from_str::<toml::Table>(&toml::to_string(&self)?)?;
+        merge_tables(&mut table, overrides);
+        toml::Value::Table(table)
+            .try_into::<Self>()
+            .map_err(|e| anyhow!("Invalid config `{}`: {}", path.display(), e))
+    }
+
+    /// Applies the command line options. `--config=<file>` is merged first,
+    /// then the options override it:
+    ///
+    /// `--title=<title>`, `--size=<width>x<height>`, `--present-mode=<mode>`,
+    /// `--frames-in-flight=<n>`, `--validation`, `--no-validation`,
+    /// `--msaa=<samples>`, `--asset-root=<dir>`, `--gpu=<name>`, `--deferred`,
+    /// `--sky=procedural`, `--skybox=<dir>`, `--clear-color=r,g,b[,a]`,
+    /// `--orthographic`, `--reverse-z`, `--async-compute`,
+    /// `--timeline-semaphores`, `--dynamic-rendering`,
+    /// `--pipeline-cache=<file>`, `--no-pipeline-cache` and `--hot-reload`.
+    ///
+    /// Arguments not starting with `--`, like the program name or file
+    /// paths, are left for the game. Other options are an error.
+    pub fn args(mut self, args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let args = args.into_iter().collect::<Vec<_>>();
+        for arg in &args {
+            if let Some(path) = arg.strip_prefix("--config=") {
+                self = self.merge_file(Path::new(path))?;
+            }
+        }
+
+        for arg in &args {
+            let (key, value) = match arg.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            let parse_error = |e: &dyn std::fmt::Display| anyhow!("Invalid `{}`: {}", arg, e);
+            match (key, value) {
+                ("--title", Some(title)) => self.window.title = title.to_string(),
+                ("--size", Some(size)) => {
+                    let (width, height) = size
+                        .split_once('x')
+                        .ok_or_else(|| anyhow!("Expected `--size=<width>x<height>`, got `{}`.", arg))?;
+                    self.window.width = width.parse().map_err(|e| parse_error(&e))?;
+                    self.window.height = height.parse().map_err(|e| parse_error(&e))?;
+                }
+                ("--present-mode", Some(mode)) => self.present_mode = mode.parse()?,
+                ("--frames-in-flight", Some(n)) => self.frames_in_flight = n.parse().map_err(|e| parse_error(&e))?,
+                ("--validation", None) => self.validation = true,
+                ("--no-validation", None) => self.validation = false,
+                ("--msaa", Some(samples)) => self.msaa = samples.parse().map_err(|e| parse_error(&e))?,
+                ("--asset-root", Some(dir)) => self.asset_root = dir.into(),
+                ("--gpu", Some(name)) => self.preferred_gpu = Some(name.to_string()),
+                ("--deferred", None) => self.render_path = RenderPath::Deferred,
+                ("--sky", Some("procedural")) => self.sky = SkyMode::Procedural,
+                ("--sky", _) => return Err(anyhow!("Expected `--sky=procedural`, got `{}`.", arg)),
+                ("--skybox", Some(dir)) => self.sky = SkyMode::Cubemap(dir.into()),
+                ("--clear-color", Some(color)) => self.clear_color = parse_color(color)
+                    .ok_or_else(|| anyhow!("Expected `--clear-color=r,g,b[,a]`, got `{}`.", arg))?,
+                ("--orthographic", None) => self.orthographic = true,
+                ("--reverse-z", None) => self.reverse_z = true,
+                ("--async-compute", None) => self.async_compute = true,
+                ("--timeline-semaphores", None) => self.timeline_semaphores = true,
+                ("--dynamic-rendering", None) => self.dynamic_rendering = true,
+                ("--pipeline-cache", Some(path)) => self.pipeline_cache = Some(path.into()),
+                ("--no-pipeline-cache", None) => self.pipeline_cache = None,
+                ("--hot-reload", None) => self.hot_reload = true,
+                ("--config", Some(_)) => {}
+                _ if !arg.starts_with("--") => {}
+                _ => return Err(anyhow!("Unknown option `{}`.", arg)),
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.window.title = title.into();
+        self
+    }
+
+    /// Initial window size in logical pixels.
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.window.width = width;
+        self.window.height = height;
+        self
+    }
+
+    pub fn application_name(mut self, name: impl Into<String>) -> Self {
+        self.application_name = Some(name.into());
+        self
+    }
+
+    pub fn present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+
+    pub fn frames_in_flight(mut self, frames: usize) -> Self {
+        self.frames_in_flight = frames;
+        self
+    }
+
+    pub fn validation(mut self, enabled: bool) -> Self {
+        self.validation = enabled;
+        self
+    }
+
+    pub fn msaa(mut self, samples: u32) -> Self {
+        self.msaa = samples;
+        self
+    }
+
+    pub fn asset_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.asset_root = root.into();
+        self
+    }
+
+    pub fn preferred_gpu(mut self, name: impl Into<String>) -> Self {
+        self.preferred_gpu = Some(name.into());
+        self
+    }
+
+    pub fn render_path(mut self, render_path: RenderPath) -> Self {
+        self.render_path = render_path;
+        self
+    }
+
+    pub fn sky(mut self, sky: SkyMode) -> Self {
+        self.sky = sky;
+        self
+    }
+
+    pub fn clear_color(mut self, clear_color: [f32; 4]) -> Self {
+        self.clear_color = clear_color;
+        self
+    }
+
+    pub fn async_compute(mut self, enabled: bool) -> Self {
+        self.async_compute = enabled;
+        self
+    }
+
+    pub fn timeline_semaphores(mut self, enabled: bool) -> Self {
+        self.timeline_semaphores = enabled;
+        self
+    }
+
+    pub fn dynamic_rendering(mut self, enabled: bool) -> Self {
+        self.dynamic_rendering = enabled;
+        self
+    }
+
+    pub fn pipeline_cache(mut self, path: Option<PathBuf>) -> Self {
+        self.pipeline_cache = path;
+        self
+    }
+
+    pub fn hot_reload(mut self, enabled: bool) -> Self {
+        self.hot_reload = enabled;
+        self
+    }
+
+    /// `path` relative to the asset root, absolute paths are kept.
+    pub fn asset(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.asset_root.join(path)
+    }
+
+    /// The name reported to the driver.
+    pub(crate) fn app_name(&self) -> &str {
+        self.application_name.as_deref().unwrap_or(&self.window.title)
+    }
+
+    /// Checks the values that would otherwise fail deep inside Vulkan.
+    pub fn validate(&self) -> Result<()> {
+        if self.frames_in_flight == 0 {
+            return Err(anyhow!("`frames_in_flight` must be at least 1."));
+        }
+        if !self.msaa.is_power_of_two() || self.msaa > 64 {
+            return Err(anyhow!("`msaa` must be 1, 2, 4, 8, 16, 32 or 64, got {}.", self.msaa));
+        }
+        if self.window.width == 0 || self.window.height == 0 {
+            return Err(anyhow!("The window size must not be zero."));
+        }
+        Ok(())
+    }
+}
+
+/// Parses `r,g,b` or `r,g,b,a`.
+fn parse_color(value: &str) -> Option<[f32; 4]> {
+    let components = value
+        .split(',')
+        .map(|c| c.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    match components[..] {
+        [r, g, b] => Some([r, g, b, 1.0]),
+        [r, g, b, a] => Some([r, g, b, a]),
+        _ => None,
+    }
+}
+
+/// Overwrites the keys of `base` with those of `overrides`, recursing into tables.
+fn merge_tables(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(value)) => merge_tables(base, value),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<EngineConfig> {
+        EngineConfig::new().args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn options_override_defaults() {
+        let config = args(&["game", "model.obj", "--deferred", "--msaa=4", "--size=640x480"]).unwrap();
+        assert_eq!(config.render_path, RenderPath::Deferred);
+        assert_eq!(config.msaa, 4);
+        assert_eq!((config.window.width, config.window.height), (640, 480));
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        let error = args(&["game", "--defered"]).unwrap_err();
+        assert!(error.to_string().contains("--defered"), "{}", error);
+        let error = args(&["game", "--sky=foo"]).unwrap_err();
+        assert!(error.to_string().contains("--sky=foo"), "{}", error);
+        assert!(args(&["game", "--validation=yes"]).is_err());
+    }
+}
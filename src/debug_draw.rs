@@ -0,0 +1,259 @@
+use std::mem::size_of;
+use anyhow::Result;
+use cgmath::{vec3, vec4, EuclideanSpace, InnerSpace, SquareMatrix, Transform};
+use vulkanalia::{vk, Device};
+use vulkanalia::vk::{DeviceV1_0, HasBuilder};
+use crate::lights::Vec4;
+use crate::render_app::AppData;
+use crate::transforms::{Mat4, Vec3};
+
+const CIRCLE_SEGMENTS: usize = 32;
+
+/// Color, lifetime and depth testing of queued debug shapes.
+#[derive(Copy, Clone, Debug)]
+pub struct DebugStyle {
+    pub color: Vec4,
+    /// Seconds the shape stays visible, `0.0` draws it for one frame only.
+    pub duration: f32,
+    /// Whether the shape is hidden behind scene geometry.
+    pub depth_test: bool,
+}
+
+impl DebugStyle {
+    pub fn new(color: Vec4) -> Self {
+        Self { color, duration: 0.0, depth_test: true }
+    }
+
+    pub fn duration(mut self, seconds: f32) -> Self {
+        self.duration = seconds;
+        self
+    }
+
+    pub fn overlay(mut self) -> Self {
+        self.depth_test = false;
+        self
+    }
+}
+
+impl From<Vec4> for DebugStyle {
+    fn from(color: Vec4) -> Self {
+        Self::new(color)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct DebugLine {
+    start: Vec3,
+    end: Vec3,
+    style: DebugStyle,
+}
+
+/// Vertex of the debug line pipeline.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct DebugVertex {
+    pub pos: Vec3,
+    pub color: Vec4,
+}
+
+impl DebugVertex {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<DebugVertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        let pos = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build();
+        let color = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(size_of::<Vec3>() as u32)
+            .build();
+        [pos, color]
+    }
+}
+
+/// Immediate mode line drawing for debugging. Shapes queued during a frame
+/// are drawn at the end of it in world space, then dropped once their
+/// duration has run out.
+#[derive(Clone, Debug, Default)]
+pub struct DebugDraw {
+    lines: Vec<DebugLine>,
+    /// Vertices uploaded for the current frame, depth tested ones first.
+    pub(crate) depth_tested_vertex_count: u32,
+    pub(crate) overlay_vertex_count: u32,
+    /// Where the current frame's vertices live in its `FrameArena`.
+    pub(crate) vertex_buffer: vk::Buffer,
+    pub(crate) vertex_offset: vk::DeviceSize,
+}
+
+impl DebugDraw {
+    pub fn line(&mut self, start: Vec3, end: Vec3, style: impl Into<DebugStyle>) {
+        self.lines.push(DebugLine { start, end, style: style.into() });
+    }
+
+    /// Axis aligned box between `min` and `max`.
+    pub fn aabb(&mut self, min: Vec3, max: Vec3, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        let corner = |i: usize| vec3(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        );
+        self.box_edges(corner, style);
+    }
+
+    /// Three great circles of a sphere.
+    pub fn sphere(&mut self, center: Vec3, radius: f32, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        self.circle(center, vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), radius, style);
+        self.circle(center, vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0), radius, style);
+        self.circle(center, vec3(0.0, 0.0, 1.0), vec3(1.0, 0.0, 0.0), radius, style);
+    }
+
+    /// Circle in the plane spanned by the unit vectors `u` and `v`.
+    pub fn circle(&mut self, center: Vec3, u: Vec3, v: Vec3, radius: f32, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        let point = |i: usize| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+            center + (u * angle.cos() + v * angle.sin()) * radius
+        };
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), style);
+        }
+    }
+
+    /// The volume visible through `view_proj`, e.g. a camera's frustum.
+    pub fn frustum(&mut self, view_proj: Mat4, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        let Some(inverse) = view_proj.invert() else {
+            return;
+        };
+        // Vulkan clip space, depth from 0 to 1.
+        let corner = |i: usize| {
+            let ndc = cgmath::Point3::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { 0.0 } else { 1.0 },
+            );
+            inverse.transform_point(ndc).to_vec()
+        };
+        self.box_edges(corner, style);
+    }
+
+    /// X, Y and Z axes of `transform` in red, green and blue.
+    pub fn axes(&mut self, transform: Mat4, length: f32, depth_test: bool) {
+        let origin = transform.transform_point(cgmath::Point3::origin()).to_vec();
+        let axes = [
+            (vec3(1.0, 0.0, 0.0), vec4(1.0, 0.2, 0.2, 1.0)),
+            (vec3(0.0, 1.0, 0.0), vec4(0.2, 1.0, 0.2, 1.0)),
+            (vec3(0.0, 0.0, 1.0), vec4(0.2, 0.4, 1.0, 1.0)),
+        ];
+        for (axis, color) in axes {
+            let end = origin + transform.transform_vector(axis).normalize() * length;
+            self.line(origin, end, DebugStyle { color, duration: 0.0, depth_test });
+        }
+    }
+
+    /// A square grid in the XY plane, `size` wide with `divisions` cells per side.
+    pub fn grid(&mut self, center: Vec3, size: f32, divisions: u32, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        let half = size / 2.0;
+        let divisions = divisions.max(1);
+        for i in 0..=divisions {
+            let offset = -half + size * i as f32 / divisions as f32;
+            self.line(center + vec3(offset, -half, 0.0), center + vec3(offset, half, 0.0), style);
+            self.line(center + vec3(-half, offset, 0.0), center + vec3(half, offset, 0.0), style);
+        }
+    }
+
+    fn box_edges(&mut self, corner: impl Fn(usize) -> Vec3, style: DebugStyle) {
+        const EDGES: [(usize, usize); 12] = [
+            (0, 1), (2, 3), (4, 5), (6, 7),
+            (0, 2), (1, 3), (4, 6), (5, 7),
+            (0, 4), (1, 5), (2, 6), (3, 7),
+        ];
+        for (a, b) in EDGES {
+            self.line(corner(a), corner(b), style);
+        }
+    }
+
+    /// Vertices of all queued lines, depth tested ones first.
+    fn vertices(&self) -> (Vec<DebugVertex>, u32) {
+        let mut vertices = Vec::with_capacity(self.lines.len() * 2);
+        for depth_test in [true, false] {
+            for line in self.lines.iter().filter(|l| l.style.depth_test == depth_test) {
+                vertices.push(DebugVertex { pos: line.start, color: line.style.color });
+                vertices.push(DebugVertex { pos: line.end, color: line.style.color });
+            }
+        }
+        let depth_tested = self.lines.iter().filter(|l| l.style.depth_test).count() as u32 * 2;
+        (vertices, depth_tested)
+    }
+
+    /// Ages the queued shapes by `dt` seconds and drops the expired ones.
+    pub fn end_frame(&mut self, dt: f32) {
+        self.lines.retain_mut(|line| {
+            line.style.duration -= dt;
+            line.style.duration > 0.0
+        });
+    }
+}
+
+/// Copies the queued lines into the arena of frame `frame`.
+pub(crate) unsafe fn upload_debug_draw(device: &Device, data: &mut AppData, frame: usize) -> Result<()> {
+    let (vertices, depth_tested) = data.debug_draw.vertices();
+    data.debug_draw.depth_tested_vertex_count = depth_tested;
+    data.debug_draw.overlay_vertex_count = vertices.len() as u32 - depth_tested;
+    if vertices.is_empty() {
+        return Ok(());
+    }
+
+    let (buffer, offset) = data.frame_arenas[frame].push(device, &vertices)?;
+    data.debug_draw.vertex_buffer = buffer;
+    data.debug_draw.vertex_offset = offset;
+    Ok(())
+}
+
+/// Draws the lines uploaded for frame `frame`.
+pub(crate) unsafe fn record_debug_draw(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    frame: usize,
+) {
+    let draw = &data.debug_draw;
+    if draw.depth_tested_vertex_count + draw.overlay_vertex_count == 0 {
+        return;
+    }
+
+    device.cmd_bind_vertex_buffers(command_buffer, 0, &[draw.vertex_buffer], &[draw.vertex_offset]);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *data.pipeline_layout,
+        0,
+        &[data.descriptor_sets[frame]],
+        &[],
+    );
+
+    if draw.depth_tested_vertex_count > 0 {
+        device.cmd_bind_pipeline(
+            command_buffer, vk::PipelineBindPoint::GRAPHICS, *data.debug_line_pipeline);
+        device.cmd_draw(command_buffer, draw.depth_tested_vertex_count, 1, 0, 0);
+    }
+    if draw.overlay_vertex_count > 0 {
+        device.cmd_bind_pipeline(
+            command_buffer, vk::PipelineBindPoint::GRAPHICS, *data.debug_overlay_pipeline);
+        device.cmd_draw(command_buffer, draw.overlay_vertex_count, 1, draw.depth_tested_vertex_count, 0);
+    }
+}
@@ -0,0 +1,82 @@
+use vulkanalia::vk;
+use vulkanalia::vk::Handle;
+use winit::keyboard::KeyCode;
+use crate::resource_util::Pipeline;
+
+/// Visualizations that replace the lit scene, switched at runtime with F1-F7.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DebugView {
+    #[default]
+    Lit,
+    /// Triangle edges, drawn without depth test. Needs `fill_mode_non_solid`.
+    Wireframe,
+    /// World space vertex normals as colors.
+    Normals,
+    /// A checker pattern in texture space, shows stretching and seams.
+    UvChecker,
+    /// View space distance, near is white.
+    Depth,
+    /// The mip level the texture is sampled at.
+    MipLevel,
+    /// How many fragments are shaded per pixel.
+    Overdraw,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 7] = [
+        DebugView::Lit,
+        DebugView::Wireframe,
+        DebugView::Normals,
+        DebugView::UvChecker,
+        DebugView::Depth,
+        DebugView::MipLevel,
+        DebugView::Overdraw,
+    ];
+
+    pub fn from_key(key: KeyCode) -> Option<Self> {
+        match key {
+            KeyCode::F1 => Some(DebugView::Lit),
+            KeyCode::F2 => Some(DebugView::Wireframe),
+            KeyCode::F3 => Some(DebugView::Normals),
+            KeyCode::F4 => Some(DebugView::UvChecker),
+            KeyCode::F5 => Some(DebugView::Depth),
+            KeyCode::F6 => Some(DebugView::MipLevel),
+            KeyCode::F7 => Some(DebugView::Overdraw),
+            _ => None,
+        }
+    }
+
+    /// The fragment shader source of the view, `None` for `Lit`.
+    pub fn fragment_shader(self) -> Option<&'static str> {
+        match self {
+            DebugView::Lit => None,
+            DebugView::Wireframe => Some("debug_flat.frag"),
+            DebugView::Normals => Some("debug_normals.frag"),
+            DebugView::UvChecker => Some("debug_uv.frag"),
+            DebugView::Depth => Some("debug_depth.frag"),
+            DebugView::MipLevel => Some("debug_mip.frag"),
+            DebugView::Overdraw => Some("debug_overdraw.frag"),
+        }
+    }
+
+    /// Whether the view is drawn on top of everything rather than depth tested.
+    pub fn ignores_depth(self) -> bool {
+        matches!(self, DebugView::Wireframe | DebugView::Overdraw)
+    }
+}
+
+/// One pipeline per `DebugView`, indexed by `DebugView as usize`. The entry
+/// for `Lit` and for unsupported views stays null.
+#[derive(Debug, Default)]
+pub struct DebugPipelines {
+    pub pipelines: Vec<Pipeline>,
+}
+
+impl DebugPipelines {
+    pub fn get(&self, view: DebugView) -> Option<vk::Pipeline> {
+        self.pipelines
+            .get(view as usize)
+            .map(|p| p.handle())
+            .filter(|p| !p.is_null())
+    }
+}
@@ -0,0 +1,229 @@
+use std::mem::size_of;
+use anyhow::{anyhow, Result};
+use vulkanalia::{vk, Device, Instance};
+use vulkanalia::vk::{DeviceV1_0, HasBuilder, InstanceV1_0};
+use serde::{Deserialize, Serialize};
+use crate::buffer_util::create_buffer;
+use crate::image_util::{create_image, create_image_view};
+use crate::lights::LightUniform;
+use crate::render_app::AppData;
+use crate::shader_compiler::ShaderUser;
+use crate::shader_module_util::reflect_pipelines;
+use crate::resource_util::{Buffer, DescriptorPool, DescriptorSetLayout, Image, ImageView, Memory, Pipeline, PipelineLayout};
+
+/// Which renderer is used to shade the scene, chosen at startup.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderPath {
+    /// Geometry is shaded directly into the swapchain image.
+    #[default]
+    Forward,
+    /// Geometry is written to a G-buffer which is then lit in a second subpass.
+    Deferred,
+}
+
+pub const GBUFFER_ALBEDO_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+pub const GBUFFER_NORMAL_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+pub const GBUFFER_MATERIAL_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+/// A color attachment of the G-buffer.
+#[derive(Debug, Default)]
+pub struct Attachment {
+    /// Owned here, passes only see `view`.
+    _image: Image,
+    _memory: Memory,
+    pub view: ImageView,
+}
+
+/// The Vulkan handles used only by the deferred render path.
+#[derive(Debug, Default)]
+pub struct DeferredData {
+    pub albedo: Attachment,
+    pub normal: Attachment,
+    pub material: Attachment,
+
+    pub lighting_set_layout: DescriptorSetLayout,
+    pub lighting_pipeline_layout: PipelineLayout,
+    pub lighting_pipeline: Pipeline,
+    pub light_volume_pipeline: Pipeline,
+
+    pub descriptor_pool: DescriptorPool,
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    pub light_buffers: Vec<Buffer>,
+    pub light_buffers_memory: Vec<Memory>,
+}
+
+unsafe fn create_attachment(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    format: vk::Format,
+) -> Result<Attachment> {
+    let (image, memory) = create_image(
+        instance,
+        device,
+        data,
+        data.swapchain_extent.width,
+        data.swapchain_extent.height,
+        1,
+        vk::SampleCountFlags::_1,
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::INPUT_ATTACHMENT,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+    let view = create_image_view(device, data, *image, format, vk::ImageAspectFlags::COLOR, 1)?;
+    Ok(Attachment { _image: image, _memory: memory, view })
+}
+
+/// Creates the albedo, normal and material attachments of the G-buffer.
+/// Depth is shared with the depth attachment of the forward path.
+pub unsafe fn create_gbuffer_objects(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    data.deferred.albedo = create_attachment(instance, device, data, GBUFFER_ALBEDO_FORMAT)?;
+    data.deferred.normal = create_attachment(instance, device, data, GBUFFER_NORMAL_FORMAT)?;
+    data.deferred.material = create_attachment(instance, device, data, GBUFFER_MATERIAL_FORMAT)?;
+    Ok(())
+}
+
+/// The shaders of the ambient and point light pipelines, which share the
+/// lighting layout.
+pub(crate) const LIGHTING_SHADERS: [(&str, &str); 2] = [
+    ("fullscreen.vert", "deferred_light.frag"),
+    ("light_volume.vert", "light_volume.frag"),
+];
+
+/// Creates the lighting set layout from `LIGHTING_SHADERS`. They read albedo,
+/// normal, material and depth as input attachments, then the lights.
+pub unsafe fn create_lighting_descriptor_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    let interface = reflect_pipelines(data, &LIGHTING_SHADERS, ShaderUser::Scene)?;
+    let bindings = interface.set_layout_bindings(0);
+    let info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .bindings(&bindings);
+    data.deferred.lighting_set_layout = data.deletion_queue.own(device.create_descriptor_set_layout(&info, None)?);
+    Ok(())
+}
+
+pub unsafe fn create_light_buffers(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    data.deferred.light_buffers.clear();
+    data.deferred.light_buffers_memory.clear();
+
+    for _ in 0..data.config.frames_in_flight {
+        let (buffer, memory) = create_buffer(
+            instance,
+            device,
+            data,
+            size_of::<LightUniform>() as u64,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        data.deferred.light_buffers.push(buffer);
+        data.deferred.light_buffers_memory.push(memory);
+    }
+
+    Ok(())
+}
+
+pub unsafe fn create_lighting_descriptor_sets(device: &Device, data: &mut AppData) -> Result<()> {
+    let count = data.config.frames_in_flight as u32;
+
+    let input_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::INPUT_ATTACHMENT)
+        .descriptor_count(4 * count);
+
+    let ubo_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(count);
+
+    let pool_sizes = &[input_size, ubo_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(count);
+    data.deferred.descriptor_pool = data.deletion_queue.own(device.create_descriptor_pool(&info, None)?);
+
+    let layouts = vec![*data.deferred.lighting_set_layout; count as usize];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.deferred.descriptor_pool)
+        .set_layouts(&layouts);
+    data.deferred.descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    let views = [
+        (*data.deferred.albedo.view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        (*data.deferred.normal.view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        (*data.deferred.material.view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        (*data.depth_image_view, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL),
+    ];
+
+    for (i, set) in data.deferred.descriptor_sets.iter().enumerate() {
+        let image_infos = views
+            .iter()
+            .map(|(view, layout)| {
+                [vk::DescriptorImageInfo::builder()
+                    .image_view(*view)
+                    .image_layout(*layout)
+                    .build()]
+            })
+            .collect::<Vec<_>>();
+
+        let mut writes = image_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(binding as u32)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
+                    .image_info(info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let buffer_info = &[vk::DescriptorBufferInfo::builder()
+            .buffer(*data.deferred.light_buffers[i])
+            .offset(0)
+            .range(size_of::<LightUniform>() as u64)
+            .build()];
+
+        writes.push(
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(4)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(buffer_info)
+                .build(),
+        );
+
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+    }
+
+    Ok(())
+}
+
+/// Drops the swapchain sized resources of the deferred path, the deletion
+/// queue destroys them.
+pub fn release_deferred_swapchain_objects(data: &mut AppData) {
+    let deferred = &mut data.deferred;
+    deferred.albedo = Attachment::default();
+    deferred.normal = Attachment::default();
+    deferred.material = Attachment::default();
+    deferred.descriptor_pool = DescriptorPool::default();
+    deferred.descriptor_sets.clear();
+    deferred.light_buffers.clear();
+    deferred.light_buffers_memory.clear();
+    deferred.lighting_pipeline = Pipeline::default();
+    deferred.light_volume_pipeline = Pipeline::default();
+    deferred.lighting_pipeline_layout = PipelineLayout::default();
+}
+
+/// Checks that the G-buffer formats can be rendered to on the selected device.
+pub unsafe fn check_gbuffer_support(instance: &Instance, data: &AppData) -> Result<()> {
+    for format in [GBUFFER_ALBEDO_FORMAT, GBUFFER_NORMAL_FORMAT, GBUFFER_MATERIAL_FORMAT] {
+        let properties = instance.get_physical_device_format_properties(data.physical_device, format);
+        if !properties.optimal_tiling_features.contains(vk::FormatFeatureFlags::COLOR_ATTACHMENT) {
+            return Err(anyhow!("G-buffer format {:?} is not supported as color attachment.", format));
+        }
+    }
+    Ok(())
+}
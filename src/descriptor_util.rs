@@ -4,35 +4,29 @@ use vulkanalia::vk::{DeviceV1_0, HasBuilder};
 use crate::render_app::AppData;
 use anyhow::Result;
 use crate::buffer_util::create_buffer;
+use crate::shader_module_util::load_forward_shaders;
+use crate::spirv_reflect::{reflect, ShaderInterface};
 use crate::transforms::UniformBufferObject;
 
-pub unsafe fn create_descriptor_set_layout(device: &Device, data: &mut AppData )-> Result<()> {
-    let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
-        .binding(0)
-        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-        .descriptor_count(1)
-        .stage_flags(vk::ShaderStageFlags::VERTEX);
+/// Creates the global descriptor set layout from set 0 of the built in
+/// forward shaders, which every object pipeline binds.
+pub unsafe fn create_descriptor_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    let (vert, frag) = load_forward_shaders(data)?;
+    data.object_interface = ShaderInterface::merge(&[reflect(&vert)?, reflect(&frag)?])?;
 
-    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
-        .binding(1)
-        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-        .descriptor_count(1)
-        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
-
-    let bindings = &[ubo_binding, sampler_binding];
+    let bindings = data.object_interface.set_layout_bindings(0);
     let info = vk::DescriptorSetLayoutCreateInfo::builder()
-        .bindings(bindings);
-    data.descriptor_set_layout = device.create_descriptor_set_layout(&info, None)?;
+        .bindings(&bindings);
+    data.descriptor_set_layout = data.deletion_queue.own(device.create_descriptor_set_layout(&info, None)?);
     Ok(())
 }
 
-
-
+/// Creates one uniform buffer per frame in flight.
 pub unsafe fn create_uniform_buffers(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
     data.uniform_buffers.clear();
     data.uniform_buffers_memory.clear();
 
-    for _ in 0..data.swapchain_images.len() {
+    for _ in 0..data.config.frames_in_flight {
         let (uniform_buffer, uniform_buffer_memory) = create_buffer(
             instance,
             device,
@@ -52,36 +46,38 @@ pub unsafe fn create_uniform_buffers(instance: &Instance, device: &Device, data:
 pub unsafe fn create_descriptor_pool(device: &Device, data: &mut AppData) -> Result<()> {
     let ubo_size = vk::DescriptorPoolSize::builder()
         .type_(vk::DescriptorType::UNIFORM_BUFFER)
-        .descriptor_count(data.swapchain_images.len() as u32);
+        .descriptor_count(data.config.frames_in_flight as u32);
 
     let sampler_size = vk::DescriptorPoolSize::builder()
         .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-        .descriptor_count(data.swapchain_images.len() as u32);
+        .descriptor_count(data.config.frames_in_flight as u32);
 
     let pool_sizes = &[ubo_size, sampler_size];
     let info = vk::DescriptorPoolCreateInfo::builder()
         .pool_sizes(pool_sizes)
-        .max_sets(data.swapchain_images.len() as u32);
-    data.descriptor_pool = device.create_descriptor_pool(&info, None)?;
+        .max_sets(data.config.frames_in_flight as u32);
+    data.descriptor_pool = data.deletion_queue.own(device.create_descriptor_pool(&info, None)?);
 
     Ok(())
 }
 
+/// Allocates one descriptor set per frame in flight, reading that frame's
+/// uniform buffer.
 pub unsafe fn create_descriptor_sets(device: &Device, data: &mut AppData) -> Result<()> {
     // Allocate
 
-    let layouts = vec![data.descriptor_set_layout; data.swapchain_images.len()];
+    let layouts = vec![*data.descriptor_set_layout; data.config.frames_in_flight];
     let info = vk::DescriptorSetAllocateInfo::builder()
-        .descriptor_pool(data.descriptor_pool)
+        .descriptor_pool(*data.descriptor_pool)
         .set_layouts(&layouts);
 
     data.descriptor_sets = device.allocate_descriptor_sets(&info)?;
 
     // Update
 
-    for i in 0..data.swapchain_images.len() {
+    for i in 0..data.config.frames_in_flight {
         let info = vk::DescriptorBufferInfo::builder()
-            .buffer(data.uniform_buffers[i])
+            .buffer(*data.uniform_buffers[i])
             .offset(0)
             .range(size_of::<UniformBufferObject>() as u64);
 
@@ -92,26 +88,37 @@ pub unsafe fn create_descriptor_sets(device: &Device, data: &mut AppData) -> Res
             .dst_array_element(0)
             .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
             .buffer_info(buffer_info);
-        let info = vk::DescriptorImageInfo::builder()
-            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(data.texture_image_view)
-            .sampler(data.texture_sampler);
+        device.update_descriptor_sets(&[ubo_write], &[] as &[vk::CopyDescriptorSet]);
+        write_texture_descriptor(device, data, i);
+    }
+    data.stale_texture_descriptors = vec![false; data.config.frames_in_flight];
 
-        let image_info = &[info];
-        let sampler_write = vk::WriteDescriptorSet::builder()
-            .dst_set(data.descriptor_sets[i])
-            .dst_binding(1)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .image_info(image_info);
+    Ok(())
+}
 
-        device.update_descriptor_sets(
-            &[ubo_write, sampler_write],
-            &[] as &[vk::CopyDescriptorSet],
-        );
+/// Points the descriptor set of `frame` at the current texture if it was
+/// replaced. Called once the frame's previous submission has finished.
+pub unsafe fn update_texture_descriptor(device: &Device, data: &mut AppData, frame: usize) {
+    if std::mem::take(&mut data.stale_texture_descriptors[frame]) {
+        write_texture_descriptor(device, data, frame);
     }
+}
 
-    Ok(())
+unsafe fn write_texture_descriptor(device: &Device, data: &AppData, frame: usize) {
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(*data.texture_image_view)
+        .sampler(*data.texture_sampler);
+
+    let image_info = &[info];
+    let sampler_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.descriptor_sets[frame])
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(image_info);
+
+    device.update_descriptor_sets(&[sampler_write], &[] as &[vk::CopyDescriptorSet]);
 }
 
 
@@ -2,29 +2,73 @@ use std::collections::HashSet;
 use anyhow::anyhow;
 use log::{info, warn};
 use thiserror::Error;
-use vulkanalia::{vk, Device, Entry, Instance};
-use vulkanalia::vk::{DeviceV1_0, HasBuilder, InstanceV1_0};
-use crate::{AppData, QueueFamilyIndices, DEVICE_EXTENSIONS, PORTABILITY_MACOS_VERSION, VALIDATION_ENABLED, VALIDATION_LAYER};
+use vulkanalia::{vk, Device, Entry, Instance, Version};
+use vulkanalia::vk::{DeviceV1_0, HasBuilder, InstanceV1_0, InstanceV1_1};
+use crate::{AppData, QueueFamilyIndices, DEVICE_EXTENSIONS, PORTABILITY_MACOS_VERSION, VALIDATION_LAYER};
+use crate::deferred_util::RenderPath;
 use crate::swapchain_util::SwapchainSupport;
 
 #[derive(Debug, Error)]
 #[error("Missing {0}.")]
 pub struct SuitabilityError(pub &'static str);
-/// Picks a graphics card
+/// Picks a graphics card, the first suitable one whose name contains
+/// `EngineConfig::preferred_gpu` if set, otherwise the first suitable one.
 pub unsafe fn pick_physical_device(instance: &Instance, data: &mut AppData) -> anyhow::Result<()> {
+    let mut suitable = Vec::new();
     for physical_device in instance.enumerate_physical_devices()? {
         let properties = instance.get_physical_device_properties(physical_device);
 
         if let Err(error) = check_physical_device(instance, data, physical_device) {
             warn!("Skipping physical device (`{}`): {}", properties.device_name, error);
         } else {
-            info!("Selected physical device (`{}`).", properties.device_name);
-            data.physical_device = physical_device;
-            return Ok(());
+            suitable.push((physical_device, properties.device_name.to_string()));
         }
     }
 
-    Err(anyhow!("Failed to find suitable physical device."))
+    let preferred = data.config.preferred_gpu.as_ref().and_then(|preferred| {
+        let name = preferred.to_lowercase();
+        let found = suitable.iter().find(|(_, device_name)| device_name.to_lowercase().contains(&name));
+        if found.is_none() {
+            warn!("No suitable physical device matches `{}`.", preferred);
+        }
+        found
+    });
+
+    let (physical_device, name) = preferred
+        .or(suitable.first())
+        .ok_or_else(|| anyhow!("Failed to find suitable physical device."))?;
+    info!("Selected physical device (`{}`).", name);
+    data.physical_device = *physical_device;
+    Ok(())
+}
+
+/// The highest sample count up to `EngineConfig::msaa` that the device
+/// supports for both color and depth. The deferred path doesn't support MSAA.
+pub unsafe fn get_msaa_samples(instance: &Instance, data: &AppData) -> vk::SampleCountFlags {
+    if data.config.msaa > 1 && data.render_path == RenderPath::Deferred {
+        warn!("MSAA is not supported by the deferred render path, disabling it.");
+        return vk::SampleCountFlags::_1;
+    }
+
+    let properties = instance.get_physical_device_properties(data.physical_device);
+    let supported = properties.limits.framebuffer_color_sample_counts
+        & properties.limits.framebuffer_depth_sample_counts;
+    let samples = [
+        vk::SampleCountFlags::_64,
+        vk::SampleCountFlags::_32,
+        vk::SampleCountFlags::_16,
+        vk::SampleCountFlags::_8,
+        vk::SampleCountFlags::_4,
+        vk::SampleCountFlags::_2,
+    ]
+    .into_iter()
+    .find(|s| s.bits() <= data.config.msaa && supported.contains(*s))
+    .unwrap_or(vk::SampleCountFlags::_1);
+
+    if samples.bits() != data.config.msaa {
+        warn!("{}x MSAA is not supported, using {}x.", data.config.msaa, samples.bits());
+    }
+    samples
 }
 
 
@@ -76,6 +120,8 @@ pub unsafe fn create_logical_device(
     let mut unique_indices = HashSet::new();
     unique_indices.insert(indices.graphics);
     unique_indices.insert(indices.present);
+    unique_indices.extend(indices.transfer);
+    unique_indices.extend(indices.compute);
 
     let queue_priorities = &[1.0];
     let queue_infos = unique_indices
@@ -88,7 +134,7 @@ pub unsafe fn create_logical_device(
         .collect::<Vec<_>>();
 
 
-    let layers = if VALIDATION_ENABLED {
+    let layers = if data.config.validation {
         vec![VALIDATION_LAYER.as_ptr()]
     } else {
         vec![]
@@ -105,22 +151,89 @@ pub unsafe fn create_logical_device(
         extensions.push(vk::KHR_PORTABILITY_SUBSET_EXTENSION.name.as_ptr());
     }
 
+    // Wireframe debug rendering needs non-solid fill modes, which are optional.
+    let supported_features = instance.get_physical_device_features(data.physical_device);
+    data.fill_mode_non_solid = supported_features.fill_mode_non_solid == vk::TRUE;
+    if !data.fill_mode_non_solid {
+        warn!("Device does not support `fill_mode_non_solid`, wireframe view is disabled.");
+    }
+
     let features = vk::PhysicalDeviceFeatures::builder()
-        .sampler_anisotropy(true);
+        .sampler_anisotropy(true)
+        .fill_mode_non_solid(data.fill_mode_non_solid);
+
+    data.timeline_semaphores = data.config.timeline_semaphores && supports_timeline_semaphores(instance, data.physical_device);
+    if data.config.timeline_semaphores && !data.timeline_semaphores {
+        warn!("Device does not support timeline semaphores, using fences.");
+    }
+    let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::builder()
+        .timeline_semaphore(true);
+
+    // Dynamic rendering can't read input attachments, which the deferred
+    // lighting subpass relies on.
+    data.dynamic_rendering = data.config.dynamic_rendering && supports_dynamic_rendering(instance, data.physical_device);
+    if data.config.dynamic_rendering && !data.dynamic_rendering {
+        warn!("Device does not support dynamic rendering, using render passes.");
+    } else if data.dynamic_rendering && data.render_path == RenderPath::Deferred {
+        warn!("Dynamic rendering is not supported by the deferred render path, using render passes.");
+        data.dynamic_rendering = false;
+    }
+    let mut vulkan_13_features = vk::PhysicalDeviceVulkan13Features::builder()
+        .dynamic_rendering(true)
+        .synchronization2(true);
 
 
-    let info = vk::DeviceCreateInfo::builder()
+    let mut info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_infos)
         .enabled_layer_names(&layers)
         .enabled_extension_names(&extensions)
         .enabled_features(&features);
+    if data.timeline_semaphores {
+        info = info.push_next(&mut vulkan_12_features);
+    }
+    if data.dynamic_rendering {
+        info = info.push_next(&mut vulkan_13_features);
+    }
 
     let device = instance.create_device(data.physical_device, &info, None)?;
     data.graphics_queue = device.get_device_queue(indices.graphics, 0);
     data.present_queue = device.get_device_queue(indices.present, 0);
+    if let Some(transfer) = indices.transfer {
+        data.transfer_queue = device.get_device_queue(transfer, 0);
+    }
+    if let Some(compute) = indices.compute {
+        data.compute_queue = device.get_device_queue(compute, 0);
+    }
 
 
     Ok(device)
 
 
-}
\ No newline at end of file
+}
+
+/// Whether `physical_device` is a Vulkan 1.2 device with timeline semaphores.
+unsafe fn supports_timeline_semaphores(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let properties = instance.get_physical_device_properties(physical_device);
+    if Version::from(properties.api_version) < Version::new(1, 2, 0) {
+        return false;
+    }
+
+    let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::builder();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut vulkan_12_features);
+    instance.get_physical_device_features2(physical_device, &mut features);
+    vulkan_12_features.timeline_semaphore == vk::TRUE
+}
+
+/// Whether `physical_device` is a Vulkan 1.3 device with dynamic rendering
+/// and `synchronization2`.
+unsafe fn supports_dynamic_rendering(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let properties = instance.get_physical_device_properties(physical_device);
+    if Version::from(properties.api_version) < Version::new(1, 3, 0) {
+        return false;
+    }
+
+    let mut vulkan_13_features = vk::PhysicalDeviceVulkan13Features::builder();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut vulkan_13_features);
+    instance.get_physical_device_features2(physical_device, &mut features);
+    vulkan_13_features.dynamic_rendering == vk::TRUE && vulkan_13_features.synchronization2 == vk::TRUE
+}
@@ -0,0 +1,309 @@
+use std::path::PathBuf;
+use anyhow::Result;
+use bytemuck::Pod;
+use log::warn;
+use vulkanalia::vk::DeviceV1_0;
+use winit::dpi::LogicalSize;
+use winit::event::{Event, WindowEvent};
+use winit::event_loop::EventLoop;
+use winit::window::WindowBuilder;
+use crate::camera::{Camera, Projection};
+use crate::compute::{Compute, ComputePassDesc, StorageDesc, StorageFormat};
+use crate::config::EngineConfig;
+use crate::debug_draw::DebugDraw;
+use crate::deferred_util::RenderPath;
+use crate::game_loop::{FrameTime, GameLoop};
+use crate::input::{ActionMap, Input};
+use crate::lights::{DirectionalLight, PointLight};
+use crate::pipeline_util::{ShaderConstants, SpecializationConstants};
+use crate::render_app::App;
+use crate::scene::{Mesh, SceneObject};
+use crate::sky_util::SkyMode;
+use crate::upload_util::Uploads;
+
+/// Vertex and fragment shaders replacing the built in forward shaders,
+/// either compiled `.spv` files or sources, which need the `runtime-shaders`
+/// feature. They must use the vertex layout, descriptor set and push
+/// constants of `shaders/shader.vert`.
+#[derive(Clone, Debug)]
+pub struct ShaderPaths {
+    pub vert: PathBuf,
+    pub frag: PathBuf,
+}
+
+/// Everything needed to start the engine. Build it, then call `run`.
+#[derive(Clone, Debug, Default)]
+pub struct EngineBuilder {
+    pub(crate) config: EngineConfig,
+    pub(crate) camera: Camera,
+    pub(crate) actions: Option<ActionMap>,
+    pub(crate) models: Vec<PathBuf>,
+    pub(crate) texture: Option<PathBuf>,
+    pub(crate) forward_shaders: Option<ShaderPaths>,
+    pub(crate) storage: Vec<(String, StorageDesc)>,
+    pub(crate) compute_passes: Vec<(String, ComputePassDesc)>,
+    pub(crate) object_constants: ShaderConstants,
+}
+
+impl EngineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the whole config, e.g. one read with `EngineConfig::load`.
+    pub fn config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.config = self.config.title(title);
+        self
+    }
+
+    /// Initial window size in logical pixels.
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.config = self.config.size(width, height);
+        self
+    }
+
+    pub fn render_path(mut self, render_path: RenderPath) -> Self {
+        self.config.render_path = render_path;
+        self
+    }
+
+    pub fn sky(mut self, sky_mode: SkyMode) -> Self {
+        self.config.sky = sky_mode;
+        self
+    }
+
+    pub fn clear_color(mut self, clear_color: [f32; 4]) -> Self {
+        self.config.clear_color = clear_color;
+        self
+    }
+
+    pub fn camera(mut self, camera: Camera) -> Self {
+        self.camera = camera;
+        self
+    }
+
+    /// Input bindings. Defaults to `resources/input.toml` in the asset
+    /// root, or the built in bindings when that can't be read.
+    pub fn actions(mut self, actions: ActionMap) -> Self {
+        self.actions = Some(actions);
+        self
+    }
+
+    /// Adds an OBJ model to load. The meshes are handed to `Game::init` in
+    /// the order they were added.
+    pub fn model(mut self, path: impl Into<PathBuf>) -> Self {
+        self.models.push(path.into());
+        self
+    }
+
+    /// The texture shared by all objects, `resources/viking_room.png` in
+    /// the asset root by default.
+    pub fn texture(mut self, path: impl Into<PathBuf>) -> Self {
+        self.texture = Some(path.into());
+        self
+    }
+
+    /// Replaces the shaders of the forward path, see `ShaderPaths`.
+    pub fn forward_shaders(mut self, vert: impl Into<PathBuf>, frag: impl Into<PathBuf>) -> Self {
+        self.forward_shaders = Some(ShaderPaths { vert: vert.into(), frag: frag.into() });
+        self
+    }
+
+    /// Adds a zeroed storage buffer of `size` bytes for compute passes.
+    pub fn storage_buffer(mut self, name: impl Into<String>, size: u64) -> Self {
+        self.storage.push((name.into(), StorageDesc::Buffer { size, contents: Vec::new() }));
+        self
+    }
+
+    /// Adds a storage buffer holding the bytes of `items`, e.g. the initial
+    /// particles.
+    pub fn storage_buffer_with<T: Pod>(mut self, name: impl Into<String>, items: &[T]) -> Self {
+        let contents = bytemuck::cast_slice(items).to_vec();
+        self.storage.push((name.into(), StorageDesc::Buffer { size: contents.len() as u64, contents }));
+        self
+    }
+
+    /// Adds a storage image for compute passes.
+    pub fn storage_image(mut self, name: impl Into<String>, width: u32, height: u32, format: StorageFormat) -> Self {
+        self.storage.push((name.into(), StorageDesc::Image { width, height, format }));
+        self
+    }
+
+    /// Adds a compute pass, run with `Compute::dispatch`. `shader` is a
+    /// `.spv` file or, with the `runtime-shaders` feature, a `.comp` source.
+    /// `bindings` name the storage resources at bindings 0, 1, ... of
+    /// descriptor set 0.
+    /// `push_constant_size` is at most 128 bytes.
+    pub fn compute_pass(
+        self,
+        name: impl Into<String>,
+        shader: impl Into<PathBuf>,
+        bindings: &[&str],
+        push_constant_size: u32,
+    ) -> Self {
+        self.compute_pass_specialized(name, shader, bindings, push_constant_size, SpecializationConstants::new())
+    }
+
+    /// Like `compute_pass`, with values for the shader's specialization
+    /// constants, e.g. a kernel size.
+    pub fn compute_pass_specialized(
+        mut self,
+        name: impl Into<String>,
+        shader: impl Into<PathBuf>,
+        bindings: &[&str],
+        push_constant_size: u32,
+        constants: SpecializationConstants,
+    ) -> Self {
+        let desc = ComputePassDesc {
+            shader: shader.into(),
+            bindings: bindings.iter().map(|b| b.to_string()).collect(),
+            push_constant_size,
+            constants,
+        };
+        self.compute_passes.push((name.into(), desc));
+        self
+    }
+
+    /// Specialization constants of the forward object shaders at startup,
+    /// the deferred path draws only transparent objects with them. The game
+    /// can change them later through `Context::object_constants`. Constant 0
+    /// of the built in fragment shader turns texturing off when `false`.
+    pub fn object_constants(mut self, constants: ShaderConstants) -> Self {
+        self.object_constants = constants;
+        self
+    }
+
+    /// Applies the command line options to the config, see `EngineConfig::args`.
+    pub fn args(mut self, args: impl IntoIterator<Item = String>) -> Result<Self> {
+        self.config = self.config.args(args)?;
+        Ok(self)
+    }
+
+    /// Opens the window and runs `game` until the window is closed.
+    pub fn run<G: Game + 'static>(mut self, mut game: G) -> Result<()> {
+        self.config.validate()?;
+        if self.models.is_empty() {
+            self.models.push(self.config.asset("resources/viking_room.obj"));
+        }
+        if self.actions.is_none() {
+            let actions = ActionMap::load(&self.config.asset("resources/input.toml")).unwrap_or_else(|e| {
+                warn!("Could not load the input bindings, using the defaults: {}", e);
+                ActionMap::with_defaults()
+            });
+            self.actions = Some(actions);
+        }
+        if self.config.reverse_z {
+            self.camera.reverse_z = true;
+        }
+        if self.config.orthographic {
+            self.camera.projection = Projection::Orthographic { height: 4.0 };
+        }
+
+        // Window
+        let event_loop = EventLoop::new()?;
+        let window = WindowBuilder::new()
+            .with_title(&self.config.window.title)
+            .with_inner_size(LogicalSize::new(self.config.window.width, self.config.window.height))
+            .build(&event_loop)?;
+
+        // App
+        let mut app = unsafe { App::create(&window, &self)? };
+        app.init(&mut game)?;
+
+        #[cfg(feature = "gamepad")]
+        let mut gilrs = gilrs::Gilrs::new()
+            .map_err(|e| warn!("Gamepad support is unavailable: {}", e))
+            .ok();
+
+        let mut minimized = false; //window minimized
+        event_loop.run(move |event, elwt| {
+            match event {
+                // Request a redraw when all events were processed.
+                Event::AboutToWait => {
+                    #[cfg(feature = "gamepad")]
+                    if let Some(gilrs) = gilrs.as_mut() {
+                        app.input.poll_gamepad(gilrs);
+                    }
+                    window.request_redraw();
+                }
+                Event::DeviceEvent { event, .. } => app.handle_device_event(&event),
+                Event::WindowEvent { event, .. } => {
+                    app.handle_window_event(&event);
+                    match event {
+                        // Render a frame if our Vulkan app is not being destroyed.
+                        WindowEvent::RedrawRequested if !elwt.exiting() && !minimized => {
+                            unsafe { app.render(&window, &mut game) }.unwrap()
+                        }
+                        WindowEvent::Resized(size) => {
+                            if size.width == 0 || size.height == 0 {
+                                minimized = true;
+                            } else {
+                                minimized = false;
+                                app.resized = true;
+                            }
+                        }
+                        // Destroy our Vulkan app.
+                        WindowEvent::CloseRequested => {
+                            elwt.exit();
+                            unsafe { app.device.device_wait_idle().unwrap(); }
+                            unsafe { app.destroy(); }
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        })?;
+        Ok(())
+    }
+}
+
+/// What the engine exposes to game code. Changes to the camera, scene and
+/// lights are picked up by the next rendered frame.
+pub struct Context<'a> {
+    pub input: &'a Input,
+    pub camera: &'a mut Camera,
+    /// The objects drawn every frame.
+    pub objects: &'a mut Vec<SceneObject>,
+    /// The models added with `EngineBuilder::model`, in order.
+    pub meshes: &'a [Mesh],
+    pub sun: &'a mut DirectionalLight,
+    /// Only lit by the deferred path.
+    pub point_lights: &'a mut Vec<PointLight>,
+    pub debug_draw: &'a mut DebugDraw,
+    /// Queues the compute passes added with `EngineBuilder::compute_pass`.
+    pub compute: &'a mut Compute,
+    /// Streams in models and textures after startup without blocking rendering.
+    pub uploads: &'a mut Uploads,
+    /// Specialization constants of the forward object shaders, the G-buffer
+    /// shaders of the deferred path aren't specialized. Changing them
+    /// switches pipelines before the next frame, creating them on first use.
+    pub object_constants: &'a mut ShaderConstants,
+    pub game_loop: &'a mut GameLoop,
+}
+
+/// Game code driven by the engine. All methods have empty defaults.
+pub trait Game {
+    /// Called once after the assets are loaded, before the first frame.
+    fn init(&mut self, ctx: &mut Context) -> Result<()> {
+        let _ = ctx;
+        Ok(())
+    }
+
+    /// Called `GameLoop::fixed_dt` apart in game time, zero or more times
+    /// per frame. Put simulation here.
+    fn fixed_update(&mut self, ctx: &mut Context, dt: f32) {
+        let _ = (ctx, dt);
+    }
+
+    /// Called once per rendered frame after the fixed updates, with the
+    /// interpolation alpha between the last two ticks.
+    fn update(&mut self, ctx: &mut Context, frame: FrameTime) {
+        let _ = (ctx, frame);
+    }
+}
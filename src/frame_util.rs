@@ -0,0 +1,90 @@
+use std::mem::size_of_val;
+use std::ptr::copy_nonoverlapping as memcpy;
+use anyhow::Result;
+use vulkanalia::{vk, Device};
+use vulkanalia::vk::{DeviceV1_0, HasBuilder};
+use crate::memory_util::{Allocator, ResourceKind, Strategy};
+use crate::render_app::AppData;
+use crate::resource_util::{Buffer, DeletionQueue, Memory};
+
+/// Offset alignment of arena data, enough for uniform buffers on any device.
+const ARENA_ALIGNMENT: u64 = 256;
+
+/// Smallest arena buffer, in bytes.
+const MIN_ARENA_SIZE: u64 = 1024 * 1024;
+
+/// Host visible memory for data that only lives for one frame, e.g. debug
+/// vertices. Allocations are bumped out of one persistently mapped buffer
+/// and all released by `reset`, once the frame's fence was waited on.
+#[derive(Debug, Default)]
+pub struct FrameArena {
+    buffer: Buffer,
+    memory: Memory,
+    capacity: u64,
+    head: u64,
+    deletion_queue: DeletionQueue,
+    allocator: Allocator,
+}
+
+/// Creates one empty arena per frame in flight. Buffers are allocated on
+/// first use.
+pub fn create_frame_arenas(data: &mut AppData) {
+    data.frame_arenas = (0..data.config.frames_in_flight)
+        .map(|_| FrameArena {
+            deletion_queue: data.deletion_queue.clone(),
+            allocator: data.allocator.clone(),
+            ..Default::default()
+        })
+        .collect();
+}
+
+impl FrameArena {
+    /// Releases everything allocated. The GPU must be done with the frame.
+    pub fn reset(&mut self) {
+        self.head = 0;
+    }
+
+    /// Copies `items` into the arena, returns the buffer and offset to read
+    /// them from this frame. A full arena moves to a larger buffer, the old
+    /// one lives until the frame has finished.
+    pub unsafe fn push<T: Copy>(&mut self, device: &Device, items: &[T]) -> Result<(vk::Buffer, vk::DeviceSize)> {
+        let size = size_of_val(items) as u64;
+        let mut offset = self.head.next_multiple_of(ARENA_ALIGNMENT);
+        if offset + size > self.capacity {
+            self.grow(device, (self.capacity * 2).max(size.next_power_of_two()).max(MIN_ARENA_SIZE))?;
+            offset = 0;
+        }
+
+        let memory = self.memory.mapped_ptr()?.cast::<u8>().add(offset as usize);
+        memcpy(items.as_ptr(), memory.cast(), items.len());
+        self.head = offset + size;
+        Ok((*self.buffer, offset))
+    }
+
+    unsafe fn grow(&mut self, device: &Device, capacity: u64) -> Result<()> {
+        let info = vk::BufferCreateInfo::builder()
+            .size(capacity)
+            .usage(vk::BufferUsageFlags::VERTEX_BUFFER
+                | vk::BufferUsageFlags::INDEX_BUFFER
+                | vk::BufferUsageFlags::UNIFORM_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = self.deletion_queue.own(device.create_buffer(&info, None)?);
+
+        let requirements = device.get_buffer_memory_requirements(*buffer);
+        let memory = self.deletion_queue.own(self.allocator.allocate(
+            device,
+            requirements,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+            ResourceKind::Buffer,
+            Strategy::FreeList,
+        )?);
+        device.bind_buffer_memory(*buffer, memory.memory, memory.offset)?;
+
+        self.buffer = buffer;
+        self.memory = memory;
+        self.capacity = capacity;
+        self.head = 0;
+        Ok(())
+    }
+}
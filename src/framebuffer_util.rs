@@ -2,34 +2,81 @@ use vulkanalia::{vk, Device, Instance};
 use vulkanalia::vk::{DeviceV1_0, HasBuilder, InstanceV1_0};
 use crate::render_app::AppData;
 use anyhow::{anyhow, Result};
-use crate::image_util::{create_image, create_image_view};
+use crate::image_util::{create_image, create_image_view, transition_layouts, LayoutTransition};
+use crate::deferred_util::RenderPath;
 
 pub unsafe fn create_framebuffers(device: &Device, data: &mut AppData) -> anyhow::Result<()> {
     data.framebuffers = data
         .swapchain_image_views
         .iter()
         .map(|i| {
-            let attachments = &[*i, data.depth_image_view];
+            let attachments = match data.render_path {
+                RenderPath::Forward if data.msaa_samples != vk::SampleCountFlags::_1 => {
+                    vec![*data.color_image_view, *data.depth_image_view, **i]
+                }
+                RenderPath::Forward => vec![**i, *data.depth_image_view],
+                RenderPath::Deferred => vec![
+                    **i,
+                    *data.depth_image_view,
+                    *data.deferred.albedo.view,
+                    *data.deferred.normal.view,
+                    *data.deferred.material.view,
+                ],
+            };
             let create_info = vk::FramebufferCreateInfo::builder()
-                .render_pass(data.render_pass)
-                .attachments(attachments)
+                .render_pass(*data.render_pass)
+                .attachments(&attachments)
                 .width(data.swapchain_extent.width)
                 .height(data.swapchain_extent.height)
                 .layers(1);
 
 
-            device.create_framebuffer(&create_info, None)
+            Ok(data.deletion_queue.own(device.create_framebuffer(&create_info, None)?))
         })
-        .collect::<anyhow::Result<Vec<_>, _>>()?;
+        .collect::<Result<Vec<_>>>()?;
     Ok(())
 }
 
 
+/// Creates the multisampled color target of the forward path. Nothing to
+/// do without MSAA, the scene is then drawn straight into the swapchain.
+pub unsafe fn create_color_objects(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    if data.msaa_samples == vk::SampleCountFlags::_1 {
+        return Ok(());
+    }
+
+    let (color_image, color_image_memory) = create_image(
+        instance,
+        device,
+        data,
+        data.swapchain_extent.width,
+        data.swapchain_extent.height,
+        1,
+        data.msaa_samples,
+        data.swapchain_format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+
+    data.color_image = color_image;
+    data.color_image_memory = color_image_memory;
+    data.color_image_view = create_image_view(device, data, *data.color_image, data.swapchain_format, vk::ImageAspectFlags::COLOR, 1)?;
+
+    Ok(())
+}
+
 pub unsafe fn create_depth_objects(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
     // Image + Image Memory
 
     let format = get_depth_format(instance, data)?;
 
+    // The deferred lighting pass reads depth back to reconstruct positions.
+    let usage = match data.render_path {
+        RenderPath::Forward => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        RenderPath::Deferred => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::INPUT_ATTACHMENT,
+    };
+
     let (depth_image, depth_image_memory) = create_image(
         instance,
         device,
@@ -37,9 +84,10 @@ pub unsafe fn create_depth_objects(instance: &Instance, device: &Device, data: &
         data.swapchain_extent.width,
         data.swapchain_extent.height,
         1,
+        data.msaa_samples,
         format,
         vk::ImageTiling::OPTIMAL,
-        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        usage,
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
 
     )?;
@@ -49,7 +97,15 @@ pub unsafe fn create_depth_objects(instance: &Instance, device: &Device, data: &
 
     // Image View
 
-    data.depth_image_view = create_image_view(device, data.depth_image, format, vk::ImageAspectFlags::DEPTH,1)?;
+    data.depth_image_view = create_image_view(device, data, *data.depth_image, format, vk::ImageAspectFlags::DEPTH,1)?;
+
+    // Submitted with the next uploads, before the first frame uses it.
+    let command_buffer = data.uploader.graphics_command_buffer(device)?;
+    transition_layouts(device, command_buffer, &[LayoutTransition::new(
+        *data.depth_image,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    ).format(format)])?;
 
     Ok(())
 }
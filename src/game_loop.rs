@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Frames kept for the statistics, about two seconds at 60 fps.
+const STATS_WINDOW: usize = 120;
+/// Longest real frame time simulated at once, so a stall (a breakpoint, a
+/// window drag) doesn't trigger a burst of catch up ticks.
+const MAX_FRAME_TIME: f32 = 0.25;
+
+/// Rolling frame time statistics over the last `STATS_WINDOW` frames.
+#[derive(Clone, Debug)]
+pub struct FrameStats {
+    frame_times: VecDeque<f32>,
+    last_report: Instant,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self { frame_times: VecDeque::with_capacity(STATS_WINDOW), last_report: Instant::now() }
+    }
+}
+
+impl FrameStats {
+    fn push(&mut self, frame_time: f32) {
+        if self.frame_times.len() == STATS_WINDOW {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+    }
+
+    /// Average frame time in seconds.
+    pub fn average(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32
+    }
+
+    pub fn min(&self) -> f32 {
+        self.frame_times.iter().copied().fold(f32::INFINITY, f32::min)
+    }
+
+    pub fn max(&self) -> f32 {
+        self.frame_times.iter().copied().fold(0.0, f32::max)
+    }
+
+    pub fn fps(&self) -> f32 {
+        let average = self.average();
+        if average > 0.0 { 1.0 / average } else { 0.0 }
+    }
+
+    /// Whether `interval` has passed since this last returned true, for
+    /// showing the statistics without flooding the output.
+    pub fn report_due(&mut self, interval: Duration) -> bool {
+        if self.last_report.elapsed() < interval {
+            return false;
+        }
+        self.last_report = Instant::now();
+        true
+    }
+}
+
+impl std::fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.0} fps, {:.2} ms (min {:.2}, max {:.2})",
+            self.fps(),
+            self.average() * 1000.0,
+            self.min() * 1000.0,
+            self.max() * 1000.0,
+        )
+    }
+}
+
+/// What to do in the current frame.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FrameTime {
+    /// Fixed updates to run before rendering.
+    pub ticks: u32,
+    /// Real seconds since the previous frame, for things that follow wall
+    /// clock time even when paused, such as the camera.
+    pub real_dt: f32,
+    /// How far rendering is between the previous and the latest tick, from
+    /// 0 to 1. Used to interpolate the simulation state.
+    pub alpha: f32,
+}
+
+/// A fixed timestep clock: the simulation advances in ticks of `fixed_dt`
+/// seconds of game time while rendering runs at whatever rate it can.
+#[derive(Clone, Debug)]
+pub struct GameLoop {
+    /// Seconds of game time per tick.
+    pub fixed_dt: f32,
+    /// Game seconds per real second.
+    pub time_scale: f32,
+    pub stats: FrameStats,
+    paused: bool,
+    step_requested: bool,
+    accumulator: f32,
+    last_frame: Instant,
+    tick: u64,
+}
+
+impl GameLoop {
+    pub fn new(tick_rate: f32) -> Self {
+        Self {
+            fixed_dt: 1.0 / tick_rate,
+            time_scale: 1.0,
+            stats: FrameStats::default(),
+            paused: false,
+            step_requested: false,
+            accumulator: 0.0,
+            last_frame: Instant::now(),
+            tick: 0,
+        }
+    }
+
+    /// Measures the time since the previous call and returns how many ticks
+    /// to run this frame. Call once per rendered frame.
+    pub fn advance(&mut self) -> FrameTime {
+        let now = Instant::now();
+        let real_dt = (now - self.last_frame).as_secs_f32();
+        self.last_frame = now;
+        self.stats.push(real_dt);
+
+        if self.paused {
+            let ticks = std::mem::take(&mut self.step_requested) as u32;
+            self.tick += ticks as u64;
+            return FrameTime { ticks, real_dt, alpha: self.accumulator / self.fixed_dt };
+        }
+
+        self.accumulator += real_dt.min(MAX_FRAME_TIME) * self.time_scale;
+        let ticks = (self.accumulator / self.fixed_dt) as u32;
+        self.accumulator -= ticks as f32 * self.fixed_dt;
+        self.tick += ticks as u64;
+
+        FrameTime { ticks, real_dt, alpha: self.accumulator / self.fixed_dt }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.step_requested = false;
+    }
+
+    /// Runs exactly one tick on the next frame while paused.
+    pub fn step(&mut self) {
+        if self.paused {
+            self.step_requested = true;
+        }
+    }
+
+    /// Ticks run so far.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Game time simulated so far, in seconds.
+    pub fn time(&self) -> f32 {
+        self.tick as f32 * self.fixed_dt
+    }
+}
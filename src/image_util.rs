@@ -1,55 +1,67 @@
 
 use std::fs::File;
-use std::intrinsics::copy_nonoverlapping as memcpy;
 
 use std::path;
 use vulkanalia::{vk, Device, Instance};
 use crate::render_app::AppData;
+use crate::resource_util::{Image, ImageView, Memory};
 use anyhow::{anyhow, Result};
 use vulkanalia::vk::{DeviceV1_0, HasBuilder, InstanceV1_0};
-use crate::buffer_util::{begin_single_time_commands, create_buffer, end_single_time_commands, get_memory_type_index};
+use crate::memory_util::{ResourceKind, Strategy};
 pub unsafe fn create_texture_image(
     instance: &Instance,
     device: &Device,
     data: &mut AppData,
     image_path : path::PathBuf,
 ) -> Result<()> {
-    let image =  match File::open(image_path){
-        Ok(f) => f,
-        Err(e) => return Err(anyhow!(e)),
-    };
+    let texture = read_texture(&image_path)?;
+    let (texture_image, texture_image_memory) = upload_texture(instance, device, data, &texture)?;
+    data.texture_image = texture_image;
+    data.texture_image_memory = texture_image_memory;
+    data.mip_levels = texture.mip_levels();
+    Ok(())
+}
+
+/// The decoded pixels of a texture, 8 bit RGBA.
+#[derive(Clone, Debug)]
+pub struct TextureData {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl TextureData {
+    /// Levels of the full mip chain.
+    pub fn mip_levels(&self) -> u32 {
+        (self.width.max(self.height) as f32).log2().floor() as u32 + 1
+    }
+}
+
+/// Reads and decodes the PNG at `path`. Does no Vulkan work, so it can run
+/// on any thread.
+pub fn read_texture(path: &path::Path) -> Result<TextureData> {
+    let image = File::open(path).map_err(|e| anyhow!("Failed to open texture `{}`: {}", path.display(), e))?;
 
     let decoder = png::Decoder::new(image);
     let mut reader = decoder.read_info()?;
 
-
     let mut pixels = vec![0;  reader.info().raw_bytes()];
     reader.next_frame(&mut pixels)?;
 
-    let size = reader.info().raw_bytes() as u64;
     let (width, height) = reader.info().size();
+    Ok(TextureData { width, height, pixels })
+}
 
-
-    data.mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
-        instance,
-        device,
-        data,
-        size,
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
-    )?;
-
-    let memory = device.map_memory(
-        staging_buffer_memory,
-        0,
-        size,
-        vk::MemoryMapFlags::empty(),
-    )?;
-
-    memcpy(pixels.as_ptr(), memory.cast(), pixels.len());
-
-    device.unmap_memory(staging_buffer_memory);
+/// Creates a sampled image for `texture` and records its upload and mip
+/// chain into the current batch of the uploader.
+pub unsafe fn upload_texture(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    texture: &TextureData,
+) -> Result<(Image, Memory)> {
+    let TextureData { width, height, ref pixels } = *texture;
+    let mip_levels = texture.mip_levels();
 
     let (texture_image, texture_image_memory) = create_image(
         instance,
@@ -57,7 +69,8 @@ pub unsafe fn create_texture_image(
         data,
         width,
         height,
-        data.mip_levels,
+        mip_levels,
+        vk::SampleCountFlags::_1,
         vk::Format::R8G8B8A8_SRGB,
         vk::ImageTiling::OPTIMAL,
         vk::ImageUsageFlags::SAMPLED
@@ -66,66 +79,67 @@ pub unsafe fn create_texture_image(
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
-    data.texture_image = texture_image;
-    data.texture_image_memory = texture_image_memory;
+    let (staging_buffer, staging_offset) = data.uploader.stage(device, pixels)?;
+    let command_buffer = data.uploader.command_buffer(device)?;
 
-    transition_image_layout(
-        device,
-        data,
-        data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
+    transition_layouts(device, command_buffer, &[LayoutTransition::new(
+        *texture_image,
         vk::ImageLayout::UNDEFINED,
         vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        data.mip_levels,
-    )?;
+    )])?;
 
     copy_buffer_to_image(
         device,
-        data,
+        command_buffer,
         staging_buffer,
-        data.texture_image,
+        staging_offset,
+        *texture_image,
         width,
         height,
-    )?;
+    );
 
-    transition_image_layout(
-        device,
-        data,
-        data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
-        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-        data.mip_levels
-    )?;
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
+    // The mip chain is blitted on the graphics queue.
+    let range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(mip_levels)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build();
+    data.uploader.transfer_image(device, *texture_image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, range)?;
+    let command_buffer = data.uploader.graphics_command_buffer(device)?;
 
     generate_mipmaps(
         instance,
         device,
         data,
-        data.texture_image,
+        command_buffer,
+        *texture_image,
         vk::Format::R8G8B8A8_SRGB,
         width,
         height,
-        data.mip_levels,
+        mip_levels,
     )?;
-    Ok(())
+    Ok((texture_image, texture_image_memory))
 }
 
 
+/// Takes the parts of `vk::ImageCreateInfo` that vary between the images
+/// of the engine.
+#[allow(clippy::too_many_arguments)]
 pub unsafe fn create_image(
-    instance: &Instance,
+    _instance: &Instance,
     device: &Device,
     data: &AppData,
     width: u32,
     height: u32,
     mip_levels: u32,
+    samples: vk::SampleCountFlags,
     format: vk::Format,
     tiling: vk::ImageTiling,
     usage: vk::ImageUsageFlags,
     properties: vk::MemoryPropertyFlags,
-) -> Result<(vk::Image, vk::DeviceMemory)> {
+) -> Result<(Image, Memory)> {
     let info = vk::ImageCreateInfo::builder()
         .image_type(vk::ImageType::_2D)
         .extent(vk::Extent3D {
@@ -139,85 +153,165 @@ pub unsafe fn create_image(
         .tiling(tiling)
         .initial_layout(vk::ImageLayout::UNDEFINED)
         .usage(usage)
-        .samples(vk::SampleCountFlags::_1)
+        .samples(samples)
         .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
-    let image = device.create_image(&info, None)?;
-
-    let requirements = device.get_image_memory_requirements(image);
+    let image = data.deletion_queue.own(device.create_image(&info, None)?);
 
-    let info = vk::MemoryAllocateInfo::builder()
-        .allocation_size(requirements.size)
-        .memory_type_index(get_memory_type_index(
-            instance,
-            data,
-            properties,
-            requirements,
-        )?);
+    let requirements = device.get_image_memory_requirements(*image);
 
-    let image_memory = device.allocate_memory(&info, None)?;
+    let kind = if tiling == vk::ImageTiling::LINEAR { ResourceKind::Buffer } else { ResourceKind::Image };
+    let image_memory = data.deletion_queue.own(data.allocator.allocate(
+        device,
+        requirements,
+        properties,
+        kind,
+        Strategy::FreeList,
+    )?);
 
-    device.bind_image_memory(image, image_memory, 0)?;
+    device.bind_image_memory(*image, image_memory.memory, image_memory.offset)?;
 
     Ok((image, image_memory))
 }
 
-pub unsafe fn transition_image_layout(
-    device: &Device,
-    data: &AppData,
-    image: vk::Image,
-    format: vk::Format,
-    old_layout: vk::ImageLayout,
-    new_layout: vk::ImageLayout,
-    mip_levels: u32,
-) -> Result<()> {
+/// A layout transition of a range of mip levels and array layers of an
+/// image. Record them with `transition_layouts`.
+#[derive(Copy, Clone, Debug)]
+pub struct LayoutTransition {
+    pub image: vk::Image,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+    pub range: vk::ImageSubresourceRange,
+}
 
-    let (
-        src_access_mask,
-        dst_access_mask,
-        src_stage_mask,
-        dst_stage_mask,
-    ) = match (old_layout, new_layout) {
-        (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
-            vk::AccessFlags::empty(),
+impl LayoutTransition {
+    /// Every mip level and array layer of the color image `image`.
+    pub fn new(image: vk::Image, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout) -> Self {
+        let range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(vk::REMAINING_MIP_LEVELS)
+            .base_array_layer(0)
+            .layer_count(vk::REMAINING_ARRAY_LAYERS)
+            .build();
+        Self { image, old_layout, new_layout, range }
+    }
+
+    /// Uses the aspects of `format`, depth and stencil for combined formats.
+    pub fn format(mut self, format: vk::Format) -> Self {
+        self.range.aspect_mask = format_aspect(format);
+        self
+    }
+
+    pub fn mips(mut self, base: u32, count: u32) -> Self {
+        self.range.base_mip_level = base;
+        self.range.level_count = count;
+        self
+    }
+
+    pub fn layers(mut self, base: u32, count: u32) -> Self {
+        self.range.base_array_layer = base;
+        self.range.layer_count = count;
+        self
+    }
+}
+
+/// The aspects of an image of `format`.
+pub fn format_aspect(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        vk::Format::D16_UNORM_S8_UINT | vk::Format::D24_UNORM_S8_UINT | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+/// The stages and access of work using an image in `layout`. A transition
+/// waits for them in the old layout and blocks them in the new one.
+fn layout_scope(layout: vk::ImageLayout) -> Result<(vk::PipelineStageFlags, vk::AccessFlags)> {
+    let fragment_tests = vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+    let shaders = vk::PipelineStageFlags::VERTEX_SHADER
+        | vk::PipelineStageFlags::FRAGMENT_SHADER
+        | vk::PipelineStageFlags::COMPUTE_SHADER;
+    Ok(match layout {
+        vk::ImageLayout::UNDEFINED => (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty()),
+        vk::ImageLayout::PREINITIALIZED => (vk::PipelineStageFlags::HOST, vk::AccessFlags::HOST_WRITE),
+        vk::ImageLayout::GENERAL => (
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+        ),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+            fragment_tests,
             vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
-            vk::PipelineStageFlags::TOP_OF_PIPE,
-            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
         ),
-        (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
-            vk::AccessFlags::TRANSFER_WRITE,
-            vk::AccessFlags::SHADER_READ,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL => (
+            fragment_tests | shaders,
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::SHADER_READ,
         ),
-        _ => return Err(anyhow!("Unsupported image layout transition!")),
-    };
-
-    let command_buffer = begin_single_time_commands(device, data)?;
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (shaders, vk::AccessFlags::SHADER_READ),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_READ),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+        // Waits on the acquire semaphore chain with any stage. Presentation
+        // is ordered by the semaphore signaled after the submission.
+        vk::ImageLayout::PRESENT_SRC_KHR => (vk::PipelineStageFlags::ALL_COMMANDS, vk::AccessFlags::empty()),
+        _ => return Err(anyhow!("Unsupported image layout {:?}.", layout)),
+    })
+}
 
-    let aspect_mask = if new_layout == vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL {
-        match format { vk::Format::D32_SFLOAT_S8_UINT | vk::Format::D24_UNORM_S8_UINT =>
-                vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
-            _ => vk::ImageAspectFlags::DEPTH }
+/// Records `transitions` as one pipeline barrier. Each waits for the work
+/// using its old layout and makes its writes visible to the work using the
+/// new one.
+pub unsafe fn transition_layouts(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    transitions: &[LayoutTransition],
+) -> Result<()> {
+    if transitions.is_empty() {
+        return Ok(());
     }
-    else { vk::ImageAspectFlags::COLOR };
 
-    let subresource = vk::ImageSubresourceRange::builder()
-        .aspect_mask(aspect_mask)
-        .base_mip_level(0)
-        .level_count(mip_levels)
-        .base_array_layer(0)
-        .layer_count(1);
-
-    let barrier = vk::ImageMemoryBarrier::builder()
-        .old_layout(old_layout)
-        .new_layout(new_layout)
-        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        .image(image)
-        .subresource_range(subresource)
-        .src_access_mask(src_access_mask)
-        .dst_access_mask(dst_access_mask);
+    let write_access = vk::AccessFlags::HOST_WRITE
+        | vk::AccessFlags::MEMORY_WRITE
+        | vk::AccessFlags::SHADER_WRITE
+        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+        | vk::AccessFlags::TRANSFER_WRITE;
+
+    let mut src_stage_mask = vk::PipelineStageFlags::empty();
+    let mut dst_stage_mask = vk::PipelineStageFlags::empty();
+    let mut barriers = Vec::with_capacity(transitions.len());
+    for transition in transitions {
+        if matches!(transition.new_layout, vk::ImageLayout::UNDEFINED | vk::ImageLayout::PREINITIALIZED) {
+            return Err(anyhow!("Can't transition an image to {:?}.", transition.new_layout));
+        }
+        let (src_stages, src_access) = layout_scope(transition.old_layout)?;
+        let (dst_stages, dst_access) = match transition.new_layout {
+            vk::ImageLayout::PRESENT_SRC_KHR => (vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::empty()),
+            layout => layout_scope(layout)?,
+        };
+        src_stage_mask |= src_stages;
+        dst_stage_mask |= dst_stages;
+
+        // Reads in the old layout only need the execution dependency.
+        barriers.push(vk::ImageMemoryBarrier::builder()
+            .old_layout(transition.old_layout)
+            .new_layout(transition.new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(transition.image)
+            .subresource_range(transition.range)
+            .src_access_mask(src_access & write_access)
+            .dst_access_mask(dst_access)
+            .build());
+    }
 
     device.cmd_pipeline_barrier(
         command_buffer,
@@ -226,24 +320,21 @@ pub unsafe fn transition_image_layout(
         vk::DependencyFlags::empty(),
         &[] as &[vk::MemoryBarrier],
         &[] as &[vk::BufferMemoryBarrier],
-        &[barrier],
+        &barriers,
     );
 
-    end_single_time_commands(device, data, command_buffer)?;
-
     Ok(())
 }
 
 unsafe fn copy_buffer_to_image(
     device: &Device,
-    data: &AppData,
+    command_buffer: vk::CommandBuffer,
     buffer: vk::Buffer,
+    buffer_offset: vk::DeviceSize,
     image: vk::Image,
     width: u32,
     height: u32,
-) -> Result<()> {
-    let command_buffer = begin_single_time_commands(device, data)?;
-
+) {
     let subresource = vk::ImageSubresourceLayers::builder()
         .aspect_mask(vk::ImageAspectFlags::COLOR)
         .mip_level(0)
@@ -251,7 +342,7 @@ unsafe fn copy_buffer_to_image(
         .layer_count(1);
 
     let region = vk::BufferImageCopy::builder()
-        .buffer_offset(0)
+        .buffer_offset(buffer_offset)
         .buffer_row_length(0)
         .buffer_image_height(0)
         .image_subresource(subresource)
@@ -265,16 +356,13 @@ unsafe fn copy_buffer_to_image(
         vk::ImageLayout::TRANSFER_DST_OPTIMAL,
         &[region],
     );
-
-    end_single_time_commands(device, data, command_buffer)?;
-
-    Ok(())
 }
 
 pub unsafe fn create_texture_image_view(device: &Device, data: &mut AppData) -> Result<()> {
     data.texture_image_view = create_image_view(
         device,
-        data.texture_image,
+        data,
+        *data.texture_image,
         vk::Format::R8G8B8A8_SRGB,
         vk::ImageAspectFlags::COLOR,
         data.mip_levels
@@ -286,11 +374,12 @@ pub unsafe fn create_texture_image_view(device: &Device, data: &mut AppData) ->
 
 pub unsafe fn create_image_view(
     device: &Device,
+    data: &AppData,
     image: vk::Image,
     format: vk::Format,
     aspects: vk::ImageAspectFlags,
     mip_levels: u32,
-) -> Result<vk::ImageView> {
+) -> Result<ImageView> {
     let subresource_range = vk::ImageSubresourceRange::builder()
         .aspect_mask(aspects)
         .base_mip_level(0)
@@ -304,7 +393,7 @@ pub unsafe fn create_image_view(
         .format(format)
         .subresource_range(subresource_range);
 
-    Ok(device.create_image_view(&info, None)?)
+    Ok(data.deletion_queue.own(device.create_image_view(&info, None)?))
 }
 
 
@@ -326,7 +415,7 @@ pub unsafe fn create_texture_sampler(device: &Device, data: &mut AppData) -> Res
         .mip_lod_bias(0.0)
         .min_lod(0.0)
         .max_lod(data.mip_levels as f32);
-    data.texture_sampler = device.create_sampler(&info, None)?;
+    data.texture_sampler = data.deletion_queue.own(device.create_sampler(&info, None)?);
 
 
 
@@ -335,10 +424,12 @@ pub unsafe fn create_texture_sampler(device: &Device, data: &mut AppData) -> Res
 }
 
 
+#[allow(clippy::too_many_arguments)]
 unsafe fn generate_mipmaps(
     instance: &Instance,
     device: &Device,
     data: &AppData,
+    command_buffer: vk::CommandBuffer,
     image: vk::Image,
     format: vk::Format,
     width: u32,
@@ -357,39 +448,18 @@ unsafe fn generate_mipmaps(
 
     // Mipmaps
 
-    let command_buffer = begin_single_time_commands(device, data)?;
-
-    let subresource = vk::ImageSubresourceRange::builder()
-        .aspect_mask(vk::ImageAspectFlags::COLOR)
-        .base_array_layer(0)
-        .layer_count(1)
-        .level_count(1);
-
-    let mut barrier = vk::ImageMemoryBarrier::builder()
-        .image(image)
-        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        .subresource_range(subresource);
+    // Each level is read by the blit into the next, then made shader
+    // readable together with the transition of the next level.
+    let mip = |level, old_layout, new_layout| LayoutTransition::new(image, old_layout, new_layout).mips(level, 1);
+    let mut pending = Vec::new();
 
     let mut mip_width = width;
     let mut mip_height = height;
 
     for i in 1..mip_levels {
-        barrier.subresource_range.base_mip_level = i - 1;
-        barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-        barrier.new_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
-        barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-        barrier.dst_access_mask = vk::AccessFlags::TRANSFER_READ;
-
-        device.cmd_pipeline_barrier(
-            command_buffer,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::DependencyFlags::empty(),
-            &[] as &[vk::MemoryBarrier],
-            &[] as &[vk::BufferMemoryBarrier],
-            &[barrier],
-        );
+        pending.push(mip(i - 1, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL));
+        transition_layouts(device, command_buffer, &pending)?;
+        pending.clear();
 
         let src_subresource = vk::ImageSubresourceLayers::builder()
             .aspect_mask(vk::ImageAspectFlags::COLOR)
@@ -433,20 +503,7 @@ unsafe fn generate_mipmaps(
             vk::Filter::LINEAR,
         );
 
-        barrier.old_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
-        barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-        barrier.src_access_mask = vk::AccessFlags::TRANSFER_READ;
-        barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
-
-        device.cmd_pipeline_barrier(
-            command_buffer,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::FRAGMENT_SHADER,
-            vk::DependencyFlags::empty(),
-            &[] as &[vk::MemoryBarrier],
-            &[] as &[vk::BufferMemoryBarrier],
-            &[barrier],
-        );
+        pending.push(mip(i - 1, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL));
 
         if mip_width > 1 {
             mip_width /= 2;
@@ -457,23 +514,8 @@ unsafe fn generate_mipmaps(
         }
     }
 
-    barrier.subresource_range.base_mip_level = mip_levels - 1;
-    barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-    barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-    barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-    barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
-
-    device.cmd_pipeline_barrier(
-        command_buffer,
-        vk::PipelineStageFlags::TRANSFER,
-        vk::PipelineStageFlags::FRAGMENT_SHADER,
-        vk::DependencyFlags::empty(),
-        &[] as &[vk::MemoryBarrier],
-        &[] as &[vk::BufferMemoryBarrier],
-        &[barrier],
-    );
-
-    end_single_time_commands(device, data, command_buffer)?;
+    pending.push(mip(mip_levels - 1, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL));
+    transition_layouts(device, command_buffer, &pending)?;
 
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,401 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::path::Path;
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use serde::de::value::{Error as ValueError, StrDeserializer};
+use serde::Deserialize;
+use winit::event::{DeviceEvent, ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::window::{CursorGrabMode, Window};
+use crate::transforms::Vec2;
+
+/// Pressed, held and released state of a set of buttons. `pressed` and
+/// `released` only hold for the frame the change happened in.
+#[derive(Clone, Debug)]
+pub struct ButtonState<T> {
+    held: HashSet<T>,
+    pressed: HashSet<T>,
+    released: HashSet<T>,
+}
+
+impl<T> Default for ButtonState<T> {
+    fn default() -> Self {
+        Self { held: HashSet::new(), pressed: HashSet::new(), released: HashSet::new() }
+    }
+}
+
+impl<T: Copy + Eq + Hash> ButtonState<T> {
+    fn press(&mut self, button: T) {
+        if self.held.insert(button) {
+            self.pressed.insert(button);
+        }
+    }
+
+    fn release(&mut self, button: T) {
+        if self.held.remove(&button) {
+            self.released.insert(button);
+        }
+    }
+
+    fn release_all(&mut self) {
+        self.released.extend(self.held.drain());
+    }
+
+    fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+    }
+
+    pub fn held(&self, button: T) -> bool {
+        self.held.contains(&button)
+    }
+
+    pub fn pressed(&self, button: T) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    pub fn released(&self, button: T) -> bool {
+        self.released.contains(&button)
+    }
+}
+
+/// A physical input an action can be bound to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    #[cfg(feature = "gamepad")]
+    GamepadButton(gilrs::Button),
+    /// One direction of a gamepad axis, `sign` is 1 or -1.
+    #[cfg(feature = "gamepad")]
+    GamepadAxis(gilrs::Axis, f32),
+}
+
+impl Binding {
+    /// Parses a binding name: a winit `KeyCode` such as `KeyW`, `MouseLeft`,
+    /// `MouseRight` or `MouseMiddle`, a gamepad button such as `PadSouth` or
+    /// a stick direction such as `PadLeftStickY+`. Gamepad bindings parse to
+    /// `None` when the `gamepad` feature is disabled.
+    pub fn parse(name: &str) -> Result<Option<Binding>> {
+        match name {
+            "MouseLeft" => return Ok(Some(Binding::Mouse(MouseButton::Left))),
+            "MouseRight" => return Ok(Some(Binding::Mouse(MouseButton::Right))),
+            "MouseMiddle" => return Ok(Some(Binding::Mouse(MouseButton::Middle))),
+            _ => {}
+        }
+
+        if let Some(pad) = name.strip_prefix("Pad") {
+            return parse_gamepad_binding(pad).map_err(|_| anyhow!("Unknown gamepad binding `{}`.", name));
+        }
+
+        KeyCode::deserialize(StrDeserializer::<ValueError>::new(name))
+            .map(|key| Some(Binding::Key(key)))
+            .map_err(|_| anyhow!("Unknown key `{}`.", name))
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn parse_gamepad_binding(name: &str) -> Result<Option<Binding>> {
+    use gilrs::{Axis, Button};
+
+    let axis = |name: &str| match name {
+        "LeftStickX" => Some(Axis::LeftStickX),
+        "LeftStickY" => Some(Axis::LeftStickY),
+        "RightStickX" => Some(Axis::RightStickX),
+        "RightStickY" => Some(Axis::RightStickY),
+        _ => None,
+    };
+    if let Some(stick) = name.strip_suffix('+') {
+        return axis(stick).map(|a| Some(Binding::GamepadAxis(a, 1.0))).ok_or_else(|| anyhow!("unknown axis"));
+    }
+    if let Some(stick) = name.strip_suffix('-') {
+        return axis(stick).map(|a| Some(Binding::GamepadAxis(a, -1.0))).ok_or_else(|| anyhow!("unknown axis"));
+    }
+
+    let button = match name {
+        "South" => Button::South,
+        "East" => Button::East,
+        "North" => Button::North,
+        "West" => Button::West,
+        "LeftTrigger" => Button::LeftTrigger,
+        "LeftTrigger2" => Button::LeftTrigger2,
+        "RightTrigger" => Button::RightTrigger,
+        "RightTrigger2" => Button::RightTrigger2,
+        "Select" => Button::Select,
+        "Start" => Button::Start,
+        "Mode" => Button::Mode,
+        "LeftThumb" => Button::LeftThumb,
+        "RightThumb" => Button::RightThumb,
+        "DPadUp" => Button::DPadUp,
+        "DPadDown" => Button::DPadDown,
+        "DPadLeft" => Button::DPadLeft,
+        "DPadRight" => Button::DPadRight,
+        _ => return Err(anyhow!("unknown button")),
+    };
+    Ok(Some(Binding::GamepadButton(button)))
+}
+
+#[cfg(not(feature = "gamepad"))]
+fn parse_gamepad_binding(_name: &str) -> Result<Option<Binding>> {
+    Ok(None)
+}
+
+/// The file format of `ActionMap::load`.
+#[derive(Deserialize)]
+struct ActionFile {
+    actions: HashMap<String, Vec<String>>,
+}
+
+/// Named actions such as `"move_forward"`, each bound to any number of
+/// physical inputs.
+#[derive(Clone, Debug, Default)]
+pub struct ActionMap {
+    actions: HashMap<String, Vec<Binding>>,
+}
+
+impl ActionMap {
+    /// Loads bindings from a TOML file with an `[actions]` table, e.g.
+    /// `move_forward = ["KeyW", "ArrowUp"]`. See `Binding::parse` for names.
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)?;
+        Self::from_toml(&source)
+    }
+
+    pub fn from_toml(source: &str) -> Result<Self> {
+        let file: ActionFile = toml::from_str(source)?;
+        let mut map = Self::default();
+        for (action, names) in file.actions {
+            for name in names {
+                match Binding::parse(&name)? {
+                    Some(binding) => map.bind(&action, binding),
+                    None => debug!("Ignoring `{}` for `{}`, gamepad support is disabled.", name, action),
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        self.actions.entry(action.to_string()).or_default().push(binding);
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.actions.get(action).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// The bindings shipped in `resources/input.toml`, built into the binary.
+    pub fn with_defaults() -> Self {
+        Self::from_toml(include_str!("resources/input.toml"))
+            .expect("the built in action file is valid")
+    }
+}
+
+/// Gamepad state, merged over all connected gamepads.
+#[cfg(feature = "gamepad")]
+#[derive(Clone, Debug, Default)]
+pub struct GamepadState {
+    pub buttons: ButtonState<gilrs::Button>,
+    axes: HashMap<gilrs::Axis, f32>,
+}
+
+#[cfg(feature = "gamepad")]
+impl GamepadState {
+    pub fn axis(&self, axis: gilrs::Axis) -> f32 {
+        self.axes.get(&axis).copied().unwrap_or(0.0)
+    }
+}
+
+/// Keyboard, mouse and gamepad state for the current frame, fed by winit
+/// events and queried by game code. Call `end_frame` once per frame after
+/// the state has been read.
+#[derive(Clone, Debug)]
+pub struct Input {
+    pub keys: ButtonState<KeyCode>,
+    pub mouse_buttons: ButtonState<MouseButton>,
+    #[cfg(feature = "gamepad")]
+    pub gamepad: GamepadState,
+    /// Raw mouse movement in pixels since the last frame, also reported
+    /// while the cursor is grabbed.
+    pub mouse_delta: Vec2,
+    /// Scrolled lines since the last frame, positive away from the user.
+    pub wheel: f32,
+    /// Cursor position in physical pixels.
+    pub cursor_position: Vec2,
+    pub actions: ActionMap,
+    cursor_grabbed: bool,
+}
+
+impl Input {
+    pub fn new(actions: ActionMap) -> Self {
+        Self {
+            keys: ButtonState::default(),
+            mouse_buttons: ButtonState::default(),
+            #[cfg(feature = "gamepad")]
+            gamepad: GamepadState::default(),
+            mouse_delta: Vec2::new(0.0, 0.0),
+            wheel: 0.0,
+            cursor_position: Vec2::new(0.0, 0.0),
+            actions,
+            cursor_grabbed: false,
+        }
+    }
+
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                event: KeyEvent { physical_key: PhysicalKey::Code(key), state, .. },
+                ..
+            } => match state {
+                ElementState::Pressed => self.keys.press(*key),
+                ElementState::Released => self.keys.release(*key),
+            },
+            WindowEvent::MouseInput { button, state, .. } => match state {
+                ElementState::Pressed => self.mouse_buttons.press(*button),
+                ElementState::Released => self.mouse_buttons.release(*button),
+            },
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.wheel += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 40.0,
+                };
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = Vec2::new(position.x as f32, position.y as f32);
+            }
+            // Releases arriving while unfocused would be lost.
+            WindowEvent::Focused(false) => {
+                self.keys.release_all();
+                self.mouse_buttons.release_all();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.mouse_delta.x += delta.0 as f32;
+            self.mouse_delta.y += delta.1 as f32;
+        }
+    }
+
+    /// Applies the pending gamepad events.
+    #[cfg(feature = "gamepad")]
+    pub fn poll_gamepad(&mut self, gilrs: &mut gilrs::Gilrs) {
+        use gilrs::EventType;
+
+        while let Some(gilrs::Event { event, .. }) = gilrs.next_event() {
+            match event {
+                EventType::ButtonPressed(button, _) => self.gamepad.buttons.press(button),
+                EventType::ButtonReleased(button, _) => self.gamepad.buttons.release(button),
+                EventType::AxisChanged(axis, value, _) => { self.gamepad.axes.insert(axis, value); }
+                EventType::Disconnected => {
+                    self.gamepad.buttons.release_all();
+                    self.gamepad.axes.clear();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Clears the per-frame state: presses, releases, mouse delta and wheel.
+    pub fn end_frame(&mut self) {
+        self.keys.end_frame();
+        self.mouse_buttons.end_frame();
+        #[cfg(feature = "gamepad")]
+        self.gamepad.buttons.end_frame();
+        self.mouse_delta = Vec2::new(0.0, 0.0);
+        self.wheel = 0.0;
+    }
+
+    /// Hides the cursor and keeps it in the window, or releases it again.
+    pub fn set_cursor_grab(&mut self, window: &Window, grab: bool) {
+        if grab == self.cursor_grabbed {
+            return;
+        }
+        let result = if grab {
+            // Not every platform supports both modes.
+            window.set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(e) = result {
+            warn!("Could not change the cursor grab: {}.", e);
+            return;
+        }
+        window.set_cursor_visible(!grab);
+        self.cursor_grabbed = grab;
+    }
+
+    pub fn cursor_grabbed(&self) -> bool {
+        self.cursor_grabbed
+    }
+
+    fn binding_held(&self, binding: &Binding) -> bool {
+        self.binding_value(binding) > 0.5
+    }
+
+    /// How far a binding is engaged, from 0 to 1.
+    fn binding_value(&self, binding: &Binding) -> f32 {
+        match *binding {
+            Binding::Key(key) => self.keys.held(key) as i32 as f32,
+            Binding::Mouse(button) => self.mouse_buttons.held(button) as i32 as f32,
+            #[cfg(feature = "gamepad")]
+            Binding::GamepadButton(button) => self.gamepad.buttons.held(button) as i32 as f32,
+            #[cfg(feature = "gamepad")]
+            Binding::GamepadAxis(axis, sign) => (self.gamepad.axis(axis) * sign).clamp(0.0, 1.0),
+        }
+    }
+
+    fn binding_pressed(&self, binding: &Binding) -> bool {
+        match *binding {
+            Binding::Key(key) => self.keys.pressed(key),
+            Binding::Mouse(button) => self.mouse_buttons.pressed(button),
+            #[cfg(feature = "gamepad")]
+            Binding::GamepadButton(button) => self.gamepad.buttons.pressed(button),
+            #[cfg(feature = "gamepad")]
+            Binding::GamepadAxis(..) => false,
+        }
+    }
+
+    fn binding_released(&self, binding: &Binding) -> bool {
+        match *binding {
+            Binding::Key(key) => self.keys.released(key),
+            Binding::Mouse(button) => self.mouse_buttons.released(button),
+            #[cfg(feature = "gamepad")]
+            Binding::GamepadButton(button) => self.gamepad.buttons.released(button),
+            #[cfg(feature = "gamepad")]
+            Binding::GamepadAxis(..) => false,
+        }
+    }
+
+    /// Whether any input bound to `action` is held down.
+    pub fn action_held(&self, action: &str) -> bool {
+        self.actions.bindings(action).iter().any(|b| self.binding_held(b))
+    }
+
+    /// Whether `action` was triggered this frame.
+    pub fn action_pressed(&self, action: &str) -> bool {
+        self.actions.bindings(action).iter().any(|b| self.binding_pressed(b))
+    }
+
+    /// Whether `action` was let go this frame.
+    pub fn action_released(&self, action: &str) -> bool {
+        self.actions.bindings(action).iter().any(|b| self.binding_released(b))
+    }
+
+    /// The strongest input bound to `action`, from 0 to 1. Analog for sticks.
+    pub fn action_value(&self, action: &str) -> f32 {
+        self.actions
+            .bindings(action)
+            .iter()
+            .map(|b| self.binding_value(b))
+            .fold(0.0, f32::max)
+    }
+
+    /// `action_value(positive) - action_value(negative)`.
+    pub fn axis(&self, positive: &str, negative: &str) -> f32 {
+        self.action_value(positive) - self.action_value(negative)
+    }
+}
@@ -1,13 +1,13 @@
 use std::collections::HashSet;
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::os::raw::c_void;
 use anyhow::anyhow;
 use log::{debug, error, info, trace, warn};
-use vulkanalia::{vk, Entry, Instance};
+use vulkanalia::{vk, Entry, Instance, Version};
 use vulkanalia::vk::{EntryV1_0, HasBuilder};
 use winit::window::Window;
 use crate::render_app::AppData;
-use crate::{PORTABILITY_MACOS_VERSION, VALIDATION_ENABLED, VALIDATION_LAYER};
+use crate::{PORTABILITY_MACOS_VERSION, VALIDATION_LAYER};
 use vulkanalia::window as vk_window;
 //Debug callback function extern keyword so that vulkan can call our rust function
 extern "system" fn debug_callback(
@@ -38,19 +38,28 @@ pub unsafe fn create_instance(window: &Window,
 
 
 
+    // 1.0 loaders reject any higher version, newer ones accept anything and
+    // each device reports what it supports.
+    let api_version = if entry.version()? >= Version::new(1, 1, 0) {
+        vk::make_version(1, 3, 0)
+    } else {
+        vk::make_version(1, 0, 0)
+    };
+
+    let application_name = CString::new(data.config.app_name())?;
     let application_info = vk::ApplicationInfo::builder()
-        .application_name(b"Vulkan Tutorial\0")
+        .application_name(application_name.as_bytes_with_nul())
         .application_version(vk::make_version(1, 0, 0))
-        .engine_name(b"No Engine\0")
+        .engine_name(b"Elingine\0")
         .engine_version(vk::make_version(1, 0, 0))
-        .api_version(vk::make_version(1, 0, 0));
+        .api_version(api_version);
 
     let mut extensions = vk_window::get_required_instance_extensions(window)
         .iter()
         .map(|e| e.as_ptr())
         .collect::<Vec<_>>();
 
-    if VALIDATION_ENABLED {
+    if data.config.validation {
         extensions.push(vk::EXT_DEBUG_UTILS_EXTENSION.name.as_ptr());
     }
 
@@ -75,11 +84,11 @@ pub unsafe fn create_instance(window: &Window,
         .map(|l| l.layer_name)
         .collect::<HashSet<_>>();
 
-    if VALIDATION_ENABLED && !available_layers.contains(&VALIDATION_LAYER) {
+    if data.config.validation && !available_layers.contains(&VALIDATION_LAYER) {
         return Err(anyhow!("Validation layer requested but not supported."));
     }
 
-    let layers = if VALIDATION_ENABLED {
+    let layers = if data.config.validation {
         vec![VALIDATION_LAYER.as_ptr()]
     } else {
         Vec::new()
@@ -103,7 +112,7 @@ pub unsafe fn create_instance(window: &Window,
         )
         .user_callback(Some(debug_callback));
 
-    if VALIDATION_ENABLED {
+    if data.config.validation {
         info = info.push_next(&mut debug_info);
     }
 
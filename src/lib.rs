@@ -0,0 +1,73 @@
+//! A small Vulkan engine. Describe the window and assets with an
+//! `EngineBuilder`, implement `Game` and hand it to `EngineBuilder::run`.
+//! Startup settings live in an `EngineConfig`, which can also be read from
+//! a TOML file and overridden on the command line.
+
+mod swapchain_util;
+mod device_util;
+mod render_app;
+mod pipeline_util;
+mod instance_util;
+mod queue_family_indices;
+mod command_buffer_util;
+mod render_pass_util;
+mod sync_util;
+mod command_pool;
+mod framebuffer_util;
+mod shader_module_util;
+mod vertexbuffer_util;
+mod buffer_util;
+mod descriptor_util;
+mod image_util;
+mod deferred_util;
+mod sky_util;
+mod debug_view;
+mod resource_util;
+mod memory_util;
+mod upload_util;
+mod compute;
+mod frame_util;
+mod rendering_util;
+mod render_graph;
+mod shader_compiler;
+mod spirv_reflect;
+
+pub mod transforms;
+pub mod lights;
+pub mod scene;
+pub mod debug_draw;
+pub mod camera;
+pub mod input;
+pub mod game_loop;
+pub mod engine;
+pub mod config;
+
+use vulkanalia::prelude::v1_0::*;
+use vulkanalia::Version;
+
+use crate::queue_family_indices::QueueFamilyIndices;
+use crate::render_app::AppData;
+
+pub use crate::camera::{Camera, CameraController, Projection};
+pub use crate::compute::{Compute, StorageFormat};
+pub use crate::config::{EngineConfig, PresentMode, WindowConfig};
+pub use crate::debug_draw::{DebugDraw, DebugStyle};
+pub use crate::debug_view::DebugView;
+pub use crate::deferred_util::RenderPath;
+pub use crate::engine::{Context, EngineBuilder, Game, ShaderPaths};
+pub use crate::game_loop::{FrameStats, FrameTime, GameLoop};
+pub use crate::input::{ActionMap, Binding, Input};
+pub use crate::lights::{DirectionalLight, PointLight};
+pub use crate::pipeline_util::{ShaderConstants, SpecializationConstants, SpecializationValue};
+pub use crate::render_graph::{BufferAccess, BufferId, ImageAccess, ImageDesc, ImageId, Import, PassBuilder, PassContext, RecordFn, RenderGraph};
+pub use crate::scene::{AlphaMode, Material, Mesh, SceneObject};
+pub use crate::sky_util::SkyMode;
+pub use crate::upload_util::{UploadTicket, Uploads};
+pub use crate::transforms::{Mat4, Vec2, Vec3};
+pub use crate::lights::Vec4;
+pub use bytemuck::{Pod, Zeroable};
+
+const PORTABILITY_MACOS_VERSION: Version = Version::new(1, 3, 216);
+const VALIDATION_LAYER: vk::ExtensionName =
+    vk::ExtensionName::from_bytes(b"VK_LAYER_KHRONOS_validation");
+const DEVICE_EXTENSIONS: &[vk::ExtensionName] = &[vk::KHR_SWAPCHAIN_EXTENSION.name];
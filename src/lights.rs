@@ -0,0 +1,90 @@
+use cgmath::{vec3, vec4, InnerSpace};
+use crate::transforms::{Mat4, Vec3};
+
+pub type Vec4 = cgmath::Vector4<f32>;
+
+/// Upper bound on the point lights uploaded to the lighting pass.
+pub const MAX_POINT_LIGHTS: usize = 64;
+
+/// A point light. `position.w` holds the radius of the light volume,
+/// `color.w` the intensity.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PointLight {
+    pub position: Vec4,
+    pub color: Vec4,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, radius: f32, color: Vec3, intensity: f32) -> Self {
+        Self {
+            position: position.extend(radius),
+            color: color.extend(intensity),
+        }
+    }
+}
+
+/// A directional light such as the sun. `direction` points from the light
+/// towards the scene, `color.w` holds the intensity.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct DirectionalLight {
+    pub direction: Vec4,
+    pub color: Vec4,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self {
+            direction: direction.normalize().extend(0.0),
+            color: color.extend(intensity),
+        }
+    }
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self::new(vec3(-0.4, -0.3, -1.0), vec3(1.0, 0.96, 0.9), 1.0)
+    }
+}
+
+/// Uniform block read by the deferred lighting shaders (std140 layout).
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct LightUniform {
+    pub view: Mat4,
+    pub proj: Mat4,
+    pub inv_view_proj: Mat4,
+    pub camera_position: Vec4,
+    /// xy: size of the render area in pixels, zw: its reciprocal.
+    pub viewport: Vec4,
+    /// x: depth of the far plane, 0 with reverse-Z and 1 otherwise.
+    pub depth_range: Vec4,
+    pub ambient: Vec4,
+    pub sun: DirectionalLight,
+    /// x: number of valid entries in `point_lights`.
+    pub point_light_count: [u32; 4],
+    pub point_lights: [PointLight; MAX_POINT_LIGHTS],
+}
+
+/// A handful of coloured lights placed around the origin.
+pub fn default_point_lights() -> Vec<PointLight> {
+    let colors = [
+        vec3(1.0, 0.3, 0.2),
+        vec3(0.2, 1.0, 0.3),
+        vec3(0.3, 0.4, 1.0),
+        vec3(1.0, 0.8, 0.3),
+    ];
+    colors
+        .iter()
+        .enumerate()
+        .map(|(i, color)| {
+            let angle = i as f32 / colors.len() as f32 * std::f32::consts::TAU;
+            PointLight::new(vec3(angle.cos(), angle.sin(), 0.5), 1.5, *color, 2.0)
+        })
+        .collect()
+}
+
+pub fn ambient_light() -> Vec4 {
+    vec4(0.05, 0.05, 0.06, 1.0)
+}
@@ -0,0 +1,498 @@
+use std::ffi::c_void;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use vulkanalia::{vk, Device, Instance};
+use vulkanalia::vk::{DeviceV1_0, Handle, HasBuilder, InstanceV1_0};
+
+/// Size of the device memory blocks sub-allocated from. Heaps smaller than
+/// 1 GiB use an eighth of the heap instead.
+const BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How allocations are placed inside a block.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Best fit from a free list, freed ranges merge with their neighbours.
+    /// For long lived resources.
+    #[default]
+    FreeList,
+    /// Bump allocation, the block is reused once everything in it is freed.
+    /// For short lived staging buffers.
+    Linear,
+}
+
+/// What the memory is bound to. When the device has a
+/// `bufferImageGranularity` buffers and optimally tiled images get separate
+/// blocks, so neighbouring allocations never alias a granularity page.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+    Buffer,
+    Image,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct MappedPtr(*mut u8);
+
+// The pointer is only written through while the allocation is owned.
+unsafe impl Send for MappedPtr {}
+unsafe impl Sync for MappedPtr {}
+
+/// A range of device memory handed out by `Allocator`, owned through
+/// `resource_util::Memory`. Bind resources at `offset`.
+#[derive(Copy, Clone, Debug)]
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    memory_type: u32,
+    /// Index into `State::blocks`, `None` for dedicated allocations.
+    block: Option<usize>,
+    mapped: Option<MappedPtr>,
+}
+
+impl Allocation {
+    pub fn null() -> Self {
+        Self {
+            memory: vk::DeviceMemory::null(),
+            offset: 0,
+            size: 0,
+            memory_type: 0,
+            block: None,
+            mapped: None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.memory.is_null()
+    }
+
+    /// Start of the allocation in host memory. Host visible memory stays
+    /// mapped for its whole lifetime, so there is nothing to unmap.
+    pub fn mapped_ptr(&self) -> Result<*mut c_void> {
+        self.mapped
+            .map(|p| p.0.cast())
+            .ok_or_else(|| anyhow!("Memory is not host visible."))
+    }
+}
+
+#[derive(Debug)]
+struct Block {
+    memory: vk::DeviceMemory,
+    size: u64,
+    memory_type: u32,
+    kind: ResourceKind,
+    strategy: Strategy,
+    mapped: Option<MappedPtr>,
+    /// Free ranges as `(offset, size)` sorted by offset, for `Strategy::FreeList`.
+    free: Vec<(u64, u64)>,
+    /// Next free offset, for `Strategy::Linear`.
+    head: u64,
+    allocations: usize,
+    used: u64,
+}
+
+impl Block {
+    fn pool(&self) -> (u32, ResourceKind, Strategy) {
+        (self.memory_type, self.kind, self.strategy)
+    }
+
+    fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        let offset = match self.strategy {
+            Strategy::Linear => {
+                let offset = align_up(self.head, alignment);
+                if offset + size > self.size {
+                    return None;
+                }
+                self.head = offset + size;
+                offset
+            }
+            Strategy::FreeList => {
+                let (index, offset) = self.free
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, &(start, len))| {
+                        let offset = align_up(start, alignment);
+                        (offset + size <= start + len).then_some((i, offset, len))
+                    })
+                    .min_by_key(|&(_, _, len)| len)
+                    .map(|(i, offset, _)| (i, offset))?;
+                let (start, len) = self.free.remove(index);
+                let end = start + len;
+                if offset + size < end {
+                    self.free.insert(index, (offset + size, end - offset - size));
+                }
+                if start < offset {
+                    self.free.insert(index, (start, offset - start));
+                }
+                offset
+            }
+        };
+        self.allocations += 1;
+        self.used += size;
+        Some(offset)
+    }
+
+    fn free(&mut self, offset: u64, size: u64) {
+        self.allocations -= 1;
+        self.used -= size;
+        match self.strategy {
+            Strategy::Linear => {
+                if self.allocations == 0 {
+                    self.head = 0;
+                }
+            }
+            Strategy::FreeList => {
+                let index = self.free.partition_point(|&(start, _)| start < offset);
+                self.free.insert(index, (offset, size));
+                if index + 1 < self.free.len() && offset + size == self.free[index + 1].0 {
+                    self.free[index].1 += self.free.remove(index + 1).1;
+                }
+                if index > 0 && self.free[index - 1].0 + self.free[index - 1].1 == offset {
+                    let (_, len) = self.free.remove(index);
+                    self.free[index - 1].1 += len;
+                }
+            }
+        }
+    }
+
+    fn allocation(&self, index: usize, offset: u64, size: u64) -> Allocation {
+        Allocation {
+            memory: self.memory,
+            offset,
+            size,
+            memory_type: self.memory_type,
+            block: Some(index),
+            mapped: self.mapped.map(|p| MappedPtr(unsafe { p.0.add(offset as usize) })),
+        }
+    }
+}
+
+/// Device memory in use, see `Allocator::stats`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllocatorStats {
+    /// Live allocations, dedicated ones included.
+    pub allocations: usize,
+    pub blocks: usize,
+    pub dedicated: usize,
+    /// Bytes allocated from the driver.
+    pub reserved: u64,
+    /// Bytes handed out to resources.
+    pub used: u64,
+}
+
+impl fmt::Display for AllocatorStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const MIB: f64 = 1024.0 * 1024.0;
+        write!(
+            f,
+            "{} allocations in {} blocks and {} dedicated, {:.1} of {:.1} MiB used",
+            self.allocations,
+            self.blocks,
+            self.dedicated,
+            self.used as f64 / MIB,
+            self.reserved as f64 / MIB,
+        )
+    }
+}
+
+#[derive(Default)]
+struct State {
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    buffer_image_granularity: u64,
+    non_coherent_atom_size: u64,
+    max_allocations: u32,
+    /// Freed blocks leave a `None` so block indices stay valid.
+    blocks: Vec<Option<Block>>,
+    dedicated: Vec<Allocation>,
+}
+
+impl State {
+    fn memory_type_index(&self, properties: vk::MemoryPropertyFlags, requirements: vk::MemoryRequirements) -> Result<u32> {
+        (0..self.memory_properties.memory_type_count)
+            .find(|i| {
+                let suitable = (requirements.memory_type_bits & (1 << i)) != 0;
+                let memory_type = self.memory_properties.memory_types[*i as usize];
+                suitable && memory_type.property_flags.contains(properties)
+            })
+            .ok_or_else(|| anyhow!("Failed to find suitable memory type."))
+    }
+
+    fn block_size(&self, memory_type: u32) -> u64 {
+        let heap = self.memory_properties.memory_types[memory_type as usize].heap_index;
+        let heap_size = self.memory_properties.memory_heaps[heap as usize].size;
+        if heap_size < 1024 * 1024 * 1024 { heap_size / 8 } else { BLOCK_SIZE }
+    }
+
+    /// Allocates and, if host visible, maps a whole `vk::DeviceMemory`.
+    unsafe fn allocate_memory(&self, device: &Device, memory_type: u32, size: u64) -> Result<(vk::DeviceMemory, Option<MappedPtr>)> {
+        let count = self.blocks.iter().flatten().count() + self.dedicated.len();
+        if count >= self.max_allocations as usize {
+            return Err(anyhow!("Reached the device limit of {} memory allocations.", self.max_allocations));
+        }
+
+        let info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type);
+        let memory = device.allocate_memory(&info, None)?;
+
+        let flags = self.memory_properties.memory_types[memory_type as usize].property_flags;
+        let mapped = if flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            match device.map_memory(memory, 0, vk::WHOLE_SIZE as u64, vk::MemoryMapFlags::empty()) {
+                Ok(ptr) => Some(MappedPtr(ptr.cast())),
+                Err(e) => {
+                    device.free_memory(memory, None);
+                    return Err(e.into());
+                }
+            }
+        } else {
+            None
+        };
+        Ok((memory, mapped))
+    }
+
+    fn stats(&self) -> AllocatorStats {
+        let mut stats = AllocatorStats::default();
+        for block in self.blocks.iter().flatten() {
+            stats.allocations += block.allocations;
+            stats.blocks += 1;
+            stats.reserved += block.size;
+            stats.used += block.used;
+        }
+        for allocation in &self.dedicated {
+            stats.allocations += 1;
+            stats.dedicated += 1;
+            stats.reserved += allocation.size;
+            stats.used += allocation.size;
+        }
+        stats
+    }
+}
+
+/// Sub-allocates device memory from large blocks, one set of blocks per
+/// memory type, resource kind and `Strategy`. Large images and anything
+/// bigger than a block get a dedicated allocation. Cheap to clone, clones
+/// share the blocks.
+#[derive(Clone, Default)]
+pub struct Allocator(Arc<Mutex<State>>);
+
+impl Allocator {
+    pub unsafe fn new(instance: &Instance, physical_device: vk::PhysicalDevice) -> Self {
+        let limits = instance.get_physical_device_properties(physical_device).limits;
+        let state = State {
+            memory_properties: instance.get_physical_device_memory_properties(physical_device),
+            buffer_image_granularity: limits.buffer_image_granularity,
+            non_coherent_atom_size: limits.non_coherent_atom_size,
+            max_allocations: limits.max_memory_allocation_count,
+            ..Default::default()
+        };
+        Self(Arc::new(Mutex::new(state)))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn memory_type_index(&self, properties: vk::MemoryPropertyFlags, requirements: vk::MemoryRequirements) -> Result<u32> {
+        self.lock().memory_type_index(properties, requirements)
+    }
+
+    /// Finds room for `requirements` in the first memory type with
+    /// `properties`, creating a block when none has space.
+    pub unsafe fn allocate(
+        &self,
+        device: &Device,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+        kind: ResourceKind,
+        strategy: Strategy,
+    ) -> Result<Allocation> {
+        let mut state = self.lock();
+        let memory_type = state.memory_type_index(properties, requirements)?;
+        let size = requirements.size;
+        let block_size = state.block_size(memory_type);
+
+        if size > block_size || (kind == ResourceKind::Image && size >= block_size / 2) {
+            let (memory, mapped) = state.allocate_memory(device, memory_type, size)?;
+            let allocation = Allocation { memory, offset: 0, size, memory_type, block: None, mapped };
+            state.dedicated.push(allocation);
+            return Ok(allocation);
+        }
+
+        let flags = state.memory_properties.memory_types[memory_type as usize].property_flags;
+        let mut alignment = requirements.alignment.max(1);
+        if flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) && !flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT) {
+            alignment = alignment.max(state.non_coherent_atom_size);
+        }
+        let kind = if state.buffer_image_granularity > 1 { kind } else { ResourceKind::Buffer };
+        let pool = (memory_type, kind, strategy);
+
+        for (index, block) in state.blocks.iter_mut().enumerate() {
+            let Some(block) = block.as_mut().filter(|b| b.pool() == pool) else {
+                continue;
+            };
+            if let Some(offset) = block.allocate(size, alignment) {
+                return Ok(block.allocation(index, offset, size));
+            }
+        }
+
+        let (memory, mapped) = state.allocate_memory(device, memory_type, block_size)?;
+        let mut block = Block {
+            memory,
+            size: block_size,
+            memory_type,
+            kind,
+            strategy,
+            mapped,
+            free: vec![(0, block_size)],
+            head: 0,
+            allocations: 0,
+            used: 0,
+        };
+        let offset = block.allocate(size, alignment).expect("allocation fits an empty block");
+        let index = match state.blocks.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                state.blocks.push(None);
+                state.blocks.len() - 1
+            }
+        };
+        let allocation = block.allocation(index, offset, size);
+        state.blocks[index] = Some(block);
+        Ok(allocation)
+    }
+
+    /// Returns `allocation` to its block. One empty block per pool is kept
+    /// for reuse, further empty blocks are freed.
+    pub unsafe fn free(&self, device: &Device, allocation: Allocation) {
+        let mut state = self.lock();
+        let Some(index) = allocation.block else {
+            state.dedicated.retain(|a| a.memory != allocation.memory);
+            device.free_memory(allocation.memory, None);
+            return;
+        };
+
+        let Some(block) = state.blocks[index].as_mut() else {
+            error!("Freed {:?} from a block that no longer exists.", allocation);
+            return;
+        };
+        block.free(allocation.offset, allocation.size);
+        if block.allocations > 0 {
+            return;
+        }
+        let pool = block.pool();
+        let empty = state.blocks.iter().flatten().filter(|b| b.pool() == pool && b.allocations == 0).count();
+        if empty > 1 {
+            if let Some(block) = state.blocks[index].take() {
+                device.free_memory(block.memory, None);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        self.lock().stats()
+    }
+
+    /// Frees every block and reports the allocations that were never
+    /// returned. The device must be idle.
+    pub unsafe fn destroy(&self, device: &Device) {
+        let mut state = self.lock();
+        let stats = state.stats();
+        info!("Device memory at shutdown: {}.", stats);
+        if stats.allocations > 0 {
+            error!("{} device memory allocations ({} bytes) were never freed:", stats.allocations, stats.used);
+            for block in state.blocks.iter().flatten().filter(|b| b.allocations > 0) {
+                error!(
+                    "  {} allocations ({} bytes) in a {:?} {:?} block of memory type {}",
+                    block.allocations, block.used, block.kind, block.strategy, block.memory_type);
+            }
+            for allocation in &state.dedicated {
+                error!("  dedicated allocation ({} bytes) of memory type {}", allocation.size, allocation.memory_type);
+            }
+        }
+
+        for block in state.blocks.drain(..).flatten() {
+            device.free_memory(block.memory, None);
+        }
+        for allocation in state.dedicated.drain(..) {
+            device.free_memory(allocation.memory, None);
+        }
+    }
+}
+
+impl fmt::Debug for Allocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Allocator").field(&self.stats()).finish()
+    }
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(size: u64, strategy: Strategy) -> Block {
+        Block {
+            memory: vk::DeviceMemory::null(),
+            size,
+            memory_type: 0,
+            kind: ResourceKind::Buffer,
+            strategy,
+            mapped: None,
+            free: vec![(0, size)],
+            head: 0,
+            allocations: 0,
+            used: 0,
+        }
+    }
+
+    #[test]
+    fn alignment_padding_stays_free() {
+        let mut block = block(1024, Strategy::FreeList);
+        assert_eq!(block.allocate(10, 1), Some(0));
+        assert_eq!(block.allocate(16, 256), Some(256));
+        assert_eq!(block.free, vec![(10, 246), (272, 752)]);
+        // The padding is reused by allocations that fit into it.
+        assert_eq!(block.allocate(200, 8), Some(16));
+    }
+
+    #[test]
+    fn best_fit_takes_the_smallest_range() {
+        let mut block = block(1024, Strategy::FreeList);
+        block.free = vec![(0, 100), (200, 50), (300, 724)];
+        assert_eq!(block.allocate(40, 1), Some(200));
+        assert_eq!(block.allocate(60, 1), Some(0));
+        assert_eq!(block.allocate(101, 1), Some(300));
+        assert_eq!(block.allocate(1000, 1), None);
+    }
+
+    #[test]
+    fn free_merges_with_both_neighbours() {
+        let mut block = block(1024, Strategy::FreeList);
+        let a = block.allocate(100, 1).unwrap();
+        let b = block.allocate(100, 1).unwrap();
+        let c = block.allocate(100, 1).unwrap();
+        block.free(a, 100);
+        block.free(c, 100);
+        assert_eq!(block.free, vec![(0, 100), (200, 824)]);
+        block.free(b, 100);
+        assert_eq!(block.free, vec![(0, 1024)]);
+        assert_eq!((block.allocations, block.used), (0, 0));
+    }
+
+    #[test]
+    fn linear_resets_once_empty() {
+        let mut block = block(256, Strategy::Linear);
+        let a = block.allocate(100, 1).unwrap();
+        let b = block.allocate(100, 64).unwrap();
+        assert_eq!((a, b), (0, 128));
+        assert_eq!(block.allocate(100, 1), None);
+        block.free(a, 100);
+        assert_eq!(block.allocate(50, 1), None, "space is only reused once the block is empty");
+        block.free(b, 100);
+        assert_eq!(block.allocate(200, 1), Some(0));
+    }
+}
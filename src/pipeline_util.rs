@@ -1,35 +1,592 @@
-use vulkanalia::{vk, Device};
-use vulkanalia::vk::{DeviceV1_0, Handle, HasBuilder};
+use std::hash::{Hash, Hasher};
+use std::io::ErrorKind;
+use std::mem::size_of;
+use anyhow::anyhow;
+use log::{info, warn};
+use vulkanalia::{vk, Device, Instance};
+use vulkanalia::vk::{DeviceV1_0, HasBuilder, InstanceV1_0};
 use crate::render_app::AppData;
-use crate::shader_module_util::create_shader_module;
+use crate::shader_compiler::ShaderUser;
+use crate::shader_module_util::{create_shader_module, load_forward_shaders, load_shader, load_shader_file};
+use crate::spirv_reflect::{reflect, vertex_attributes, ConstantType, ShaderInterface};
 use crate::vertexbuffer_util::Vertex;
+use crate::deferred_util::RenderPath;
+use crate::sky_util::SkyMode;
+use crate::scene::ObjectPushConstants;
+use crate::debug_view::DebugView;
+use crate::debug_draw::DebugVertex;
+use crate::resource_util::Pipeline;
 
+/// How a pipeline writes into its color attachments.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    Opaque,
+    Alpha,
+    Additive,
+}
+
+/// The vertex layout a pipeline consumes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VertexInput {
+    /// Vertices are generated from `gl_VertexIndex`.
+    None,
+    Mesh,
+    Debug,
+}
+
+/// The value of a `layout(constant_id = ..) const` in a shader.
+#[derive(Copy, Clone, Debug)]
+pub enum SpecializationValue {
+    Bool(bool),
+    Int(i32),
+    Uint(u32),
+    Float(f32),
+}
+
+impl SpecializationValue {
+    fn constant_type(self) -> ConstantType {
+        match self {
+            Self::Bool(_) => ConstantType::Bool,
+            Self::Int(_) => ConstantType::Int,
+            Self::Uint(_) => ConstantType::Uint,
+            Self::Float(_) => ConstantType::Float,
+        }
+    }
+
+    /// The 4 bytes the shader reads, booleans are a `VkBool32`.
+    fn bits(self) -> u32 {
+        match self {
+            Self::Bool(value) => value as u32,
+            Self::Int(value) => value as u32,
+            Self::Uint(value) => value,
+            Self::Float(value) => value.to_bits(),
+        }
+    }
+}
+
+// Floats compare by their bits so values can key the pipeline variants.
+impl PartialEq for SpecializationValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.constant_type() == other.constant_type() && self.bits() == other.bits()
+    }
+}
+
+impl Eq for SpecializationValue {}
+
+impl Hash for SpecializationValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.constant_type().hash(state);
+        self.bits().hash(state);
+    }
+}
+
+impl From<bool> for SpecializationValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<i32> for SpecializationValue {
+    fn from(value: i32) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<u32> for SpecializationValue {
+    fn from(value: u32) -> Self {
+        Self::Uint(value)
+    }
+}
+
+impl From<f32> for SpecializationValue {
+    fn from(value: f32) -> Self {
+        Self::Float(value)
+    }
+}
+
+/// Specialization constants of one shader stage by constant ID. Constants
+/// the shader doesn't declare keep the default from its source.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SpecializationConstants {
+    /// Sorted by ID.
+    values: Vec<(u32, SpecializationValue)>,
+}
+
+impl SpecializationConstants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets constant `id`, replacing an earlier value.
+    pub fn set(mut self, id: u32, value: impl Into<SpecializationValue>) -> Self {
+        let value = value.into();
+        match self.values.binary_search_by_key(&id, |(id, _)| *id) {
+            Ok(index) => self.values[index].1 = value,
+            Err(index) => self.values.insert(index, (id, value)),
+        }
+        self
+    }
+
+    pub fn get(&self, id: u32) -> Option<SpecializationValue> {
+        self.values
+            .binary_search_by_key(&id, |(id, _)| *id)
+            .ok()
+            .map(|index| self.values[index].1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The map entries and data of a `vk::SpecializationInfo`.
+    fn map(&self) -> (Vec<vk::SpecializationMapEntry>, Vec<u8>) {
+        let mut entries = Vec::with_capacity(self.values.len());
+        let mut bytes = Vec::with_capacity(self.values.len() * 4);
+        for (id, value) in &self.values {
+            entries.push(vk::SpecializationMapEntry::builder()
+                .constant_id(*id)
+                .offset(bytes.len() as u32)
+                .size(4)
+                .build());
+            bytes.extend_from_slice(&value.bits().to_ne_bytes());
+        }
+        (entries, bytes)
+    }
+
+    /// Fails when a constant the shader declares is given a value of another type.
+    fn check(&self, interface: &ShaderInterface) -> anyhow::Result<()> {
+        for (id, value) in &self.values {
+            match interface.specialization.get(id) {
+                Some(&expected) if expected != value.constant_type() => {
+                    return Err(anyhow!(
+                        "Specialization constant {} is a {:?} in the shader, but was given {:?}.",
+                        id, expected, value,
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Specialization constants of the vertex and fragment stage of a graphics pipeline.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ShaderConstants {
+    pub vertex: SpecializationConstants,
+    pub fragment: SpecializationConstants,
+}
+
+/// The fixed function state and shaders that differ between the
+/// graphics pipelines of the engine. Everything else is shared.
+#[derive(Clone, Debug)]
+pub struct GraphicsPipelineDesc<'a> {
+    pub vert: &'a [u8],
+    pub frag: &'a [u8],
+    pub vertex_input: VertexInput,
+    pub topology: vk::PrimitiveTopology,
+    pub polygon_mode: vk::PolygonMode,
+    pub cull_mode: vk::CullModeFlags,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    /// For a conventional depth range, flipped when the camera uses reverse-Z.
+    pub depth_compare_op: vk::CompareOp,
+    pub blend: BlendMode,
+    /// Only takes effect when the render pass is multisampled.
+    pub alpha_to_coverage: bool,
+    pub color_attachment_count: usize,
+    /// Null with dynamic rendering, the pipeline then uses `AppData::rendering_formats`.
+    pub render_pass: vk::RenderPass,
+    pub subpass: u32,
+    pub constants: ShaderConstants,
+}
+
+impl<'a> GraphicsPipelineDesc<'a> {
+    /// The state used by the forward pipeline: indexed triangles with
+    /// depth test and write and a single opaque color attachment.
+    pub fn new(vert: &'a [u8], frag: &'a [u8], render_pass: vk::RenderPass) -> Self {
+        Self {
+            vert,
+            frag,
+            vertex_input: VertexInput::Mesh,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::empty(),
+            depth_test: true,
+            depth_write: true,
+            depth_compare_op: vk::CompareOp::LESS,
+            blend: BlendMode::Opaque,
+            alpha_to_coverage: false,
+            color_attachment_count: 1,
+            render_pass,
+            subpass: 0,
+            constants: ShaderConstants::default(),
+        }
+    }
+}
+
+/// Pipeline layout shared by every pipeline drawing `SceneObject`s: the
+/// global descriptor set plus `ObjectPushConstants` for each draw, in the
+/// stages `AppData::object_interface` uses them.
+unsafe fn create_object_pipeline_layout(device: &Device, data: &mut AppData) -> anyhow::Result<()> {
+    let mut push_constant_ranges = Vec::new();
+    if let Some(range) = data.object_interface.push_constants {
+        let size = size_of::<ObjectPushConstants>() as u32;
+        if range.size > size {
+            return Err(anyhow!(
+                "The object shaders use {} bytes of push constants, `ObjectPushConstants` has {}.",
+                range.size,
+                size,
+            ));
+        }
+        push_constant_ranges.push(vk::PushConstantRange { size, ..range });
+    }
+
+    let set_layouts = &[*data.descriptor_set_layout];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(&push_constant_ranges);
+    data.pipeline_layout = data.deletion_queue.own(device.create_pipeline_layout(&layout_info, None)?);
+    Ok(())
+}
+
+/// The pipelines drawing scene objects, created for one `ShaderConstants`.
+#[derive(Debug)]
+pub struct ObjectPipelines {
+    pub opaque: Pipeline,
+    pub cutout: Pipeline,
+    pub transparent: Pipeline,
+}
+
+/// Creates the opaque, cutout and transparent pipelines of the forward path,
+/// with the game's shaders if it replaced the built in ones.
 pub unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> anyhow::Result<()> {
-    let vert = include_bytes!("shaders/vert.spv");
-    let frag = include_bytes!("shaders/frag.spv");
+    create_object_pipeline_layout(device, data)?;
+    let pipelines = create_object_pipelines(device, data)?;
+    set_object_pipelines(data, pipelines);
+    Ok(())
+}
+
+/// Creates the object pipelines of the current render path. Constant IDs are
+/// per shader, so `AppData::object_constants` only specializes the forward
+/// shaders, which the deferred path still draws transparent objects with.
+unsafe fn create_object_pipelines(device: &Device, data: &mut AppData) -> anyhow::Result<ObjectPipelines> {
+    let constants = data.object_constants.clone();
+    if data.render_path == RenderPath::Deferred {
+        let vert = load_shader(data, "gbuffer.vert", &[], ShaderUser::Scene)?;
+        let frag = load_shader(data, "gbuffer.frag", &[], ShaderUser::Scene)?;
+        check_object_layout(data, &vert, &frag, "The G-buffer shaders")?;
+        let mut desc = GraphicsPipelineDesc::new(&vert, &frag, *data.render_pass);
+        desc.color_attachment_count = 3;
+        let opaque = create_graphics_pipeline(device, data, &desc, *data.pipeline_layout)?;
+
+        desc.alpha_to_coverage = true;
+        let cutout = create_graphics_pipeline(device, data, &desc, *data.pipeline_layout)?;
+
+        // Transparent objects can't be stored in the G-buffer, they are forward
+        // shaded on top of the lit result.
+        let (vert, frag) = load_forward_shaders(data)?;
+        let transparent = create_transparent_pipeline(device, data, &vert, &frag, 1, &constants)?;
+        return Ok(ObjectPipelines { opaque, cutout, transparent });
+    }
+
+    // The built in fragment shader only alpha tests in its cutout variant.
+    let (vert, frag, cutout_frag) = match data.forward_shaders.clone() {
+        Some(shaders) => {
+            let vert = load_shader_file(data, &shaders.vert, ShaderUser::Scene)?;
+            let frag = load_shader_file(data, &shaders.frag, ShaderUser::Scene)?;
+            (vert, frag.clone(), frag)
+        }
+        None => {
+            let (vert, frag) = load_forward_shaders(data)?;
+            let cutout_frag = load_shader(data, "shader.frag", &[("ALPHA_TEST", "1")], ShaderUser::Scene)?;
+            (vert, frag, cutout_frag)
+        }
+    };
+    check_object_layout(data, &vert, &frag, "The forward shaders")?;
+
+    let mut desc = GraphicsPipelineDesc::new(&vert, &frag, *data.render_pass);
+    desc.constants = constants.clone();
+    let opaque = create_graphics_pipeline(device, data, &desc, *data.pipeline_layout)?;
+
+    desc.frag = &cutout_frag;
+    desc.alpha_to_coverage = true;
+    let cutout = create_graphics_pipeline(device, data, &desc, *data.pipeline_layout)?;
+
+    let transparent = create_transparent_pipeline(device, data, &vert, &frag, 0, &constants)?;
+    Ok(ObjectPipelines { opaque, cutout, transparent })
+}
+
+/// Fails unless the object pipeline layout, which `what` is drawn with,
+/// provides everything `vert` and `frag` use.
+fn check_object_layout(data: &AppData, vert: &[u8], frag: &[u8], what: &str) -> anyhow::Result<()> {
+    let interface = ShaderInterface::merge(&[reflect(vert)?, reflect(frag)?])?;
+    data.object_interface
+        .check_provides(&interface)
+        .map_err(|e| anyhow!("{} don't fit the object pipeline layout: {}", what, e))
+}
+
+/// Installs freshly created object pipelines. Variants built for other
+/// constants used the previous shaders or render pass and are dropped.
+fn set_object_pipelines(data: &mut AppData, pipelines: ObjectPipelines) {
+    swap_object_pipelines(data, pipelines);
+    data.object_pipelines_constants = data.object_constants.clone();
+    data.object_variants.clear();
+}
+
+/// Puts `pipelines` in place of the current object pipelines and returns those.
+fn swap_object_pipelines(data: &mut AppData, pipelines: ObjectPipelines) -> ObjectPipelines {
+    ObjectPipelines {
+        opaque: std::mem::replace(&mut data.pipeline, pipelines.opaque),
+        cutout: std::mem::replace(&mut data.cutout_pipeline, pipelines.cutout),
+        transparent: std::mem::replace(&mut data.transparent_pipeline, pipelines.transparent),
+    }
+}
+
+/// Object pipelines kept for constants the game switched away from.
+const MAX_OBJECT_VARIANTS: usize = 8;
+
+/// Switches the object pipelines to `AppData::object_constants` when the game
+/// changed them. The pipelines of the last `MAX_OBJECT_VARIANTS` earlier
+/// constants are kept, so switching back doesn't create them again.
+pub unsafe fn select_object_pipelines(device: &Device, data: &mut AppData) -> anyhow::Result<()> {
+    if data.object_constants == data.object_pipelines_constants {
+        return Ok(());
+    }
+    let pipelines = match take_variant(&mut data.object_variants, &data.object_constants) {
+        Some(pipelines) => pipelines,
+        None => create_object_pipelines(device, data)?,
+    };
+    let previous = swap_object_pipelines(data, pipelines);
+    let previous_constants = std::mem::replace(&mut data.object_pipelines_constants, data.object_constants.clone());
+    // Frames in flight may still use evicted pipelines, the deletion queue waits.
+    cache_variant(&mut data.object_variants, previous_constants, previous);
+    Ok(())
+}
+
+/// Removes the pipelines cached for `constants` from `variants`.
+fn take_variant<T>(variants: &mut Vec<(ShaderConstants, T)>, constants: &ShaderConstants) -> Option<T> {
+    let index = variants.iter().position(|(cached, _)| cached == constants)?;
+    Some(variants.remove(index).1)
+}
+
+/// Caches `pipelines` as the most recently used, dropping the least recently
+/// used beyond `MAX_OBJECT_VARIANTS`.
+fn cache_variant<T>(variants: &mut Vec<(ShaderConstants, T)>, constants: ShaderConstants, pipelines: T) {
+    variants.push((constants, pipelines));
+    if variants.len() > MAX_OBJECT_VARIANTS {
+        variants.remove(0);
+    }
+}
+
+/// Blended objects are drawn after the opaque ones, depth tested against them
+/// but without writing depth so they don't hide each other.
+unsafe fn create_transparent_pipeline(
+    device: &Device,
+    data: &AppData,
+    vert: &[u8],
+    frag: &[u8],
+    subpass: u32,
+    constants: &ShaderConstants,
+) -> anyhow::Result<Pipeline> {
+    let mut desc = GraphicsPipelineDesc::new(vert, frag, *data.render_pass);
+    desc.depth_write = false;
+    desc.blend = BlendMode::Alpha;
+    desc.subpass = subpass;
+    desc.constants = constants.clone();
+    create_graphics_pipeline(device, data, &desc, *data.pipeline_layout)
+}
+
+/// Creates the G-buffer and lighting pipelines of the deferred path.
+pub unsafe fn create_deferred_pipelines(device: &Device, data: &mut AppData) -> anyhow::Result<()> {
+    create_object_pipeline_layout(device, data)?;
+    let pipelines = create_object_pipelines(device, data)?;
+    set_object_pipelines(data, pipelines);
 
-    let vert_shader_module = create_shader_module(device, &vert[..])?;
-    let frag_shader_module = create_shader_module(device, &frag[..])?;
+    let set_layouts = &[*data.deferred.lighting_set_layout];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts);
+    data.deferred.lighting_pipeline_layout = data.deletion_queue.own(device.create_pipeline_layout(&layout_info, None)?);
+
+    // Ambient and directional light, one full-screen triangle.
+    let vert = load_shader(data, "fullscreen.vert", &[], ShaderUser::Scene)?;
+    let frag = load_shader(data, "deferred_light.frag", &[], ShaderUser::Scene)?;
+    let mut desc = GraphicsPipelineDesc::new(&vert, &frag, *data.render_pass);
+    desc.vertex_input = VertexInput::None;
+    desc.depth_test = false;
+    desc.depth_write = false;
+    desc.subpass = 1;
+    data.deferred.lighting_pipeline =
+        create_graphics_pipeline(device, data, &desc, *data.deferred.lighting_pipeline_layout)?;
+
+    // Point lights, one screen-space quad bounding each light volume.
+    let vert = load_shader(data, "light_volume.vert", &[], ShaderUser::Scene)?;
+    let frag = load_shader(data, "light_volume.frag", &[], ShaderUser::Scene)?;
+    let mut desc = GraphicsPipelineDesc::new(&vert, &frag, *data.render_pass);
+    desc.vertex_input = VertexInput::None;
+    desc.topology = vk::PrimitiveTopology::TRIANGLE_STRIP;
+    desc.depth_test = false;
+    desc.depth_write = false;
+    desc.blend = BlendMode::Additive;
+    desc.subpass = 1;
+    data.deferred.light_volume_pipeline =
+        create_graphics_pipeline(device, data, &desc, *data.deferred.lighting_pipeline_layout)?;
+
+    Ok(())
+}
+
+/// Creates one pipeline per debug view, reusing the object pipeline layout.
+/// They draw into the subpass that writes the swapchain image; in the deferred
+/// path depth comes from the G-buffer pass, so they test against it read-only.
+pub unsafe fn create_debug_pipelines(device: &Device, data: &mut AppData) -> anyhow::Result<()> {
+    let vert = load_shader(data, "debug.vert", &[], ShaderUser::Debug)?;
+    let mut pipelines = Vec::with_capacity(DebugView::ALL.len());
+    for view in DebugView::ALL {
+        let frag_name = match view.fragment_shader() {
+            Some(name) if view != DebugView::Wireframe || data.fill_mode_non_solid => name,
+            _ => {
+                pipelines.push(Pipeline::default());
+                continue;
+            }
+        };
+
+        let frag = load_shader(data, frag_name, &[], ShaderUser::Debug)?;
+        check_object_layout(data, &vert, &frag, &format!("The shaders of the {:?} debug view", view))?;
+        let mut desc = GraphicsPipelineDesc::new(&vert, &frag, *data.render_pass);
+        match data.render_path {
+            RenderPath::Forward => {}
+            RenderPath::Deferred => {
+                desc.subpass = 1;
+                desc.depth_write = false;
+                desc.depth_compare_op = vk::CompareOp::LESS_OR_EQUAL;
+            }
+        }
+        if view.ignores_depth() {
+            desc.depth_test = false;
+            desc.depth_write = false;
+        }
+        match view {
+            DebugView::Wireframe => desc.polygon_mode = vk::PolygonMode::LINE,
+            DebugView::Overdraw => desc.blend = BlendMode::Additive,
+            _ => {}
+        }
+        pipelines.push(create_graphics_pipeline(device, data, &desc, *data.pipeline_layout)?);
+    }
+    data.debug_pipelines.pipelines = pipelines;
+    Ok(())
+}
+
+/// Creates the line pipelines of `DebugDraw`, one depth tested and one
+/// drawn on top of everything. Neither writes depth.
+pub unsafe fn create_debug_line_pipelines(device: &Device, data: &mut AppData) -> anyhow::Result<()> {
+    let vert = load_shader(data, "debug_line.vert", &[], ShaderUser::DebugLines)?;
+    let frag = load_shader(data, "debug_line.frag", &[], ShaderUser::DebugLines)?;
+    check_object_layout(data, &vert, &frag, "The debug line shaders")?;
+    let mut desc = GraphicsPipelineDesc::new(&vert, &frag, *data.render_pass);
+    desc.vertex_input = VertexInput::Debug;
+    desc.topology = vk::PrimitiveTopology::LINE_LIST;
+    desc.depth_write = false;
+    desc.depth_compare_op = vk::CompareOp::LESS_OR_EQUAL;
+    desc.blend = BlendMode::Alpha;
+    desc.subpass = match data.render_path {
+        RenderPath::Forward => 0,
+        RenderPath::Deferred => 1,
+    };
+    data.debug_line_pipeline = create_graphics_pipeline(device, data, &desc, *data.pipeline_layout)?;
+
+    desc.depth_test = false;
+    data.debug_overlay_pipeline = create_graphics_pipeline(device, data, &desc, *data.pipeline_layout)?;
+    Ok(())
+}
 
+/// Creates the sky pipeline, drawn after opaque geometry at the far plane.
+pub unsafe fn create_sky_pipeline(device: &Device, data: &mut AppData) -> anyhow::Result<()> {
+    let frag_name = match data.sky.mode {
+        SkyMode::ClearColor => return Ok(()),
+        SkyMode::Cubemap(_) => "sky_cubemap.frag",
+        SkyMode::Procedural => "sky_procedural.frag",
+    };
+
+    let set_layouts = &[*data.sky.set_layout];
+    let push_constant_ranges = data.sky.interface.push_constants.as_slice();
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+    data.sky.pipeline_layout = data.deletion_queue.own(device.create_pipeline_layout(&layout_info, None)?);
+
+    let vert = load_shader(data, "sky.vert", &[], ShaderUser::Sky)?;
+    let frag = load_shader(data, frag_name, &[], ShaderUser::Sky)?;
+    let mut desc = GraphicsPipelineDesc::new(&vert, &frag, *data.render_pass);
+    desc.vertex_input = VertexInput::None;
+    desc.depth_write = false;
+    desc.depth_compare_op = vk::CompareOp::LESS_OR_EQUAL;
+    desc.subpass = match data.render_path {
+        RenderPath::Forward => 0,
+        RenderPath::Deferred => 1,
+    };
+    data.sky.pipeline = create_graphics_pipeline(device, data, &desc, *data.sky.pipeline_layout)?;
+    Ok(())
+}
+
+pub unsafe fn create_graphics_pipeline(
+    device: &Device,
+    data: &AppData,
+    desc: &GraphicsPipelineDesc,
+    layout: vk::PipelineLayout,
+) -> anyhow::Result<Pipeline> {
+    let vert_interface = reflect(desc.vert)?;
+    let frag_interface = reflect(desc.frag)?;
+    desc.constants.vertex.check(&vert_interface)?;
+    desc.constants.fragment.check(&frag_interface)?;
+
+    // Only the attributes the vertex shader reads are bound.
+    let interface = ShaderInterface::merge(&[vert_interface, frag_interface])?;
+    let attributes = match desc.vertex_input {
+        VertexInput::None if !interface.vertex_inputs.is_empty() => {
+            return Err(anyhow!("The vertex shader reads vertex inputs, but the pipeline has none."));
+        }
+        VertexInput::None => Vec::new(),
+        VertexInput::Mesh => vertex_attributes(&Vertex::attribute_descriptions(), &interface)?,
+        VertexInput::Debug => vertex_attributes(&DebugVertex::attribute_descriptions(), &interface)?,
+    };
+
+    let vert_shader_module = create_shader_module(device, desc.vert)?;
+    let frag_shader_module = create_shader_module(device, desc.frag)?;
+
+    let (vert_entries, vert_data) = desc.constants.vertex.map();
+    let vert_specialization = vk::SpecializationInfo::builder()
+        .map_entries(&vert_entries)
+        .data(&vert_data);
     let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::VERTEX)
         .module(vert_shader_module)
-        .name(b"main\0");
+        .name(b"main\0")
+        .specialization_info(&vert_specialization);
 
-    //specialization_info for shader constants!!
+    let (frag_entries, frag_data) = desc.constants.fragment.map();
+    let frag_specialization = vk::SpecializationInfo::builder()
+        .map_entries(&frag_entries)
+        .data(&frag_data);
     let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::FRAGMENT)
         .module(frag_shader_module)
-        .name(b"main\0");
+        .name(b"main\0")
+        .specialization_info(&frag_specialization);
 
-    let binding_descriptions = &[Vertex::binding_description()];
-    let attribute_descriptions = Vertex::attribute_descriptions();
-    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
-        .vertex_binding_descriptions(binding_descriptions)
-        .vertex_attribute_descriptions(&attribute_descriptions);
+    let mesh_bindings = &[Vertex::binding_description()];
+    let debug_bindings = &[DebugVertex::binding_description()];
+    let vertex_input_state = match desc.vertex_input {
+        VertexInput::None => vk::PipelineVertexInputStateCreateInfo::builder(),
+        VertexInput::Mesh => vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(mesh_bindings)
+            .vertex_attribute_descriptions(&attributes),
+        VertexInput::Debug => vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(debug_bindings)
+            .vertex_attribute_descriptions(&attributes),
+    };
 
     let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
-        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .topology(desc.topology)
         .primitive_restart_enable(false);
 
     let viewport = vk::Viewport::builder()
@@ -54,21 +611,22 @@ pub unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> anyhow::Re
     let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
         .depth_clamp_enable(false)
         .rasterizer_discard_enable(false)
-        .polygon_mode(vk::PolygonMode::FILL)
+        .polygon_mode(desc.polygon_mode)
         .line_width(1.0)
-        .cull_mode(vk::CullModeFlags::empty())
+        .cull_mode(desc.cull_mode)
         .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
         .depth_bias_enable(false);
 
 
     let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
         .sample_shading_enable(false)
-        .rasterization_samples(vk::SampleCountFlags::_1);
+        .rasterization_samples(data.msaa_samples)
+        .alpha_to_coverage_enable(desc.alpha_to_coverage && data.msaa_samples != vk::SampleCountFlags::_1);
 
     let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
-        .depth_test_enable(true)
-        .depth_write_enable(true)
-        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_test_enable(desc.depth_test)
+        .depth_write_enable(desc.depth_write)
+        .depth_compare_op(data.camera.depth_compare_op(desc.depth_compare_op))
         .depth_bounds_test_enable(false)
         .min_depth_bounds(0.0) // Optional.
         .max_depth_bounds(1.0) // Optional.
@@ -77,41 +635,35 @@ pub unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> anyhow::Re
       //  .back(/* vk::StencilOpState */); // Optional.
 
 
+    let (blend_enable, dst_color_blend_factor) = match desc.blend {
+        BlendMode::Opaque => (false, vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+        BlendMode::Alpha => (true, vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+        BlendMode::Additive => (true, vk::BlendFactor::ONE),
+    };
     let attachment = vk::PipelineColorBlendAttachmentState::builder()
         .color_write_mask(vk::ColorComponentFlags::all())
-        .blend_enable(false)
+        .blend_enable(blend_enable)
         .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
-        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .dst_color_blend_factor(dst_color_blend_factor)
         .color_blend_op(vk::BlendOp::ADD)
         .src_alpha_blend_factor(vk::BlendFactor::ONE)
         .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
-        .alpha_blend_op(vk::BlendOp::ADD);
+        .alpha_blend_op(vk::BlendOp::ADD)
+        .build();
 
-    let attachments = &[attachment];
+    let attachments = vec![attachment; desc.color_attachment_count];
     let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
         .logic_op_enable(false)
         .logic_op(vk::LogicOp::COPY)
-        .attachments(attachments)
+        .attachments(&attachments)
         .blend_constants([0.0, 0.0, 0.0, 0.0]);
 
-    let dynamic_states = &[
-        vk::DynamicState::VIEWPORT,
-        vk::DynamicState::LINE_WIDTH,
-    ];
-
-    let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
-        .dynamic_states(dynamic_states);
-
-
-    let set_layouts = &[data.descriptor_set_layout];
-    let layout_info = vk::PipelineLayoutCreateInfo::builder()
-        .set_layouts(set_layouts);
-    data.pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
-
-
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(&data.rendering_formats.color)
+        .depth_attachment_format(data.rendering_formats.depth);
 
     let stages = &[vert_stage, frag_stage];
-    let info = vk::GraphicsPipelineCreateInfo::builder()
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
         .stages(stages)
         .vertex_input_state(&vertex_input_state)
         .input_assembly_state(&input_assembly_state)
@@ -120,16 +672,173 @@ pub unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> anyhow::Re
         .multisample_state(&multisample_state)
         .depth_stencil_state(&depth_stencil_state)
         .color_blend_state(&color_blend_state)
-        .layout(data.pipeline_layout)
-        .render_pass(data.render_pass)
-        .subpass(0);
-
+        .layout(layout)
+        .render_pass(desc.render_pass)
+        .subpass(desc.subpass);
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
 
-    data.pipeline = device.create_graphics_pipelines(
-        vk::PipelineCache::null(), &[info], None)?.0[0];
+    let pipeline = data.deletion_queue.own(device.create_graphics_pipelines(
+        *data.pipeline_cache, &[info], None)?.0[0]);
 
 
     device.destroy_shader_module(vert_shader_module, None);
     device.destroy_shader_module(frag_shader_module, None);
+    Ok(pipeline)
+}
+
+/// Creates a compute pipeline running `main` of `shader`.
+pub unsafe fn create_compute_pipeline(
+    device: &Device,
+    data: &AppData,
+    shader: &[u8],
+    layout: vk::PipelineLayout,
+    constants: &SpecializationConstants,
+) -> anyhow::Result<Pipeline> {
+    constants.check(&reflect(shader)?)?;
+    let shader_module = create_shader_module(device, shader)?;
+
+    let (entries, specialization_data) = constants.map();
+    let specialization = vk::SpecializationInfo::builder()
+        .map_entries(&entries)
+        .data(&specialization_data);
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization);
+
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(layout);
+
+    let pipeline = device.create_compute_pipelines(*data.pipeline_cache, &[info], None);
+    device.destroy_shader_module(shader_module, None);
+    Ok(data.deletion_queue.own(pipeline?.0[0]))
+}
+
+/// Length of the version one pipeline cache header: length, version, vendor
+/// ID and device ID, followed by the pipeline cache UUID.
+const PIPELINE_CACHE_HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+/// Creates the pipeline cache every pipeline is created with, seeded from
+/// `EngineConfig::pipeline_cache` if that file was written by this device
+/// and driver.
+pub unsafe fn create_pipeline_cache(instance: &Instance, device: &Device, data: &mut AppData) -> anyhow::Result<()> {
+    let initial_data = match &data.config.pipeline_cache {
+        Some(path) => match std::fs::read(path) {
+            Ok(bytes) if pipeline_cache_compatible(instance, data.physical_device, &bytes) => {
+                info!("Loaded {} bytes of pipeline cache from `{}`.", bytes.len(), path.display());
+                bytes
+            }
+            Ok(_) => {
+                info!("Ignoring pipeline cache `{}`, it was written by another device or driver.", path.display());
+                Vec::new()
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!("Failed to read pipeline cache `{}`: {}", path.display(), e);
+                Vec::new()
+            }
+        },
+        None => Vec::new(),
+    };
+
+    let info = vk::PipelineCacheCreateInfo::builder().initial_data(&initial_data);
+    data.pipeline_cache = data.deletion_queue.own(device.create_pipeline_cache(&info, None)?);
+    Ok(())
+}
+
+/// Whether the header of `bytes` matches the vendor, device and pipeline
+/// cache UUID of `physical_device`. Drivers should reject foreign data
+/// themselves, not all of them do.
+unsafe fn pipeline_cache_compatible(instance: &Instance, physical_device: vk::PhysicalDevice, bytes: &[u8]) -> bool {
+    if bytes.len() < PIPELINE_CACHE_HEADER_SIZE {
+        return false;
+    }
+    // The header is little endian whatever the host.
+    let word = |i: usize| u32::from_le_bytes([bytes[i * 4], bytes[i * 4 + 1], bytes[i * 4 + 2], bytes[i * 4 + 3]]);
+    let properties = instance.get_physical_device_properties(physical_device);
+    word(0) as usize >= PIPELINE_CACHE_HEADER_SIZE
+        && word(1) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && word(2) == properties.vendor_id
+        && word(3) == properties.device_id
+        && bytes[16..PIPELINE_CACHE_HEADER_SIZE] == properties.pipeline_cache_uuid[..]
+}
+
+/// Writes the pipeline cache to `EngineConfig::pipeline_cache` for the next
+/// launch. The file is replaced only once the new one is complete.
+pub unsafe fn save_pipeline_cache(device: &Device, data: &AppData) -> anyhow::Result<()> {
+    let Some(path) = &data.config.pipeline_cache else {
+        return Ok(());
+    };
+    let bytes = device.get_pipeline_cache_data(*data.pipeline_cache)?;
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, &bytes)
+        .and_then(|_| std::fs::rename(&partial, path))
+        .map_err(|e| anyhow!("Failed to write pipeline cache `{}`: {}", path.display(), e))?;
+    info!("Saved {} bytes of pipeline cache to `{}`.", bytes.len(), path.display());
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader_compiler::EMBEDDED_SHADERS;
+
+    fn textured(value: bool) -> ShaderConstants {
+        ShaderConstants { fragment: SpecializationConstants::new().set(0, value), ..Default::default() }
+    }
+
+    #[test]
+    fn map_packs_constants_by_id() {
+        let constants = SpecializationConstants::new()
+            .set(3, 1.5f32)
+            .set(0, true)
+            .set(1, -2)
+            .set(0, false);
+        let (entries, bytes) = constants.map();
+        let ids = entries.iter().map(|e| (e.constant_id, e.offset, e.size)).collect::<Vec<_>>();
+        assert_eq!(ids, [(0, 0, 4), (1, 4, 4), (3, 8, 4)]);
+        let words = bytes.chunks(4).map(|word| u32::from_ne_bytes(word.try_into().unwrap())).collect::<Vec<_>>();
+        assert_eq!(words, [0, -2i32 as u32, 1.5f32.to_bits()]);
+    }
+
+    #[test]
+    fn check_rejects_constants_of_another_type() {
+        let frag = EMBEDDED_SHADERS
+            .iter()
+            .find(|shader| shader.name == "shader.frag" && shader.defines.is_empty())
+            .unwrap();
+        let interface = reflect(frag.spirv).unwrap();
+        assert!(SpecializationConstants::new().set(0, false).check(&interface).is_ok());
+        // Constants the shader doesn't declare are ignored.
+        assert!(SpecializationConstants::new().set(7, 1.0f32).check(&interface).is_ok());
+        let error = SpecializationConstants::new().set(0, 1u32).check(&interface).unwrap_err().to_string();
+        assert!(error.contains("is a Bool in the shader"), "{}", error);
+    }
+
+    #[test]
+    fn variants_evict_the_least_recently_used() {
+        let mut variants = Vec::new();
+        for id in 0..MAX_OBJECT_VARIANTS as u32 {
+            let constants = ShaderConstants { vertex: SpecializationConstants::new().set(id, 0u32), ..Default::default() };
+            cache_variant(&mut variants, constants, id);
+        }
+        cache_variant(&mut variants, textured(true), 100);
+
+        // Using a variant makes it the most recently used again.
+        let oldest = variants[0].0.clone();
+        let pipelines = take_variant(&mut variants, &oldest).unwrap();
+        assert_eq!(pipelines, 1);
+        cache_variant(&mut variants, oldest, pipelines);
+
+        cache_variant(&mut variants, textured(false), 101);
+        assert_eq!(variants.len(), MAX_OBJECT_VARIANTS);
+        let kept = variants.iter().map(|(_, pipelines)| *pipelines).collect::<Vec<_>>();
+        assert_eq!(kept, [3, 4, 5, 6, 7, 100, 1, 101]);
+        assert_eq!(take_variant(&mut variants, &textured(false)), Some(101));
+        assert_eq!(take_variant(&mut variants, &textured(false)), None);
+    }
+}
@@ -8,6 +8,11 @@ use crate::render_app::AppData;
 pub struct QueueFamilyIndices {
     pub graphics: u32,
     pub present: u32,
+    /// A family for uploads other than `graphics`, `None` if the device has
+    /// no transfer only or async compute family.
+    pub transfer: Option<u32>,
+    /// A compute family without graphics, for async compute.
+    pub compute: Option<u32>,
 }
 
 //Note! To Increase performance choose one queuefamily instead of separate '
@@ -21,13 +26,34 @@ impl QueueFamilyIndices {
         let properties = instance
             .get_physical_device_queue_family_properties(physical_device);
 
+        // Compute passes run on the graphics queue unless async compute is on.
         let graphics = properties
             .iter()
-            .position(|p| p.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            .position(|p| p.queue_flags.contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE))
+            .map(|i| i as u32);
+
+        let compute = properties
+            .iter()
+            .position(|p| {
+                p.queue_flags.contains(vk::QueueFlags::COMPUTE)
+                    && !p.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+            })
+            .map(|i| i as u32);
+
+        // Prefer a transfer only family, usually a dedicated DMA engine.
+        let transfer_only = properties.iter().position(|p| {
+            p.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                && !p.queue_flags.intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+        });
+        let transfer = transfer_only
+            .or_else(|| properties.iter().position(|p| {
+                p.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !p.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+            }))
             .map(|i| i as u32);
 
         let mut present = None;
-        for (index, properties) in properties.iter().enumerate() {
+        for index in 0..properties.len() {
             if instance.get_physical_device_surface_support_khr(
                 physical_device,
                 index as u32,
@@ -40,7 +66,7 @@ impl QueueFamilyIndices {
 
 
         if let (Some(graphics), Some(present)) = (graphics, present) {
-            Ok(Self { graphics, present })
+            Ok(Self { graphics, present, transfer, compute })
         } else {
             Err(anyhow!(SuitabilityError("Missing required queue families.")))
         }
@@ -1,77 +1,164 @@
 use anyhow::anyhow;
+use log::{error, info, warn};
 use vulkanalia::{vk, Device, Entry, Instance};
 use vulkanalia::loader::{LibloadingLoader, LIBRARY};
 use vulkanalia::vk::{DeviceV1_0, ExtDebugUtilsExtension, Handle, HasBuilder, InstanceV1_0, KhrSurfaceExtension, KhrSwapchainExtension};
+use winit::event::{DeviceEvent, ElementState, KeyEvent, WindowEvent};
+use winit::keyboard::PhysicalKey;
 use winit::window::Window;
 use vulkanalia::window as vk_window;
-use crate::{MAX_FRAMES_IN_FLIGHT, VALIDATION_ENABLED};
-use crate::command_buffer_util::create_command_buffers;
-use crate::command_pool::{create_command_pool, create_transient_command_pool};
-use crate::device_util::{create_logical_device, pick_physical_device};
-use crate::framebuffer_util::{create_depth_objects, create_framebuffers};
+use crate::command_buffer_util::{create_command_buffers, update_command_buffer};
+use crate::command_pool::{create_command_pool, create_transfer_command_pool, create_transient_command_pool};
+use crate::device_util::{create_logical_device, get_msaa_samples, pick_physical_device};
+use crate::framebuffer_util::{create_color_objects, create_depth_objects, create_framebuffers};
 use crate::instance_util::create_instance;
-use crate::pipeline_util::create_pipeline;
+use crate::pipeline_util::{create_pipeline, create_pipeline_cache, save_pipeline_cache, select_object_pipelines, ObjectPipelines, ShaderConstants};
 use crate::render_pass_util::create_render_pass;
+use crate::rendering_util::{create_forward_graph, create_rendering_formats, RenderingFormats};
+use crate::render_graph::{ImageId, RenderGraph};
 use crate::swapchain_util::{create_swapchain, create_swapchain_image_views};
-use crate::sync_util::create_sync_objects;
-use crate::descriptor_util::{create_descriptor_pool, create_descriptor_set_layout, create_descriptor_sets, create_uniform_buffers};
-use crate::vertexbuffer_util::{create_index_buffer, create_vertex_buffer, load_model, Vertex, VertexData};
-use std::time::Instant;
-use cgmath::{point3, vec3, Deg};
+use crate::sync_util::{create_sync_objects, Timeline};
+use crate::descriptor_util::{create_descriptor_pool, create_descriptor_set_layout, create_descriptor_sets, create_uniform_buffers, update_texture_descriptor};
+use crate::vertexbuffer_util::{create_index_buffer, create_vertex_buffer, load_model, Geometry, Vertex};
+use std::time::Duration;
+use cgmath::{point3, vec3};
 use crate::transforms::{Mat4, UniformBufferObject};
 use std::ptr::copy_nonoverlapping as memcpy;
 use crate::image_util::{create_texture_image, create_texture_image_view, create_texture_sampler};
+use crate::deferred_util::{check_gbuffer_support, create_gbuffer_objects, create_light_buffers, create_lighting_descriptor_set_layout, create_lighting_descriptor_sets, release_deferred_swapchain_objects, DeferredData, RenderPath};
+use crate::lights::{ambient_light, default_point_lights, DirectionalLight, LightUniform, PointLight, MAX_POINT_LIGHTS};
+use crate::pipeline_util::create_deferred_pipelines;
+use crate::render_pass_util::create_deferred_render_pass;
+use cgmath::{vec4, SquareMatrix};
+use crate::pipeline_util::{create_debug_line_pipelines, create_debug_pipelines, create_sky_pipeline};
+use crate::camera::{Camera, CameraController, OrbitController};
+use crate::input::Input;
+use crate::config::EngineConfig;
+use crate::engine::{Context, EngineBuilder, Game, ShaderPaths};
+use crate::game_loop::{FrameTime, GameLoop};
+use crate::debug_draw::{upload_debug_draw, DebugDraw};
+use crate::debug_view::{DebugPipelines, DebugView};
+use crate::scene::{Mesh, SceneObject};
+use crate::sky_util::{create_cubemap, create_sky_descriptor_set_layout, create_sky_descriptor_sets, release_sky_swapchain_objects, SkyData, SkyMode};
+use crate::memory_util::Allocator;
+use crate::upload_util::{create_uploader, stream_uploads, Uploader, Uploads};
+use crate::compute::{create_compute, reload_compute_pass, submit_compute, Compute};
+use crate::shader_compiler::{ShaderUser, Shaders};
+use crate::spirv_reflect::ShaderInterface;
+use crate::frame_util::{create_frame_arenas, FrameArena};
+use crate::resource_util::{Buffer, CommandPool, DeletionQueue, DescriptorPool, DescriptorSetLayout, Fence, Framebuffer, Image, ImageView, Memory, Pipeline, PipelineCache, PipelineLayout, RenderPass, Sampler, Semaphore, Swapchain};
+
+/// Simulation ticks per second of game time.
+const TICK_RATE: f32 = 60.0;
 
 /// Our Vulkan app.
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct App {
-    pub entry: Entry,
+    /// Keeps the Vulkan library loaded for `instance`.
+    _entry: Entry,
     pub instance: Instance,
     pub data: AppData,
     pub(crate) device: Device,
     frame: usize,
+    /// Frames submitted so far, keys the deletion queue.
+    frames_rendered: u64,
     pub(crate) resized: bool,
-    start: Instant,
+    pub game_loop: GameLoop,
+    frame_time: FrameTime,
+    title: String,
+    camera_controller: CameraController,
+    pub input: Input,
 }
 
 
 impl App {
     /// Creates our Vulkan app.
-    pub(crate) unsafe fn create(window: &Window) -> anyhow::Result<Self> {
+    pub(crate) unsafe fn create(window: &Window, builder: &EngineBuilder) -> anyhow::Result<Self> {
         let resized = false;
         let loader = LibloadingLoader::new(LIBRARY)?;
         let entry = Entry::new(loader).map_err(|b| anyhow!("{}", b))?;
-        let mut data = AppData::default();
+        let mut data = AppData {
+            config: builder.config.clone(),
+            render_path: builder.config.render_path,
+            msaa_samples: vk::SampleCountFlags::_1,
+            forward_shaders: builder.forward_shaders.clone(),
+            object_constants: builder.object_constants.clone(),
+            shaders: Shaders::new(builder.config.asset("shaders")),
+            ..Default::default()
+        };
+        if data.config.hot_reload {
+            if let Err(e) = data.shaders.watch() {
+                warn!("{}", e);
+            }
+        }
+        data.point_lights = default_point_lights();
+        data.sky.mode = builder.config.sky.clone();
+        data.sky.clear_color = builder.config.clear_color;
+        data.camera = builder.camera;
+        let camera_controller = CameraController::Orbit(OrbitController::new(&data.camera, point3(0.0, 0.0, 0.0)));
         let instance = create_instance(window, &entry, &mut data)?;
         data.surface = vk_window::create_surface(&instance, &window, &window)?;
         pick_physical_device(&instance, &mut data)?;
+        data.allocator = Allocator::new(&instance, data.physical_device);
+        data.msaa_samples = get_msaa_samples(&instance, &data);
+        if data.render_path == RenderPath::Deferred {
+            check_gbuffer_support(&instance, &data)?;
+        }
         let device = create_logical_device(&entry, &instance, &mut data)?;
-        let start = Instant::now();
+        create_pipeline_cache(&instance, &device, &mut data)?;
 
         create_swapchain(window, &instance, &device, &mut data)?;
         create_swapchain_image_views(&device, &mut data)?;
-        create_render_pass(&instance, &device, &mut data)?;
         create_descriptor_set_layout(&device, &mut data)?;
-
-        create_pipeline(&device, &mut data)?;
+        if data.render_path == RenderPath::Deferred {
+            create_lighting_descriptor_set_layout(&device, &mut data)?;
+        }
+        create_sky_descriptor_set_layout(&device, &mut data)?;
         create_command_pool(&instance, &device, &mut data)?;
-        create_depth_objects(&instance, &device, &mut data)?;
-        create_framebuffers(&device, &mut data)?;
-        create_texture_image(&instance, &device, &mut data, "src/resources/viking_room.png".parse()?)?;
+        create_transient_command_pool(&instance, &device, &mut data)?;
+        create_transfer_command_pool(&instance, &device, &mut data)?;
+        create_uploader(&instance, &device, &mut data)?;
+        create_render_targets(&instance, &device, &mut data)?;
+        let texture = builder.texture.clone().unwrap_or_else(|| data.config.asset("resources/viking_room.png"));
+        create_texture_image(&instance, &device, &mut data, texture)?;
         create_texture_image_view(&device, &mut data)?;
         create_texture_sampler(&device, &mut data)?;
-        create_transient_command_pool(&instance, &device, &mut data)?;
-        load_model(&mut data)?;
+        if let SkyMode::Cubemap(directory) = data.sky.mode.clone() {
+            create_cubemap(&instance, &device, &mut data, &directory)?;
+        }
+        for path in &builder.models {
+            let mesh = load_model(&mut data, path)?;
+            data.meshes.push(mesh);
+        }
         create_vertex_buffer(&instance, &device, &mut data)?;
         create_index_buffer(&instance, &device, &mut data)?;
         create_uniform_buffers(&instance, &device, &mut data)?;
         create_descriptor_pool(&device, &mut data)?;
         create_descriptor_sets(&device, &mut data)?;
+        create_lighting_objects(&instance, &device, &mut data)?;
+        create_sky_descriptor_sets(&device, &mut data)?;
+        create_compute(&instance, &device, &mut data, &builder.storage, &builder.compute_passes)?;
+        // One wait for all scene uploads.
+        data.uploader.wait(&device)?;
 
         create_command_buffers(&device, &mut data)?;
         create_sync_objects(&device, &mut data)?;
-
-        Ok(Self { entry, instance, data, device, frame: 0, resized, start})
+        create_frame_arenas(&mut data);
+
+        Ok(Self {
+            _entry: entry,
+            instance,
+            data,
+            device,
+            frame: 0,
+            frames_rendered: 0,
+            resized,
+            game_loop: GameLoop::new(TICK_RATE),
+            frame_time: FrameTime::default(),
+            title: builder.config.window.title.clone(),
+            camera_controller,
+            input: Input::new(builder.actions.clone().unwrap_or_default()),
+        })
     }
 
     unsafe fn recreate_swapchain(&mut self, window: &Window) -> anyhow::Result<()> {
@@ -79,75 +166,150 @@ impl App {
         self.destroy_swapchain();
         create_swapchain(window, &self.instance, &self.device, &mut self.data)?;
         create_swapchain_image_views(&self.device, &mut self.data)?;
-        create_render_pass(&self.instance, &self.device, &mut self.data)?;
-        create_pipeline(&self.device, &mut self.data)?;
-        create_depth_objects(&self.instance, &self.device, &mut self.data)?;
-        create_framebuffers(&self.device, &mut self.data)?;
+        create_render_targets(&self.instance, &self.device, &mut self.data)?;
+        // Runs the layout transitions of the new attachments.
+        self.data.uploader.wait(&self.device)?;
         create_uniform_buffers(&self.instance, &self.device, &mut self.data)?;
         create_descriptor_pool(&self.device, &mut self.data)?;
         create_descriptor_sets(&self.device, &mut self.data)?;
+        create_lighting_objects(&self.instance, &self.device, &mut self.data)?;
+        create_sky_descriptor_sets(&self.device, &mut self.data)?;
         create_command_buffers(&self.device, &mut self.data)?;
         Ok(())
     }
 
-    pub unsafe fn update_uniform_buffer(&self, image_index: usize) -> anyhow::Result<()> {
-        let time = self.start.elapsed().as_secs_f32();
-        let model = Mat4::from_axis_angle(
-            vec3(0.0, 0.0, 1.0),
-            Deg(90.0) * time
-        );
-        let view = Mat4::look_at_rh(
-            point3(2.0, 2.0, 2.0),
-            point3(0.0, 0.0, 0.0),
-            vec3(0.0, 0.0, 1.0),
-        );
-        let correction = Mat4::new(
-            1.0,  0.0,       0.0, 0.0,
-            // We're also flipping the Y-axis with this line's `-1.0`.
-            0.0, -1.0,       0.0, 0.0,
-            0.0,  0.0, 1.0 / 2.0, 0.0,
-            0.0,  0.0, 1.0 / 2.0, 1.0,
-        );
+    /// Rebuilds the pipelines whose shaders changed on disk. Old pipelines
+    /// stay queued for deletion until the frames using them finish, and
+    /// are kept when rebuilding fails.
+    unsafe fn reload_shaders(&mut self) {
+        let (device, data) = (&self.device, &mut self.data);
+        for user in data.shaders.changed_users() {
+            let result = match user {
+                ShaderUser::Scene => match data.render_path {
+                    RenderPath::Forward => create_pipeline(device, data),
+                    RenderPath::Deferred => create_deferred_pipelines(device, data),
+                },
+                ShaderUser::Sky => create_sky_pipeline(device, data),
+                ShaderUser::Debug => create_debug_pipelines(device, data),
+                ShaderUser::DebugLines => create_debug_line_pipelines(device, data),
+                ShaderUser::Compute(pass) => reload_compute_pass(device, data, pass),
+            };
+            match result {
+                Ok(()) => info!("Rebuilt the {:?} pipelines.", user),
+                Err(e) => error!("Failed to rebuild the {:?} pipelines: {:#}", user, e),
+            }
+        }
+    }
+
+    /// The parts of the app game code may touch.
+    fn context(&mut self) -> Context<'_> {
+        Context {
+            input: &self.input,
+            camera: &mut self.data.camera,
+            objects: &mut self.data.objects,
+            meshes: &self.data.meshes,
+            sun: &mut self.data.sun,
+            point_lights: &mut self.data.point_lights,
+            debug_draw: &mut self.data.debug_draw,
+            compute: &mut self.data.compute,
+            uploads: &mut self.data.uploads,
+            object_constants: &mut self.data.object_constants,
+            game_loop: &mut self.game_loop,
+        }
+    }
+
+    pub(crate) fn init(&mut self, game: &mut dyn Game) -> anyhow::Result<()> {
+        game.init(&mut self.context())
+    }
 
-        let proj = correction
-            * cgmath::perspective(
-            Deg(45.0),
+    /// Writes the uniforms of frame `frame`, whose fence was waited on.
+    pub unsafe fn update_uniform_buffer(&self, frame: usize) -> anyhow::Result<()> {
+        // Objects carry their own transforms.
+        let model = Mat4::identity();
+        let view = self.data.camera.view_matrix();
+        let proj = self.data.camera.projection_matrix(
             self.data.swapchain_extent.width as f32 / self.data.swapchain_extent.height as f32,
-            0.1,
-            10.0,
         );
+        let depth_range = vec4(self.data.camera.far_depth(), 0.0, 0.0, 0.0);
 
-        let ubo = UniformBufferObject { model, view, proj };
-        let memory = self.device.map_memory(
-            self.data.uniform_buffers_memory[image_index],
-            0,
-            size_of::<UniformBufferObject>() as u64,
-            vk::MemoryMapFlags::empty(),
-        )?;
-
+        let ubo = UniformBufferObject { model, view, proj, depth_range };
+        let memory = self.data.uniform_buffers_memory[frame].mapped_ptr()?;
         memcpy(&ubo, memory.cast(), 1);
 
-        self.device.unmap_memory(self.data.uniform_buffers_memory[image_index]);
+        if self.data.render_path == RenderPath::Deferred {
+            self.update_light_buffer(frame, view, proj, self.data.camera.position)?;
+        }
 
 
+        Ok(())
+    }
 
+    unsafe fn update_light_buffer(
+        &self,
+        frame: usize,
+        view: Mat4,
+        proj: Mat4,
+        eye: cgmath::Point3<f32>,
+    ) -> anyhow::Result<()> {
+        let mut point_lights = [PointLight::new(vec3(0.0, 0.0, 0.0), 0.0, vec3(0.0, 0.0, 0.0), 0.0); MAX_POINT_LIGHTS];
+        let count = self.data.point_lights.len().min(MAX_POINT_LIGHTS);
+        point_lights[..count].copy_from_slice(&self.data.point_lights[..count]);
+        let width = self.data.swapchain_extent.width as f32;
+        let height = self.data.swapchain_extent.height as f32;
+
+        let lights = LightUniform {
+            view,
+            proj,
+            inv_view_proj: (proj * view).invert().unwrap_or(Mat4::identity()),
+            camera_position: vec4(eye.x, eye.y, eye.z, 1.0),
+            viewport: vec4(width, height, 1.0 / width, 1.0 / height),
+            depth_range: vec4(self.data.camera.far_depth(), 0.0, 0.0, 0.0),
+            ambient: ambient_light(),
+            sun: self.data.sun,
+            point_light_count: [count as u32, 0, 0, 0],
+            point_lights,
+        };
 
+        let memory = self.data.deferred.light_buffers_memory[frame].mapped_ptr()?;
+        memcpy(&lights, memory.cast(), 1);
         Ok(())
     }
 
     /// Renders a frame for our Vulkan app.
-    pub(crate) unsafe fn render(&mut self, window: &Window) -> anyhow::Result<()> {
-
-        self.device.wait_for_fences(
-            &[self.data.in_flight_fences[self.frame]],
-            true,
-            u64::MAX,
-        )?;
+    pub(crate) unsafe fn render(&mut self, window: &Window, game: &mut dyn Game) -> anyhow::Result<()> {
+
+        // Wait for the frame `frames_in_flight` ago, which used the same
+        // command buffer, uniforms and arena. Nothing dropped up to that
+        // frame is still in use either.
+        let frames_in_flight = self.data.config.frames_in_flight as u64;
+        let completed = if self.data.timeline_semaphores {
+            // Frame `n` signals `n + 1`, so the timeline tells exactly which
+            // frames have finished.
+            let timeline = &self.data.frame_timeline;
+            timeline.wait(&self.device, (self.frames_rendered + 1).saturating_sub(frames_in_flight))?;
+            timeline.value(&self.device)?.checked_sub(1)
+        } else {
+            self.device.wait_for_fences(
+                &[*self.data.in_flight_fences[self.frame]],
+                true,
+                u64::MAX,
+            )?;
+            self.frames_rendered.checked_sub(frames_in_flight)
+        };
+        if let Some(completed) = completed {
+            self.data.deletion_queue.collect(&self.device, &self.data.allocator, completed);
+        }
+        self.data.deletion_queue.set_frame(self.frames_rendered);
+        self.data.uploader.poll(&self.device)?;
+        stream_uploads(&self.instance, &self.device, &mut self.data)?;
+        update_texture_descriptor(&self.device, &mut self.data, self.frame);
+        self.data.frame_arenas[self.frame].reset();
+        self.reload_shaders();
 
         let result = self.device.acquire_next_image_khr(
-            self.data.swapchain,
+            *self.data.swapchain,
             u64::MAX,
-            self.data.image_available_semaphores[self.frame],
+            *self.data.image_available_semaphores[self.frame],
             vk::Fence::null(),
         );
 
@@ -159,34 +321,76 @@ impl App {
         };
 
 
-        let image_in_flight = self.data.images_in_flight[image_index];
-        if !image_in_flight.is_null() {
-            self.device.wait_for_fences(&[image_in_flight], true, u64::MAX)?;
-        }
+        // Without fences there is nothing to track. The image was presented,
+        // so the frame that last rendered to it has finished with it.
+        if !self.data.timeline_semaphores {
+            let image_in_flight = self.data.images_in_flight[image_index];
+            if !image_in_flight.is_null() {
+                self.device.wait_for_fences(&[image_in_flight], true, u64::MAX)?;
+            }
 
-        self.data.images_in_flight[image_index] = self.data.in_flight_fences[self.frame];
+            self.data.images_in_flight[image_index] = *self.data.in_flight_fences[self.frame];
+        }
 
-        self.update_uniform_buffer(image_index)?;
+        self.update(window, game);
+        if let Err(e) = select_object_pipelines(&self.device, &mut self.data) {
+            error!("Failed to switch the object shader constants: {:#}", e);
+            self.data.object_constants = self.data.object_pipelines_constants.clone();
+        }
 
-        let wait_semaphores = &[self.data.image_available_semaphores[self.frame]];
-        let wait_stages = &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-        let command_buffers = &[self.data.command_buffers[image_index]];
-        let signal_semaphores = &[self.data.render_finished_semaphores[self.frame]];
+        self.update_uniform_buffer(self.frame)?;
+        upload_debug_draw(&self.device, &mut self.data, self.frame)?;
+        let view = self.data.camera.view_matrix();
+        let compute_semaphore = submit_compute(&self.device, &self.data, self.frame, self.frames_rendered + 1)?;
+        update_command_buffer(&self.device, &self.data, self.frame, image_index, view)?;
+        self.data.debug_draw.end_frame(self.frame_time.real_dt);
+        self.data.compute.end_frame();
+
+        // Binary semaphores ignore their timeline value.
+        let mut wait_semaphores = vec![*self.data.image_available_semaphores[self.frame]];
+        let mut wait_values = vec![0];
+        let mut wait_stages = vec![vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        if let Some((semaphore, value)) = compute_semaphore {
+            wait_semaphores.push(semaphore);
+            wait_values.push(value);
+            wait_stages.push(vk::PipelineStageFlags::VERTEX_INPUT
+                | vk::PipelineStageFlags::VERTEX_SHADER
+                | vk::PipelineStageFlags::FRAGMENT_SHADER);
+        }
+        let command_buffers = &[self.data.command_buffers[self.frame]];
+        let signal_semaphores = &[*self.data.render_finished_semaphores[self.frame]];
         let submit_info = vk::SubmitInfo::builder()
-            .wait_semaphores(wait_semaphores)
-            .wait_dst_stage_mask(wait_stages)
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
             .command_buffers(command_buffers)
             .signal_semaphores(signal_semaphores);
 
-        self.device.reset_fences(&[self.data.in_flight_fences[self.frame]])?;
-
-        self.device.queue_submit(
-            self.data.graphics_queue,
-            &[submit_info],
-            self.data.in_flight_fences[self.frame],
-        )?;
+        if self.data.timeline_semaphores {
+            let signal_semaphores = &[signal_semaphores[0], self.data.frame_timeline.semaphore()];
+            let signal_values = &[0, self.frames_rendered + 1];
+            let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+                .wait_semaphore_values(&wait_values)
+                .signal_semaphore_values(signal_values);
+            let submit_info = submit_info
+                .signal_semaphores(signal_semaphores)
+                .push_next(&mut timeline_info);
+            self.device.queue_submit(self.data.graphics_queue, &[submit_info], vk::Fence::null())?;
+        } else {
+            self.device.reset_fences(&[*self.data.in_flight_fences[self.frame]])?;
+
+            // Tells the next frame's async compute that this one is done.
+            let mut signal_semaphores = signal_semaphores.to_vec();
+            signal_semaphores.extend(self.data.compute.released_semaphore(self.frame));
+            let submit_info = submit_info.signal_semaphores(&signal_semaphores);
+            self.device.queue_submit(
+                self.data.graphics_queue,
+                &[submit_info],
+                *self.data.in_flight_fences[self.frame],
+            )?;
+        }
+        self.frames_rendered += 1;
 
-        let swapchains = &[self.data.swapchain];
+        let swapchains = &[*self.data.swapchain];
         let image_indices = &[image_index as u32];
         let present_info = vk::PresentInfoKHR::builder()
             .wait_semaphores(signal_semaphores)
@@ -203,128 +407,343 @@ impl App {
         } else if let Err(e) = result {
             return Err(anyhow!(e));
         }
-        self.device.queue_wait_idle(self.data.present_queue)?;
 
+        self.frame = (self.frame + 1) % self.data.config.frames_in_flight;
+        Ok(())
+    }
 
 
+    pub(crate) fn handle_window_event(&mut self, event: &WindowEvent) {
+        self.input.handle_window_event(event);
+        if let WindowEvent::KeyboardInput {
+            event: KeyEvent { physical_key: PhysicalKey::Code(key), state: ElementState::Pressed, repeat: false, .. },
+            ..
+        } = event {
+            if let Some(view) = DebugView::from_key(*key) {
+                self.set_debug_view(view);
+            }
+        }
+    }
 
-        self.frame = (self.frame + 1) % MAX_FRAMES_IN_FLIGHT;
-        Ok(())
+    pub(crate) fn handle_device_event(&mut self, event: &DeviceEvent) {
+        self.input.handle_device_event(event);
     }
 
+    /// Runs the frame's simulation ticks and the game's update with this
+    /// frame's input, then clears the input for the next frame.
+    fn update(&mut self, window: &Window, game: &mut dyn Game) {
+        self.frame_time = self.game_loop.advance();
+        self.update_time_controls();
+        let fixed_dt = self.game_loop.fixed_dt;
+        for _ in 0..self.frame_time.ticks {
+            game.fixed_update(&mut self.context(), fixed_dt);
+        }
+        let frame_time = self.frame_time;
+        game.update(&mut self.context(), frame_time);
 
-    /// Destroys our Vulkan app.
-    pub(crate) unsafe fn destroy(&mut self) {
+        if self.game_loop.stats.report_due(Duration::from_secs(1)) {
+            let paused = if self.game_loop.paused() { ", paused" } else { "" };
+            window.set_title(&format!(
+                "{} - {} x{}{}", self.title, self.game_loop.stats, self.game_loop.time_scale, paused));
+        }
 
-        self.device.device_wait_idle().unwrap();
-        self.destroy_swapchain();
-        self.device.destroy_sampler(self.data.texture_sampler, None);
-        self.device.destroy_image_view(self.data.texture_image_view, None);
+        if self.input.action_pressed("toggle_camera") {
+            self.toggle_camera_controller();
+        }
+        if self.input.action_pressed("toggle_projection") {
+            self.toggle_projection();
+        }
+        self.camera_controller.update(&mut self.data.camera, &self.input, self.frame_time.real_dt);
+        let grab = self.camera_controller.wants_cursor_grab(&self.input);
+        self.input.set_cursor_grab(window, grab);
+        self.input.end_frame();
+    }
 
-        self.device.destroy_image(self.data.texture_image, None);
-        self.device.free_memory(self.data.texture_image_memory, None);
+    /// Pause, single step and time scale, for debugging.
+    fn update_time_controls(&mut self) {
+        if self.input.action_pressed("pause") {
+            self.game_loop.set_paused(!self.game_loop.paused());
+            info!("Simulation {}.", if self.game_loop.paused() { "paused" } else { "resumed" });
+        }
+        if self.input.action_pressed("step") {
+            self.game_loop.step();
+        }
+        let scale = if self.input.action_pressed("time_faster") {
+            2.0
+        } else if self.input.action_pressed("time_slower") {
+            0.5
+        } else {
+            1.0
+        };
+        if scale != 1.0 {
+            self.game_loop.time_scale = (self.game_loop.time_scale * scale).clamp(1.0 / 16.0, 16.0);
+            info!("Time scale: {}.", self.game_loop.time_scale);
+        }
+    }
 
-        self.device.destroy_descriptor_set_layout(self.data.descriptor_set_layout, None);
+    /// Switches between the fly and orbit camera.
+    fn toggle_camera_controller(&mut self) {
+        self.camera_controller.toggle(&self.data.camera);
+        let mode = match self.camera_controller {
+            CameraController::Fly(_) => "fly",
+            CameraController::Orbit(_) => "orbit",
+        };
+        info!("Camera controller: {}.", mode);
+    }
 
-        self.data.in_flight_fences.iter().for_each(|f| self.device.destroy_fence(*f, None));
-        self.data.render_finished_semaphores.iter().for_each(|s| self.device.destroy_semaphore(*s, None));
-        self.data.image_available_semaphores.iter().for_each(|s| self.device.destroy_semaphore(*s, None));
-        self.device.free_memory(self.data.vertex_buffer_memory, None);
-        self.device.destroy_buffer(self.data.vertex_buffer, None);
-        self.device.free_memory(self.data.index_buffer_memory, None);
-        self.device.destroy_buffer(self.data.index_buffer, None);
+    /// Switches between perspective and orthographic projection.
+    fn toggle_projection(&mut self) {
+        let distance = self.camera_controller.focus_distance();
+        self.data.camera.toggle_projection(distance);
+        info!("Projection: {:?}.", self.data.camera.projection);
+    }
 
+    /// Switches the debug visualization, takes effect on the next frame.
+    fn set_debug_view(&mut self, view: DebugView) {
+        if self.data.debug_pipelines.get(view).is_none() && view != DebugView::Lit {
+            warn!("Debug view {:?} is not available on this device.", view);
+            return;
+        }
+        info!("Debug view: {:?}.", view);
+        self.data.debug_view = view;
+    }
 
-        self.device.destroy_command_pool(self.data.command_pool, None);
-        self.device.destroy_command_pool(self.data.transient_command_pool, None);
+    /// Destroys our Vulkan app.
+    pub(crate) unsafe fn destroy(&mut self) {
+        self.device.device_wait_idle().unwrap();
+        if let Err(e) = save_pipeline_cache(&self.device, &self.data) {
+            warn!("{}", e);
+        }
+
+        // Dropping the app data hands every object to the deletion queue.
+        let data = std::mem::take(&mut self.data);
+        let (surface, messenger, validation) = (data.surface, data.messenger, data.config.validation);
+        let deletion_queue = data.deletion_queue.clone();
+        let allocator = data.allocator.clone();
+        drop(data);
+        deletion_queue.flush(&self.device, &allocator);
+        allocator.destroy(&self.device);
 
         self.device.destroy_device(None);
-        self.instance.destroy_surface_khr(self.data.surface, None);
-        if VALIDATION_ENABLED {
-            self.instance.destroy_debug_utils_messenger_ext(self.data.messenger, None);
+        self.instance.destroy_surface_khr(surface, None);
+        if validation {
+            self.instance.destroy_debug_utils_messenger_ext(messenger, None);
         }
         self.instance.destroy_instance(None);
     }
 
+    /// Destroys the objects depending on the swapchain. The device must be idle.
     unsafe fn destroy_swapchain(&mut self) {
-        self.device.destroy_image_view(self.data.depth_image_view, None);
-        self.device.free_memory(self.data.depth_image_memory, None);
-        self.device.destroy_image(self.data.depth_image, None);
-        self.device.destroy_descriptor_pool(self.data.descriptor_pool, None);
-        self.data.uniform_buffers
-            .iter()
-            .for_each(|b| self.device.destroy_buffer(*b, None));
-        self.data.uniform_buffers_memory
-            .iter()
-            .for_each(|m| self.device.free_memory(*m, None));
-        self.data.framebuffers
-            .iter()
-            .for_each(|f| self.device.destroy_framebuffer(*f, None));
-        self.device.free_command_buffers(self.data.command_pool, &self.data.command_buffers);
-        self.device.destroy_pipeline(self.data.pipeline, None);
-        self.device.destroy_pipeline_layout(self.data.pipeline_layout, None);
-        self.device.destroy_render_pass(self.data.render_pass, None);
-        self.data.swapchain_image_views
-            .iter()
-            .for_each(|v| self.device.destroy_image_view(*v, None));
-        self.device.destroy_swapchain_khr(self.data.swapchain, None);
+        if self.data.render_path == RenderPath::Deferred {
+            release_deferred_swapchain_objects(&mut self.data);
+        }
+        release_sky_swapchain_objects(&mut self.data);
+        let data = &mut self.data;
+        data.color_image_view = ImageView::default();
+        data.color_image_memory = Memory::default();
+        data.color_image = Image::default();
+        data.depth_image_view = ImageView::default();
+        data.depth_image_memory = Memory::default();
+        data.depth_image = Image::default();
+        data.descriptor_pool = DescriptorPool::default();
+        data.uniform_buffers.clear();
+        data.uniform_buffers_memory.clear();
+        data.framebuffers.clear();
+        self.device.free_command_buffers(*data.command_pool, &data.command_buffers);
+        data.command_buffers.clear();
+        data.pipeline = Pipeline::default();
+        data.cutout_pipeline = Pipeline::default();
+        data.transparent_pipeline = Pipeline::default();
+        data.object_variants.clear();
+        data.debug_pipelines = DebugPipelines::default();
+        data.debug_line_pipeline = Pipeline::default();
+        data.debug_overlay_pipeline = Pipeline::default();
+        data.pipeline_layout = PipelineLayout::default();
+        data.render_pass = RenderPass::default();
+        data.render_graph = RenderGraph::default();
+        data.swapchain_image_views.clear();
+        data.swapchain = Swapchain::default();
+        data.deletion_queue.flush(&self.device, &data.allocator);
     }
 }
 
-/// The Vulkan handles and associated properties used by our Vulkan app.
-#[derive(Clone, Debug, Default)]
+/// The Vulkan objects and associated properties used by our Vulkan app.
+/// Objects are owned, dropping them queues their destruction.
+#[derive(Debug, Default)]
 pub struct AppData {
-   pub surface: vk::SurfaceKHR,
+    pub config: EngineConfig,
+    /// Destroys dropped objects once the GPU is done with them.
+    pub deletion_queue: DeletionQueue,
+    /// Sub-allocates all device memory.
+    pub allocator: Allocator,
+    /// Batches uploads to device local buffers and images.
+    pub uploader: Uploader,
+    pub surface: vk::SurfaceKHR,
     pub messenger: vk::DebugUtilsMessengerEXT,
     pub physical_device: vk::PhysicalDevice,
     pub graphics_queue: vk::Queue,
     pub present_queue: vk::Queue,
+    /// Null when the device has no compute family without graphics.
+    pub compute_queue: vk::Queue,
+    /// Null when the device has no separate transfer family.
+    pub transfer_queue: vk::Queue,
 
     pub swapchain_format: vk::Format,
     pub swapchain_extent: vk::Extent2D,
-    pub swapchain: vk::SwapchainKHR,
+    pub swapchain: Swapchain,
     pub swapchain_images: Vec<vk::Image>,
-    pub swapchain_image_views: Vec<vk::ImageView>,
-
-    pub render_pass: vk::RenderPass,
-    pub descriptor_set_layout: vk::DescriptorSetLayout,
-    pub pipeline_layout: vk::PipelineLayout,
-    pub pipeline: vk::Pipeline,
-
-    pub framebuffers: Vec<vk::Framebuffer>,
-
-    pub command_pool: vk::CommandPool,
-    pub transient_command_pool: vk::CommandPool,
+    pub swapchain_image_views: Vec<ImageView>,
+
+    /// Null with `dynamic_rendering`.
+    pub render_pass: RenderPass,
+    /// Whether the device has dynamic rendering and `synchronization2` and
+    /// the config asked for them. Only used by the forward path.
+    pub dynamic_rendering: bool,
+    /// Attachment formats pipelines are created against with `dynamic_rendering`.
+    pub rendering_formats: RenderingFormats,
+    /// Passes of the frame with `dynamic_rendering`, which owns their
+    /// attachments. `swapchain_target` is imported into it.
+    pub render_graph: RenderGraph,
+    pub swapchain_target: ImageId,
+    pub descriptor_set_layout: DescriptorSetLayout,
+    /// Bindings and push constants of the built in forward shaders, which
+    /// `descriptor_set_layout` and `pipeline_layout` are created from.
+    pub object_interface: ShaderInterface,
+    /// Shared by every pipeline, see `EngineConfig::pipeline_cache`.
+    pub pipeline_cache: PipelineCache,
+    pub pipeline_layout: PipelineLayout,
+    pub pipeline: Pipeline,
+    pub cutout_pipeline: Pipeline,
+    pub transparent_pipeline: Pipeline,
+    /// Selected by the game, see `Context::object_constants`.
+    pub object_constants: ShaderConstants,
+    /// The constants `pipeline`, `cutout_pipeline` and `transparent_pipeline`
+    /// were created with.
+    pub object_pipelines_constants: ShaderConstants,
+    /// Object pipelines of constants used before, least recently used first,
+    /// until the object pipelines are recreated.
+    pub object_variants: Vec<(ShaderConstants, ObjectPipelines)>,
+    /// Sample count of the scene color and depth attachments.
+    pub msaa_samples: vk::SampleCountFlags,
+
+    /// Empty with `dynamic_rendering`.
+    pub framebuffers: Vec<Framebuffer>,
+
+    pub command_pool: CommandPool,
+    pub transient_command_pool: CommandPool,
+    pub transfer_command_pool: CommandPool,
+    /// One per frame in flight, like the uniform buffers and descriptor sets.
     pub command_buffers: Vec<vk::CommandBuffer>,
-    pub transient_command_buffers: Vec<vk::CommandBuffer>,
 
-    pub image_available_semaphores: Vec<vk::Semaphore>,
-    pub render_finished_semaphores: Vec<vk::Semaphore>,
+    pub image_available_semaphores: Vec<Semaphore>,
+    pub render_finished_semaphores: Vec<Semaphore>,
 
-    pub in_flight_fences: Vec<vk::Fence>,
+    /// Empty with `timeline_semaphores`, which pace frames with `frame_timeline`.
+    pub in_flight_fences: Vec<Fence>,
+    /// Borrowed from `in_flight_fences`.
     pub images_in_flight: Vec<vk::Fence>,
+    /// Whether the device has timeline semaphores and the config asked for them.
+    pub timeline_semaphores: bool,
+    /// Signaled with `n + 1` when frame `n` finishes.
+    pub frame_timeline: Timeline,
 
     //pub vertex_buffer: vk::Buffer,
     //pub vertex_buffer_memory: vk::DeviceMemory,
-    pub index_buffer: vk::Buffer,
-    pub index_buffer_memory: vk::DeviceMemory,
-    pub uniform_buffers: Vec<vk::Buffer>,
-    pub uniform_buffers_memory: Vec<vk::DeviceMemory>,
-    pub descriptor_pool: vk::DescriptorPool,
+    pub index_buffer: Buffer,
+    pub index_buffer_memory: Memory,
+    pub uniform_buffers: Vec<Buffer>,
+    pub uniform_buffers_memory: Vec<Memory>,
+    pub descriptor_pool: DescriptorPool,
     pub descriptor_sets: Vec<vk::DescriptorSet>,
+    /// Per frame, whether its descriptor set still reads a texture replaced
+    /// by `Uploads::load_texture`.
+    pub stale_texture_descriptors: Vec<bool>,
 
     pub mip_levels: u32,
-    pub texture_image: vk::Image,
-    pub texture_image_memory: vk::DeviceMemory,
-    pub  texture_image_view: vk::ImageView,
-    pub texture_sampler: vk::Sampler,
-
-    pub depth_image: vk::Image,
-    pub depth_image_memory: vk::DeviceMemory,
-    pub depth_image_view: vk::ImageView,
+    pub texture_image: Image,
+    pub texture_image_memory: Memory,
+    pub  texture_image_view: ImageView,
+    pub texture_sampler: Sampler,
+
+    pub depth_image: Image,
+    pub depth_image_memory: Memory,
+    pub depth_image_view: ImageView,
+    /// Multisampled color target of the forward path, resolved into the
+    /// swapchain image. Null without MSAA.
+    pub color_image: Image,
+    pub color_image_memory: Memory,
+    pub color_image_view: ImageView,
     //pub vertex_data     : VertexData
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
-    pub vertex_buffer: vk::Buffer,
-    pub vertex_buffer_memory: vk::DeviceMemory,
+    pub vertex_buffer: Buffer,
+    pub vertex_buffer_memory: Memory,
+    /// Buffers of the streamed models, `Mesh::geometry` minus one.
+    pub geometry: Vec<Geometry>,
+    pub uploads: Uploads,
+
+    pub render_path: RenderPath,
+    pub deferred: DeferredData,
+    pub sun: DirectionalLight,
+    pub point_lights: Vec<PointLight>,
+    pub sky: SkyData,
+    pub objects: Vec<SceneObject>,
+
+    pub fill_mode_non_solid: bool,
+    pub debug_view: DebugView,
+    pub debug_pipelines: DebugPipelines,
+    pub debug_draw: DebugDraw,
+    pub camera: Camera,
+    pub meshes: Vec<Mesh>,
+    /// Replacements for the forward shaders, from `EngineBuilder::forward_shaders`.
+    pub forward_shaders: Option<ShaderPaths>,
+    /// Compiled shaders and the pipelines using them.
+    pub shaders: Shaders,
+    pub debug_line_pipeline: Pipeline,
+    pub debug_overlay_pipeline: Pipeline,
+    /// Per frame in flight memory for data written every frame.
+    pub frame_arenas: Vec<FrameArena>,
+    pub compute: Compute,
+}
+
+/// Creates the render pass, pipelines and attachments that depend on the
+/// swapchain for the selected render path. Dynamic rendering needs neither
+/// render pass nor framebuffers, its render graph creates the attachments.
+unsafe fn create_render_targets(instance: &Instance, device: &Device, data: &mut AppData) -> anyhow::Result<()> {
+    match data.render_path {
+        RenderPath::Forward if data.dynamic_rendering => {
+            create_rendering_formats(instance, data)?;
+            create_pipeline(device, data)?;
+            create_forward_graph(device, data)?;
+        }
+        RenderPath::Forward => {
+            create_render_pass(instance, device, data)?;
+            create_pipeline(device, data)?;
+            create_color_objects(instance, device, data)?;
+            create_depth_objects(instance, device, data)?;
+        }
+        RenderPath::Deferred => {
+            create_deferred_render_pass(instance, device, data)?;
+            create_deferred_pipelines(device, data)?;
+            create_depth_objects(instance, device, data)?;
+            create_gbuffer_objects(instance, device, data)?;
+        }
+    }
+    create_sky_pipeline(device, data)?;
+    create_debug_pipelines(device, data)?;
+    create_debug_line_pipelines(device, data)?;
+    if !data.dynamic_rendering {
+        create_framebuffers(device, data)?;
+    }
+    Ok(())
+}
+
+/// Creates the light uniform buffers and the descriptor sets reading the
+/// G-buffer. Only needed by the deferred path.
+unsafe fn create_lighting_objects(instance: &Instance, device: &Device, data: &mut AppData) -> anyhow::Result<()> {
+    if data.render_path == RenderPath::Deferred {
+        create_light_buffers(instance, device, data)?;
+        create_lighting_descriptor_sets(device, data)?;
+    }
+    Ok(())
 }
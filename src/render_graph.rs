@@ -0,0 +1,1109 @@
+use std::fmt;
+use anyhow::{anyhow, Result};
+use vulkanalia::{vk, Device};
+use vulkanalia::vk::{DeviceV1_0, DeviceV1_3, Handle, HasBuilder};
+use crate::image_util::create_image_view;
+use crate::memory_util::{ResourceKind, Strategy};
+use crate::render_app::AppData;
+use crate::resource_util::{Buffer, Image, ImageView, Memory};
+use crate::transforms::Mat4;
+
+/// Access flags that modify memory. Accesses without any of them are reads.
+const WRITE_ACCESS: vk::AccessFlags = vk::AccessFlags::from_bits_truncate(
+    vk::AccessFlags::COLOR_ATTACHMENT_WRITE.bits()
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE.bits()
+        | vk::AccessFlags::SHADER_WRITE.bits()
+        | vk::AccessFlags::TRANSFER_WRITE.bits(),
+);
+
+/// An image of a `RenderGraph`, transient or imported.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImageId(usize);
+
+/// A buffer of a `RenderGraph`, transient or imported.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BufferId(usize);
+
+/// How a pass uses an image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageAccess {
+    ColorAttachment,
+    DepthAttachment,
+    /// Depth tested against without writing.
+    DepthRead,
+    /// Sampled in fragment or compute shaders.
+    Sampled,
+    StorageRead,
+    StorageWrite,
+    TransferSrc,
+    TransferDst,
+}
+
+impl ImageAccess {
+    /// Stages, access, layout and the usage the image needs for it.
+    fn info(self) -> (vk::PipelineStageFlags, vk::AccessFlags, vk::ImageLayout, vk::ImageUsageFlags) {
+        let fragment_tests = vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let shaders = vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER;
+        match self {
+            Self::ColorAttachment => (
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            ),
+            Self::DepthAttachment => (
+                fragment_tests,
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            ),
+            Self::DepthRead => (
+                fragment_tests,
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
+                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            ),
+            Self::Sampled => (
+                shaders,
+                vk::AccessFlags::SHADER_READ,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::ImageUsageFlags::SAMPLED,
+            ),
+            Self::StorageRead => (
+                shaders,
+                vk::AccessFlags::SHADER_READ,
+                vk::ImageLayout::GENERAL,
+                vk::ImageUsageFlags::STORAGE,
+            ),
+            Self::StorageWrite => (
+                shaders,
+                vk::AccessFlags::SHADER_WRITE,
+                vk::ImageLayout::GENERAL,
+                vk::ImageUsageFlags::STORAGE,
+            ),
+            Self::TransferSrc => (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageUsageFlags::TRANSFER_SRC,
+            ),
+            Self::TransferDst => (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageUsageFlags::TRANSFER_DST,
+            ),
+        }
+    }
+}
+
+/// How a pass uses a buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BufferAccess {
+    Vertex,
+    Index,
+    Indirect,
+    Uniform,
+    StorageRead,
+    StorageWrite,
+    TransferSrc,
+    TransferDst,
+}
+
+impl BufferAccess {
+    /// Stages, access and the usage the buffer needs for it.
+    fn info(self) -> (vk::PipelineStageFlags, vk::AccessFlags, vk::BufferUsageFlags) {
+        let shaders = vk::PipelineStageFlags::VERTEX_SHADER
+            | vk::PipelineStageFlags::FRAGMENT_SHADER
+            | vk::PipelineStageFlags::COMPUTE_SHADER;
+        match self {
+            Self::Vertex => (
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+            ),
+            Self::Index => (
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::INDEX_READ,
+                vk::BufferUsageFlags::INDEX_BUFFER,
+            ),
+            Self::Indirect => (
+                vk::PipelineStageFlags::DRAW_INDIRECT,
+                vk::AccessFlags::INDIRECT_COMMAND_READ,
+                vk::BufferUsageFlags::INDIRECT_BUFFER,
+            ),
+            Self::Uniform => (shaders, vk::AccessFlags::UNIFORM_READ, vk::BufferUsageFlags::UNIFORM_BUFFER),
+            Self::StorageRead => (shaders, vk::AccessFlags::SHADER_READ, vk::BufferUsageFlags::STORAGE_BUFFER),
+            Self::StorageWrite => (shaders, vk::AccessFlags::SHADER_WRITE, vk::BufferUsageFlags::STORAGE_BUFFER),
+            Self::TransferSrc => (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+            ),
+            Self::TransferDst => (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::BufferUsageFlags::TRANSFER_DST,
+            ),
+        }
+    }
+}
+
+/// A transient image, created by the graph at `RenderGraph::compile` and
+/// only valid between its first and last use.
+#[derive(Copy, Clone, Debug)]
+pub struct ImageDesc {
+    pub width: u32,
+    pub height: u32,
+    pub format: vk::Format,
+    pub samples: vk::SampleCountFlags,
+    /// Both depth and stencil for combined formats, views only see depth.
+    pub aspect: vk::ImageAspectFlags,
+}
+
+/// An imported resource for one `RenderGraph::execute`.
+#[derive(Copy, Clone, Debug)]
+pub enum Import {
+    Image(ImageId, vk::Image, vk::ImageView),
+    Buffer(BufferId, vk::Buffer),
+}
+
+#[derive(Copy, Clone, Debug)]
+enum ImageKind {
+    Transient(ImageDesc),
+    /// Bound every frame, see `RenderGraph::import_image`.
+    Imported { initial_layout: vk::ImageLayout, final_layout: vk::ImageLayout },
+}
+
+#[derive(Debug)]
+struct GraphImage {
+    name: String,
+    aspect: vk::ImageAspectFlags,
+    kind: ImageKind,
+}
+
+#[derive(Debug)]
+struct GraphBuffer {
+    name: String,
+    /// Size of a transient buffer, `None` for imported ones.
+    size: Option<vk::DeviceSize>,
+}
+
+/// What a pass records, called once per `RenderGraph::execute`.
+pub type RecordFn = Box<dyn Fn(&PassContext)>;
+
+struct Pass {
+    name: String,
+    images: Vec<(ImageId, ImageAccess)>,
+    buffers: Vec<(BufferId, BufferAccess)>,
+    record: RecordFn,
+}
+
+impl fmt::Debug for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pass")
+            .field("name", &self.name)
+            .field("images", &self.images)
+            .field("buffers", &self.buffers)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Declares the resources of a pass, returned by `RenderGraph::add_pass`.
+pub struct PassBuilder<'a> {
+    pass: &'a mut Pass,
+}
+
+impl PassBuilder<'_> {
+    pub fn image(self, image: ImageId, access: ImageAccess) -> Self {
+        self.pass.images.push((image, access));
+        self
+    }
+
+    pub fn buffer(self, buffer: BufferId, access: BufferAccess) -> Self {
+        self.pass.buffers.push((buffer, access));
+        self
+    }
+
+    pub fn record(self, record: impl Fn(&PassContext) + 'static) {
+        self.pass.record = Box::new(record);
+    }
+}
+
+/// What a pass gets to record its commands with.
+pub struct PassContext<'a> {
+    pub device: &'a Device,
+    pub data: &'a AppData,
+    pub command_buffer: vk::CommandBuffer,
+    pub frame: usize,
+    pub image_index: usize,
+    /// View matrix of the frame.
+    pub view: Mat4,
+    images: &'a [(vk::Image, vk::ImageView)],
+    buffers: &'a [vk::Buffer],
+}
+
+impl PassContext<'_> {
+    pub fn image(&self, image: ImageId) -> vk::Image {
+        self.images[image.0].0
+    }
+
+    pub fn image_view(&self, image: ImageId) -> vk::ImageView {
+        self.images[image.0].1
+    }
+
+    pub fn buffer(&self, buffer: BufferId) -> vk::Buffer {
+        self.buffers[buffer.0]
+    }
+}
+
+/// A pass's use of one resource, all declared accesses merged.
+#[derive(Copy, Clone, Debug)]
+struct Use {
+    stages: vk::PipelineStageFlags,
+    access: vk::AccessFlags,
+    layout: vk::ImageLayout,
+}
+
+impl Use {
+    fn writes(&self) -> bool {
+        self.access.intersects(WRITE_ACCESS)
+    }
+}
+
+/// The accesses a resource has to be synchronized against.
+#[derive(Copy, Clone, Debug, Default)]
+struct State {
+    layout: vk::ImageLayout,
+    /// Stages and access of the last write, or of the last layout transition.
+    write_stages: vk::PipelineStageFlags,
+    write_access: vk::AccessFlags,
+    /// Stages that read since, already synchronized with the write.
+    read_stages: vk::PipelineStageFlags,
+}
+
+impl State {
+    fn stages(&self) -> vk::PipelineStageFlags {
+        self.write_stages | self.read_stages
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ImageBarrier {
+    image: ImageId,
+    src_stages: vk::PipelineStageFlags,
+    dst_stages: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct BufferBarrier {
+    buffer: BufferId,
+    src_stages: vk::PipelineStageFlags,
+    dst_stages: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+}
+
+/// The barriers recorded with one `cmd_pipeline_barrier2`.
+#[derive(Clone, Debug, Default)]
+struct Barriers {
+    images: Vec<ImageBarrier>,
+    buffers: Vec<BufferBarrier>,
+}
+
+/// Stages a barrier between `state` and a use in `stages` waits for and
+/// blocks.
+fn barrier_stages(state: &State, stages: vk::PipelineStageFlags) -> (vk::PipelineStageFlags, vk::PipelineStageFlags) {
+    let src_stages = state.stages();
+    let src_stages = if src_stages.is_empty() { vk::PipelineStageFlags::TOP_OF_PIPE } else { src_stages };
+    (src_stages, stages)
+}
+
+/// The synchronization2 flags share their bits with the original ones.
+fn stages2(stages: vk::PipelineStageFlags) -> vk::PipelineStageFlags2 {
+    vk::PipelineStageFlags2::from_bits_truncate(stages.bits() as u64)
+}
+
+fn access2(access: vk::AccessFlags) -> vk::AccessFlags2 {
+    vk::AccessFlags2::from_bits_truncate(access.bits() as u64)
+}
+
+/// Transient resources whose lifetimes don't overlap share one allocation.
+#[derive(Debug)]
+struct Slot {
+    kind: ResourceKind,
+    requirements: vk::MemoryRequirements,
+    lifetimes: Vec<(usize, usize)>,
+}
+
+/// Puts each transient into a slot of memory shared with those whose
+/// lifetimes don't overlap, largest first, each into the first slot it fits.
+/// `allocatable` tells whether memory with the merged requirements exists.
+/// Returns the slots and the slot of each resource.
+fn assign_slots(
+    transients: &mut [(usize, ResourceKind, vk::MemoryRequirements)],
+    lifetimes: &[Option<(usize, usize)>],
+    allocatable: impl Fn(vk::MemoryRequirements) -> bool,
+) -> (Vec<Slot>, Vec<Option<usize>>) {
+    transients.sort_by_key(|(_, _, requirements)| std::cmp::Reverse(requirements.size));
+    let mut slots = Vec::<Slot>::new();
+    let mut resource_slots = vec![None; lifetimes.len()];
+    for (resource, kind, requirements) in transients.iter() {
+        let (first, last) = lifetimes[*resource].unwrap();
+        let fits = |slot: &Slot| {
+            let mut merged = slot.requirements;
+            merged.memory_type_bits &= requirements.memory_type_bits;
+            slot.kind == *kind
+                && allocatable(merged)
+                && slot.lifetimes.iter().all(|(f, l)| last < *f || first > *l)
+        };
+        let index = match slots.iter().position(fits) {
+            Some(index) => index,
+            None => {
+                slots.push(Slot {
+                    kind: *kind,
+                    requirements: *requirements,
+                    lifetimes: Vec::new(),
+                });
+                slots.len() - 1
+            }
+        };
+        let slot = &mut slots[index];
+        slot.requirements.size = slot.requirements.size.max(requirements.size);
+        slot.requirements.alignment = slot.requirements.alignment.max(requirements.alignment);
+        slot.requirements.memory_type_bits &= requirements.memory_type_bits;
+        slot.lifetimes.push((first, last));
+        resource_slots[*resource] = Some(index);
+    }
+    (slots, resource_slots)
+}
+
+/// Passes declare which images and buffers they read and write. `compile`
+/// derives the execution order from those declarations, culls passes that
+/// contribute nothing to an imported resource, creates the transient
+/// resources with memory aliased between those that are never alive at the
+/// same time, and precomputes every layout transition and barrier.
+/// `execute` then records them around the passes.
+///
+/// A pass sees the writes of the passes declared before it. Imported
+/// resources, e.g. the swapchain image, are bound per frame.
+///
+/// The engine's own passes only use images and a few accesses so far, the
+/// rest is there for shadow, post-process and UI passes.
+#[derive(Debug, Default)]
+pub struct RenderGraph {
+    images: Vec<GraphImage>,
+    buffers: Vec<GraphBuffer>,
+    passes: Vec<Pass>,
+
+    order: Vec<usize>,
+    /// Recorded before each pass of `order`.
+    barriers: Vec<Barriers>,
+    /// Moves imported images into their final layout.
+    final_barriers: Barriers,
+    /// Per `ImageId` and `BufferId`, null for imported resources.
+    image_handles: Vec<(vk::Image, vk::ImageView)>,
+    buffer_handles: Vec<vk::Buffer>,
+    transient_images: Vec<Image>,
+    transient_views: Vec<ImageView>,
+    transient_buffers: Vec<Buffer>,
+    memory: Vec<Memory>,
+}
+
+impl RenderGraph {
+    pub fn transient_image(&mut self, name: &str, desc: ImageDesc) -> ImageId {
+        self.images.push(GraphImage { name: name.into(), aspect: desc.aspect, kind: ImageKind::Transient(desc) });
+        ImageId(self.images.len() - 1)
+    }
+
+    /// An image the graph doesn't own. It starts each frame in
+    /// `initial_layout`, `UNDEFINED` discards its contents, and is left in
+    /// `final_layout`, `UNDEFINED` leaves it in the layout of its last use.
+    pub fn import_image(
+        &mut self,
+        name: &str,
+        aspect: vk::ImageAspectFlags,
+        initial_layout: vk::ImageLayout,
+        final_layout: vk::ImageLayout,
+    ) -> ImageId {
+        let kind = ImageKind::Imported { initial_layout, final_layout };
+        self.images.push(GraphImage { name: name.into(), aspect, kind });
+        ImageId(self.images.len() - 1)
+    }
+
+    pub fn transient_buffer(&mut self, name: &str, size: vk::DeviceSize) -> BufferId {
+        self.buffers.push(GraphBuffer { name: name.into(), size: Some(size) });
+        BufferId(self.buffers.len() - 1)
+    }
+
+    pub fn import_buffer(&mut self, name: &str) -> BufferId {
+        self.buffers.push(GraphBuffer { name: name.into(), size: None });
+        BufferId(self.buffers.len() - 1)
+    }
+
+    pub fn add_pass(&mut self, name: &str) -> PassBuilder<'_> {
+        self.passes.push(Pass {
+            name: name.into(),
+            images: Vec::new(),
+            buffers: Vec::new(),
+            record: Box::new(|_| {}),
+        });
+        PassBuilder { pass: self.passes.last_mut().unwrap() }
+    }
+
+    /// Names of the passes in the order `execute` runs them.
+    pub fn pass_order(&self) -> Vec<&str> {
+        self.order.iter().map(|p| self.passes[*p].name.as_str()).collect()
+    }
+
+    /// Orders the passes, creates the transient resources and computes the
+    /// barriers. Resources of an earlier compile are released.
+    pub(crate) unsafe fn compile(&mut self, device: &Device, data: &AppData) -> Result<()> {
+        self.order = self.schedule();
+        let uses = self.uses()?;
+        let lifetimes = self.lifetimes(&uses);
+        let slots = self.create_transients(device, data, &lifetimes)?;
+        self.check_transient_reads(&slots)?;
+        self.compute_barriers(&uses, slots);
+        Ok(())
+    }
+
+    /// Records the barriers and passes into `command_buffer`. Every imported
+    /// resource has to be bound by `imports`. The frame's values are handed
+    /// to the passes in their `PassContext`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) unsafe fn execute(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        image_index: usize,
+        view: Mat4,
+        imports: &[Import],
+    ) -> Result<()> {
+        let mut images = self.image_handles.clone();
+        let mut buffers = self.buffer_handles.clone();
+        for import in imports {
+            match *import {
+                Import::Image(id, image, image_view) => images[id.0] = (image, image_view),
+                Import::Buffer(id, buffer) => buffers[id.0] = buffer,
+            }
+        }
+        if let Some(missing) = images.iter().position(|(image, _)| image.is_null()) {
+            return Err(anyhow!("Render graph image `{}` is not bound.", self.images[missing].name));
+        }
+        if let Some(missing) = buffers.iter().position(|buffer| buffer.is_null()) {
+            return Err(anyhow!("Render graph buffer `{}` is not bound.", self.buffers[missing].name));
+        }
+
+        let context = PassContext {
+            device,
+            data,
+            command_buffer,
+            frame,
+            image_index,
+            view,
+            images: &images,
+            buffers: &buffers,
+        };
+        for (pass, barriers) in self.order.iter().zip(&self.barriers) {
+            self.record_barriers(device, command_buffer, barriers, &images, &buffers);
+            (self.passes[*pass].record)(&context);
+        }
+        self.record_barriers(device, command_buffer, &self.final_barriers, &images, &buffers);
+        Ok(())
+    }
+
+    /// Resource index of every access, images first and buffers after them.
+    fn accesses(&self, pass: &Pass) -> Vec<(usize, bool)> {
+        let images = pass.images.iter().map(|(id, access)| {
+            let (_, flags, _, _) = access.info();
+            (id.0, flags.intersects(WRITE_ACCESS))
+        });
+        let buffers = pass.buffers.iter().map(|(id, access)| {
+            let (_, flags, _) = access.info();
+            (self.images.len() + id.0, flags.intersects(WRITE_ACCESS))
+        });
+        images.chain(buffers).collect()
+    }
+
+    fn is_imported(&self, resource: usize) -> bool {
+        match self.images.get(resource) {
+            Some(image) => matches!(image.kind, ImageKind::Imported { .. }),
+            None => self.buffers[resource - self.images.len()].size.is_none(),
+        }
+    }
+
+    /// Culls passes that neither write an imported resource nor feed one
+    /// that does, and orders the others. Among the passes whose
+    /// dependencies have run, one that doesn't depend on the pass just
+    /// scheduled goes first, so its barrier has more work to overlap with.
+    fn schedule(&self) -> Vec<usize> {
+        #[derive(Clone, Default)]
+        struct Users {
+            writer: Option<usize>,
+            readers: Vec<usize>,
+        }
+
+        let count = self.passes.len();
+        let mut users = vec![Users::default(); self.images.len() + self.buffers.len()];
+        // Reads and overwrites of a write, which pass their result on.
+        let mut data_dependencies = vec![Vec::new(); count];
+        // Writes after reads, which only constrain the order.
+        let mut order_dependencies = vec![Vec::new(); count];
+        let mut live = vec![false; count];
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            for (resource, writes) in self.accesses(pass) {
+                let users = &mut users[resource];
+                data_dependencies[index].extend(users.writer.filter(|w| *w != index));
+                if writes {
+                    order_dependencies[index].extend(users.readers.drain(..).filter(|r| *r != index));
+                    users.writer = Some(index);
+                    live[index] |= self.is_imported(resource);
+                } else {
+                    users.readers.push(index);
+                }
+            }
+        }
+
+        let mut stack = (0..count).filter(|p| live[*p]).collect::<Vec<_>>();
+        while let Some(pass) = stack.pop() {
+            for dependency in &data_dependencies[pass] {
+                if !live[*dependency] {
+                    live[*dependency] = true;
+                    stack.push(*dependency);
+                }
+            }
+        }
+
+        let dependencies = (0..count)
+            .map(|p| {
+                let mut d = data_dependencies[p].iter().chain(&order_dependencies[p])
+                    .copied()
+                    .filter(|d| live[*d])
+                    .collect::<Vec<_>>();
+                d.sort_unstable();
+                d.dedup();
+                d
+            })
+            .collect::<Vec<_>>();
+
+        let mut scheduled = vec![false; count];
+        let mut order = Vec::new();
+        let mut remaining = (0..count).filter(|p| live[*p]).collect::<Vec<_>>();
+        while !remaining.is_empty() {
+            let ready = remaining
+                .iter()
+                .copied()
+                .filter(|p| dependencies[*p].iter().all(|d| scheduled[*d]))
+                .collect::<Vec<_>>();
+            // Dependencies always point at earlier passes, so the first
+            // remaining pass is ready.
+            let last = order.last();
+            let next = ready
+                .iter()
+                .copied()
+                .find(|p| !last.is_some_and(|l| dependencies[*p].contains(l)))
+                .unwrap_or(ready[0]);
+            scheduled[next] = true;
+            order.push(next);
+            remaining.retain(|p| *p != next);
+        }
+        order
+    }
+
+    /// The merged use of every resource by each pass of `order`.
+    fn uses(&self) -> Result<Vec<Vec<(usize, Use)>>> {
+        let mut all = Vec::new();
+        for pass in self.order.iter().map(|p| &self.passes[*p]) {
+            let images = pass.images.iter().map(|(id, access)| {
+                let (stages, access, layout, _) = access.info();
+                (id.0, Use { stages, access, layout })
+            });
+            let buffers = pass.buffers.iter().map(|(id, access)| {
+                let (stages, access, _) = access.info();
+                (self.images.len() + id.0, Use { stages, access, layout: vk::ImageLayout::UNDEFINED })
+            });
+
+            let mut uses = Vec::<(usize, Use)>::new();
+            for (resource, new) in images.chain(buffers) {
+                match uses.iter_mut().find(|(r, _)| *r == resource) {
+                    Some((_, merged)) if merged.layout != new.layout => {
+                        return Err(anyhow!(
+                            "Pass `{}` uses `{}` in layouts {:?} and {:?}.",
+                            pass.name, self.images[resource].name, merged.layout, new.layout,
+                        ));
+                    }
+                    Some((_, merged)) => {
+                        merged.stages |= new.stages;
+                        merged.access |= new.access;
+                    }
+                    None => uses.push((resource, new)),
+                }
+            }
+            all.push(uses);
+        }
+        Ok(all)
+    }
+
+    /// First and last position in `order` at which each resource is used.
+    fn lifetimes(&self, uses: &[Vec<(usize, Use)>]) -> Vec<Option<(usize, usize)>> {
+        let mut lifetimes = vec![None::<(usize, usize)>; self.images.len() + self.buffers.len()];
+        for (position, pass_uses) in uses.iter().enumerate() {
+            for (resource, _) in pass_uses {
+                let lifetime = lifetimes[*resource].get_or_insert((position, position));
+                lifetime.1 = position;
+            }
+        }
+        lifetimes
+    }
+
+    /// Creates the transient resources used by the scheduled passes and
+    /// binds them to shared memory. Returns the slot of each resource.
+    unsafe fn create_transients(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+        lifetimes: &[Option<(usize, usize)>],
+    ) -> Result<Vec<Option<usize>>> {
+        self.image_handles = vec![Default::default(); self.images.len()];
+        self.buffer_handles = vec![Default::default(); self.buffers.len()];
+        self.transient_images.clear();
+        self.transient_views.clear();
+        self.transient_buffers.clear();
+        self.memory.clear();
+
+        let mut usages = vec![vk::ImageUsageFlags::empty(); self.images.len()];
+        let mut buffer_usages = vec![vk::BufferUsageFlags::empty(); self.buffers.len()];
+        for pass in self.order.iter().map(|p| &self.passes[*p]) {
+            for (id, access) in &pass.images {
+                usages[id.0] |= access.info().3;
+            }
+            for (id, access) in &pass.buffers {
+                buffer_usages[id.0] |= access.info().2;
+            }
+        }
+
+        // (resource, kind, requirements) of every transient in use.
+        let mut transients = Vec::new();
+        for (index, image) in self.images.iter().enumerate() {
+            let ImageKind::Transient(desc) = image.kind else { continue };
+            if lifetimes[index].is_none() {
+                continue;
+            }
+            let info = vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::_2D)
+                .extent(vk::Extent3D { width: desc.width, height: desc.height, depth: 1 })
+                .mip_levels(1)
+                .array_layers(1)
+                .format(desc.format)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .usage(usages[index])
+                .samples(desc.samples)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            let handle = data.deletion_queue.own(device.create_image(&info, None)?);
+            let requirements = device.get_image_memory_requirements(*handle);
+            self.image_handles[index].0 = *handle;
+            self.transient_images.push(handle);
+            transients.push((index, ResourceKind::Image, requirements));
+        }
+        for (index, buffer) in self.buffers.iter().enumerate() {
+            let resource = self.images.len() + index;
+            let Some(size) = buffer.size else { continue };
+            if lifetimes[resource].is_none() {
+                continue;
+            }
+            let info = vk::BufferCreateInfo::builder()
+                .size(size)
+                .usage(buffer_usages[index])
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            let handle = data.deletion_queue.own(device.create_buffer(&info, None)?);
+            let requirements = device.get_buffer_memory_requirements(*handle);
+            self.buffer_handles[index] = *handle;
+            self.transient_buffers.push(handle);
+            transients.push((resource, ResourceKind::Buffer, requirements));
+        }
+
+        let (slots, resource_slots) = assign_slots(&mut transients, lifetimes, |requirements| {
+            data.allocator.memory_type_index(vk::MemoryPropertyFlags::DEVICE_LOCAL, requirements).is_ok()
+        });
+        for slot in &slots {
+            let memory = data.deletion_queue.own(data.allocator.allocate(
+                device,
+                slot.requirements,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                slot.kind,
+                Strategy::FreeList,
+            )?);
+            self.memory.push(memory);
+        }
+        for (resource, kind, _) in &transients {
+            let memory = &self.memory[resource_slots[*resource].unwrap()];
+            match kind {
+                ResourceKind::Image => {
+                    let image = self.image_handles[*resource].0;
+                    device.bind_image_memory(image, memory.memory, memory.offset)?;
+                    let ImageKind::Transient(desc) = self.images[*resource].kind else { unreachable!() };
+                    let aspect = if desc.aspect.contains(vk::ImageAspectFlags::DEPTH) {
+                        vk::ImageAspectFlags::DEPTH
+                    } else {
+                        desc.aspect
+                    };
+                    let view = create_image_view(device, data, image, desc.format, aspect, 1)?;
+                    self.image_handles[*resource].1 = *view;
+                    self.transient_views.push(view);
+                }
+                ResourceKind::Buffer => {
+                    let buffer = self.buffer_handles[*resource - self.images.len()];
+                    device.bind_buffer_memory(buffer, memory.memory, memory.offset)?;
+                }
+            }
+        }
+
+        Ok(resource_slots)
+    }
+
+    /// A transient's contents are undefined until a pass writes them.
+    fn check_transient_reads(&self, resource_slots: &[Option<usize>]) -> Result<()> {
+        let mut written = vec![false; resource_slots.len()];
+        for pass in self.order.iter().map(|p| &self.passes[*p]) {
+            for (resource, writes) in self.accesses(pass) {
+                if resource_slots[resource].is_some() && !writes && !written[resource] {
+                    let name = match self.images.get(resource) {
+                        Some(image) => &image.name,
+                        None => &self.buffers[resource - self.images.len()].name,
+                    };
+                    return Err(anyhow!("Pass `{}` reads `{}` before any pass writes it.", pass.name, name));
+                }
+                written[resource] |= writes;
+            }
+        }
+        Ok(())
+    }
+
+    /// Walks the passes in order, tracking the last access of every
+    /// resource, and emits a barrier wherever a use has to wait for an
+    /// earlier one or changes the image layout.
+    fn compute_barriers(&mut self, uses: &[Vec<(usize, Use)>], resource_slots: Vec<Option<usize>>) {
+        // The next frame reuses the transients, the first resource in each
+        // slot has to wait for the last one of the frame before.
+        let slot_count = resource_slots.iter().flatten().max().map_or(0, |s| s + 1);
+        let (_, _, last) = self.walk(uses, &resource_slots, vec![State::default(); slot_count]);
+        let (barriers, final_barriers, _) = self.walk(uses, &resource_slots, last);
+        self.barriers = barriers;
+        self.final_barriers = final_barriers;
+    }
+
+    /// One frame of `compute_barriers`, starting with `slot_states`: the last
+    /// access of the latest resource in each slot, which the next one has to
+    /// wait for before reusing the memory. Returns the barriers before each
+    /// pass, the final barriers and the slot states at the end.
+    fn walk(
+        &self,
+        uses: &[Vec<(usize, Use)>],
+        resource_slots: &[Option<usize>],
+        mut slot_states: Vec<State>,
+    ) -> (Vec<Barriers>, Barriers, Vec<State>) {
+        let image_count = self.images.len();
+        let mut states = vec![None::<State>; resource_slots.len()];
+
+        let mut pass_barriers = Vec::with_capacity(uses.len());
+        for pass_uses in uses {
+            let mut barriers = Barriers::default();
+            for (resource, new) in pass_uses {
+                let state = states[*resource].get_or_insert_with(|| match resource_slots[*resource] {
+                    // The previous resource in the memory has to be done with it.
+                    Some(slot) => State { layout: vk::ImageLayout::UNDEFINED, ..slot_states[slot] },
+                    None => {
+                        let layout = match self.images.get(*resource).map(|i| i.kind) {
+                            Some(ImageKind::Imported { initial_layout, .. }) => initial_layout,
+                            _ => vk::ImageLayout::UNDEFINED,
+                        };
+                        // Previous frames may still use an imported resource
+                        // the same way.
+                        State {
+                            layout,
+                            write_stages: new.stages,
+                            write_access: new.access & WRITE_ACCESS,
+                            read_stages: vk::PipelineStageFlags::empty(),
+                        }
+                    }
+                });
+
+                let is_image = *resource < image_count;
+                let transition = is_image && state.layout != new.layout;
+                let hazard = if new.writes() {
+                    !state.stages().is_empty()
+                } else {
+                    !state.write_stages.is_empty() && !state.read_stages.contains(new.stages)
+                };
+
+                if transition || hazard {
+                    let (src_stages, dst_stages) = barrier_stages(state, new.stages);
+                    if is_image {
+                        barriers.images.push(ImageBarrier {
+                            image: ImageId(*resource),
+                            src_stages,
+                            dst_stages,
+                            src_access: state.write_access,
+                            dst_access: new.access,
+                            old_layout: state.layout,
+                            new_layout: new.layout,
+                        });
+                    } else {
+                        barriers.buffers.push(BufferBarrier {
+                            buffer: BufferId(*resource - image_count),
+                            src_stages,
+                            dst_stages,
+                            src_access: state.write_access,
+                            dst_access: new.access,
+                        });
+                    }
+                }
+
+                if new.writes() {
+                    *state = State {
+                        layout: new.layout,
+                        write_stages: new.stages,
+                        write_access: new.access & WRITE_ACCESS,
+                        read_stages: vk::PipelineStageFlags::empty(),
+                    };
+                } else if transition {
+                    // Later reads in other stages still wait for the transition.
+                    *state = State {
+                        layout: new.layout,
+                        write_stages: new.stages,
+                        write_access: vk::AccessFlags::empty(),
+                        read_stages: new.stages,
+                    };
+                } else {
+                    state.read_stages |= new.stages;
+                }
+
+                if let Some(slot) = resource_slots[*resource] {
+                    slot_states[slot] = *state;
+                }
+            }
+            pass_barriers.push(barriers);
+        }
+
+        let mut final_barriers = Barriers::default();
+        for (index, image) in self.images.iter().enumerate() {
+            let ImageKind::Imported { final_layout, .. } = image.kind else { continue };
+            let Some(state) = &states[index] else { continue };
+            if final_layout == vk::ImageLayout::UNDEFINED || final_layout == state.layout {
+                continue;
+            }
+            let (src_stages, dst_stages) = barrier_stages(state, vk::PipelineStageFlags::BOTTOM_OF_PIPE);
+            final_barriers.images.push(ImageBarrier {
+                image: ImageId(index),
+                src_stages,
+                dst_stages,
+                src_access: state.write_access,
+                dst_access: vk::AccessFlags::empty(),
+                old_layout: state.layout,
+                new_layout: final_layout,
+            });
+        }
+        (pass_barriers, final_barriers, slot_states)
+    }
+
+    /// Records `barriers` with `cmd_pipeline_barrier2` on devices set up
+    /// for dynamic rendering, which need synchronization2 anyway.
+    unsafe fn record_barriers(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        barriers: &Barriers,
+        images: &[(vk::Image, vk::ImageView)],
+        buffers: &[vk::Buffer],
+    ) {
+        if barriers.images.is_empty() && barriers.buffers.is_empty() {
+            return;
+        }
+
+        let subresource = |image: ImageId| {
+            vk::ImageSubresourceRange::builder()
+                .aspect_mask(self.images[image.0].aspect)
+                .base_mip_level(0)
+                .level_count(vk::REMAINING_MIP_LEVELS)
+                .base_array_layer(0)
+                .layer_count(vk::REMAINING_ARRAY_LAYERS)
+                .build()
+        };
+
+        let image_barriers = barriers.images
+            .iter()
+            .map(|b| {
+                vk::ImageMemoryBarrier2::builder()
+                    .src_stage_mask(stages2(b.src_stages))
+                    .src_access_mask(access2(b.src_access))
+                    .dst_stage_mask(stages2(b.dst_stages))
+                    .dst_access_mask(access2(b.dst_access))
+                    .old_layout(b.old_layout)
+                    .new_layout(b.new_layout)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(images[b.image.0].0)
+                    .subresource_range(subresource(b.image))
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let buffer_barriers = barriers.buffers
+            .iter()
+            .map(|b| {
+                vk::BufferMemoryBarrier2::builder()
+                    .src_stage_mask(stages2(b.src_stages))
+                    .src_access_mask(access2(b.src_access))
+                    .dst_stage_mask(stages2(b.dst_stages))
+                    .dst_access_mask(access2(b.dst_access))
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(buffers[b.buffer.0])
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE as u64)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let dependency_info = vk::DependencyInfo::builder()
+            .image_memory_barriers(&image_barriers)
+            .buffer_memory_barriers(&buffer_barriers);
+        device.cmd_pipeline_barrier2(command_buffer, &dependency_info);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desc() -> ImageDesc {
+        ImageDesc {
+            width: 64,
+            height: 64,
+            format: vk::Format::R8G8B8A8_UNORM,
+            samples: vk::SampleCountFlags::_1,
+            aspect: vk::ImageAspectFlags::COLOR,
+        }
+    }
+
+    fn swapchain(graph: &mut RenderGraph) -> ImageId {
+        graph.import_image(
+            "swapchain",
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )
+    }
+
+    /// `compile` without a device: every transient needs the same memory.
+    fn plan(graph: &mut RenderGraph) -> Vec<Option<usize>> {
+        graph.order = graph.schedule();
+        let uses = graph.uses().unwrap();
+        let lifetimes = graph.lifetimes(&uses);
+        let requirements = vk::MemoryRequirements { size: 1024, alignment: 256, memory_type_bits: 1 };
+        let mut transients = (0..graph.images.len())
+            .filter(|i| !graph.is_imported(*i) && lifetimes[*i].is_some())
+            .map(|i| (i, ResourceKind::Image, requirements))
+            .collect::<Vec<_>>();
+        let (_, slots) = assign_slots(&mut transients, &lifetimes, |r| r.memory_type_bits != 0);
+        graph.compute_barriers(&uses, slots.clone());
+        slots
+    }
+
+    fn barrier(graph: &RenderGraph, pass: &str, image: ImageId) -> Option<ImageBarrier> {
+        let position = graph.pass_order().iter().position(|p| *p == pass).unwrap();
+        graph.barriers[position].images.iter().find(|b| b.image == image).copied()
+    }
+
+    #[test]
+    fn passes_without_visible_output_are_culled() {
+        let mut graph = RenderGraph::default();
+        let target = swapchain(&mut graph);
+        let scratch = graph.transient_image("scratch", desc());
+        let gbuffer = graph.transient_image("gbuffer", desc());
+        graph.add_pass("unused").image(scratch, ImageAccess::ColorAttachment);
+        graph.add_pass("gbuffer").image(gbuffer, ImageAccess::ColorAttachment);
+        graph.add_pass("lighting")
+            .image(gbuffer, ImageAccess::Sampled)
+            .image(target, ImageAccess::ColorAttachment);
+        plan(&mut graph);
+        assert_eq!(graph.pass_order(), ["gbuffer", "lighting"]);
+    }
+
+    #[test]
+    fn writes_wait_for_earlier_reads() {
+        let mut graph = RenderGraph::default();
+        let target = swapchain(&mut graph);
+        let history = graph.import_image(
+            "history",
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::GENERAL,
+            vk::ImageLayout::UNDEFINED,
+        );
+        graph.add_pass("resolve")
+            .image(history, ImageAccess::Sampled)
+            .image(target, ImageAccess::ColorAttachment);
+        graph.add_pass("store").image(history, ImageAccess::StorageWrite);
+        plan(&mut graph);
+
+        assert_eq!(graph.pass_order(), ["resolve", "store"]);
+        let war = barrier(&graph, "store", history).unwrap();
+        assert!(war.src_stages.contains(vk::PipelineStageFlags::FRAGMENT_SHADER));
+        assert_eq!(war.old_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(war.new_layout, vk::ImageLayout::GENERAL);
+    }
+
+    /// `a` feeds `b` feeds `c` feeds the swapchain, so `a` and `c` are never
+    /// alive at the same time.
+    fn chain() -> (RenderGraph, [ImageId; 3]) {
+        let mut graph = RenderGraph::default();
+        let target = swapchain(&mut graph);
+        let a = graph.transient_image("a", desc());
+        let b = graph.transient_image("b", desc());
+        let c = graph.transient_image("c", desc());
+        graph.add_pass("first").image(a, ImageAccess::ColorAttachment);
+        graph.add_pass("second")
+            .image(a, ImageAccess::Sampled)
+            .image(b, ImageAccess::ColorAttachment);
+        graph.add_pass("third")
+            .image(b, ImageAccess::Sampled)
+            .image(c, ImageAccess::ColorAttachment);
+        graph.add_pass("last")
+            .image(c, ImageAccess::Sampled)
+            .image(target, ImageAccess::ColorAttachment);
+        (graph, [a, b, c])
+    }
+
+    #[test]
+    fn disjoint_lifetimes_share_a_slot() {
+        let (mut graph, [a, b, c]) = chain();
+        let slots = plan(&mut graph);
+        assert_eq!(slots[a.0], slots[c.0]);
+        assert_ne!(slots[a.0], slots[b.0]);
+
+        // `c` reuses the memory once `second` has sampled `a`.
+        let reuse = barrier(&graph, "third", c).unwrap();
+        assert!(reuse.src_stages.contains(vk::PipelineStageFlags::FRAGMENT_SHADER));
+        assert_eq!(reuse.old_layout, vk::ImageLayout::UNDEFINED);
+    }
+
+    #[test]
+    fn first_use_of_a_slot_waits_for_the_previous_frame() {
+        let (mut graph, [a, _, _]) = chain();
+        plan(&mut graph);
+
+        // The slot of `a` was last sampled as `c` by the previous frame.
+        let first = barrier(&graph, "first", a).unwrap();
+        assert!(first.src_stages.contains(vk::PipelineStageFlags::FRAGMENT_SHADER));
+        assert_eq!(first.dst_stages, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT);
+        assert_eq!(first.old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(first.new_layout, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+    }
+}
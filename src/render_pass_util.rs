@@ -1,30 +1,50 @@
 use vulkanalia::{vk, Device, Instance};
 use vulkanalia::vk::{DeviceV1_0, HasBuilder};
+use crate::deferred_util::{GBUFFER_ALBEDO_FORMAT, GBUFFER_MATERIAL_FORMAT, GBUFFER_NORMAL_FORMAT};
 use crate::framebuffer_util::get_depth_format;
 use crate::render_app::AppData;
 
+/// Render pass of the forward path. With MSAA the scene is drawn into the
+/// multisampled `AppData::color_image` and resolved into the swapchain image.
+///
+/// Attachments: 0 color, 1 depth, 2 swapchain color when multisampled.
 pub unsafe fn create_render_pass(instance: &Instance, device: &Device, data: &mut AppData) -> anyhow::Result<()> {
+    let multisampled = data.msaa_samples != vk::SampleCountFlags::_1;
+
     // Attachments
 
     let color_attachment = vk::AttachmentDescription::builder()
         .format(data.swapchain_format)
-        .samples(vk::SampleCountFlags::_1)
+        .samples(data.msaa_samples)
         .load_op(vk::AttachmentLoadOp::CLEAR)
-        .store_op(vk::AttachmentStoreOp::STORE)
+        .store_op(if multisampled { vk::AttachmentStoreOp::DONT_CARE } else { vk::AttachmentStoreOp::STORE })
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
         .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+        .final_layout(if multisampled { vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL } else { vk::ImageLayout::PRESENT_SRC_KHR })
+        .build();
 
     let depth_stencil_attachment = vk::AttachmentDescription::builder()
         .format(get_depth_format(instance, data)?)
-        .samples(vk::SampleCountFlags::_1)
+        .samples(data.msaa_samples)
         .load_op(vk::AttachmentLoadOp::CLEAR)
         .store_op(vk::AttachmentStoreOp::DONT_CARE)
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
         .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .build();
+
+    let color_resolve_attachment = vk::AttachmentDescription::builder()
+        .format(data.swapchain_format)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+        .build();
 
     // Subpasses
 
@@ -36,11 +56,19 @@ pub unsafe fn create_render_pass(instance: &Instance, device: &Device, data: &mu
         .attachment(1)
         .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
 
+    let color_resolve_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(2)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
     let color_attachments = &[color_attachment_ref];
-    let subpass = vk::SubpassDescription::builder()
+    let resolve_attachments = &[color_resolve_attachment_ref];
+    let mut subpass = vk::SubpassDescription::builder()
         .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
         .color_attachments(color_attachments)
         .depth_stencil_attachment(&depth_stencil_attachment_ref);
+    if multisampled {
+        subpass = subpass.resolve_attachments(resolve_attachments);
+    }
 
     // Dependencies
 
@@ -54,15 +82,153 @@ pub unsafe fn create_render_pass(instance: &Instance, device: &Device, data: &mu
 
     // Create
 
-    let attachments = &[color_attachment, depth_stencil_attachment];
+    let attachments = if multisampled {
+        vec![color_attachment, depth_stencil_attachment, color_resolve_attachment]
+    } else {
+        vec![color_attachment, depth_stencil_attachment]
+    };
     let subpasses = &[subpass];
     let dependencies = &[dependency];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(subpasses)
+        .dependencies(dependencies);
+
+    data.render_pass = data.deletion_queue.own(device.create_render_pass(&info, None)?);
+
+    Ok(())
+}
+
+/// Render pass of the deferred path. Subpass 0 fills the G-buffer,
+/// subpass 1 reads it back as input attachments and lights the swapchain image.
+///
+/// Attachments: 0 swapchain color, 1 depth, 2 albedo, 3 normal, 4 material.
+pub unsafe fn create_deferred_render_pass(instance: &Instance, device: &Device, data: &mut AppData) -> anyhow::Result<()> {
+    // Attachments
+
+    let color_attachment = vk::AttachmentDescription::builder()
+        .format(data.swapchain_format)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+
+    let depth_stencil_attachment = vk::AttachmentDescription::builder()
+        .format(get_depth_format(instance, data)?)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL);
+
+    let gbuffer_attachment = |format: vk::Format| {
+        vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()
+    };
+
+    // Subpasses
+
+    let gbuffer_refs = &[2, 3, 4].map(|i| {
+        vk::AttachmentReference::builder()
+            .attachment(i)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()
+    });
+
+    let depth_stencil_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(1)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let geometry_subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(gbuffer_refs)
+        .depth_stencil_attachment(&depth_stencil_attachment_ref);
+
+    let color_attachment_refs = &[vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .build()];
+
+    let input_attachment_refs = &[
+        (2, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        (3, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        (4, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        (1, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL),
+    ].map(|(attachment, layout)| {
+        vk::AttachmentReference::builder()
+            .attachment(attachment)
+            .layout(layout)
+            .build()
+    });
+
+    // Depth stays bound read-only so light volumes and the sky can depth test.
+    let read_only_depth_ref = vk::AttachmentReference::builder()
+        .attachment(1)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL);
+
+    let lighting_subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(color_attachment_refs)
+        .input_attachments(input_attachment_refs)
+        .depth_stencil_attachment(&read_only_depth_ref);
+
+    // Dependencies
+
+    let external_dependency = vk::SubpassDependency::builder()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE);
+
+    let swapchain_dependency = vk::SubpassDependency::builder()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(1)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+
+    let gbuffer_dependency = vk::SubpassDependency::builder()
+        .src_subpass(0)
+        .dst_subpass(1)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+        .dst_access_mask(vk::AccessFlags::INPUT_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ)
+        .dependency_flags(vk::DependencyFlags::BY_REGION);
+
+    // Create
+
+    let attachments = &[
+        color_attachment.build(),
+        depth_stencil_attachment.build(),
+        gbuffer_attachment(GBUFFER_ALBEDO_FORMAT),
+        gbuffer_attachment(GBUFFER_NORMAL_FORMAT),
+        gbuffer_attachment(GBUFFER_MATERIAL_FORMAT),
+    ];
+    let subpasses = &[geometry_subpass, lighting_subpass];
+    let dependencies = &[external_dependency, swapchain_dependency, gbuffer_dependency];
     let info = vk::RenderPassCreateInfo::builder()
         .attachments(attachments)
         .subpasses(subpasses)
         .dependencies(dependencies);
 
-    data.render_pass = device.create_render_pass(&info, None)?;
+    data.render_pass = data.deletion_queue.own(device.create_render_pass(&info, None)?);
 
     Ok(())
 }
@@ -0,0 +1,150 @@
+use anyhow::Result;
+use vulkanalia::{vk, Device, Instance};
+use vulkanalia::vk::{DeviceV1_3, HasBuilder};
+use crate::command_buffer_util::record_scene;
+use crate::framebuffer_util::get_depth_format;
+use crate::image_util::format_aspect;
+use crate::render_app::AppData;
+use crate::render_graph::{ImageAccess, ImageDesc, RenderGraph};
+
+/// Attachment formats of the dynamic rendering path. Pipelines are created
+/// against these instead of a render pass.
+#[derive(Clone, Debug, Default)]
+pub struct RenderingFormats {
+    pub color: Vec<vk::Format>,
+    pub depth: vk::Format,
+}
+
+/// An image written inside a dynamic rendering scope, described per frame
+/// instead of up front by a render pass and framebuffer. Layout transitions
+/// are left to the `RenderGraph` running the scope.
+#[derive(Copy, Clone)]
+pub struct RenderingAttachment {
+    pub view: vk::ImageView,
+    pub clear: vk::ClearValue,
+    /// Whether the contents are kept after the scope.
+    pub store: bool,
+    /// Single sampled view the attachment is resolved into.
+    pub resolve: Option<vk::ImageView>,
+}
+
+/// The attachments of one dynamic rendering scope.
+#[derive(Clone)]
+pub struct RenderingScope {
+    pub color: Vec<RenderingAttachment>,
+    pub depth: Option<RenderingAttachment>,
+}
+
+pub unsafe fn create_rendering_formats(instance: &Instance, data: &mut AppData) -> Result<()> {
+    data.rendering_formats = RenderingFormats {
+        color: vec![data.swapchain_format],
+        depth: get_depth_format(instance, data)?,
+    };
+    Ok(())
+}
+
+/// Builds the render graph of the forward path: one pass drawing the scene
+/// into the swapchain image. With MSAA it draws into a transient
+/// multisampled target resolved into the swapchain image. Depth is
+/// transient too, neither outlives the pass.
+pub unsafe fn create_forward_graph(device: &Device, data: &mut AppData) -> Result<()> {
+    let multisampled = data.msaa_samples != vk::SampleCountFlags::_1;
+    let extent = data.swapchain_extent;
+    let target = |format, aspect| ImageDesc {
+        width: extent.width,
+        height: extent.height,
+        format,
+        samples: data.msaa_samples,
+        aspect,
+    };
+
+    let mut graph = RenderGraph::default();
+    let swapchain = graph.import_image(
+        "swapchain",
+        vk::ImageAspectFlags::COLOR,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::PRESENT_SRC_KHR,
+    );
+    let color = multisampled.then(|| {
+        graph.transient_image("scene_color", target(data.swapchain_format, vk::ImageAspectFlags::COLOR))
+    });
+    let depth_format = data.rendering_formats.depth;
+    let depth = graph.transient_image("depth", target(depth_format, format_aspect(depth_format)));
+
+    let mut pass = graph.add_pass("scene")
+        .image(swapchain, ImageAccess::ColorAttachment)
+        .image(depth, ImageAccess::DepthAttachment);
+    if let Some(color) = color {
+        pass = pass.image(color, ImageAccess::ColorAttachment);
+    }
+    pass.record(move |ctx| {
+        let clear = vk::ClearValue { color: vk::ClearColorValue { float32: ctx.data.sky.clear_color } };
+        let color = match color {
+            Some(color) => RenderingAttachment {
+                view: ctx.image_view(color),
+                clear,
+                store: false,
+                resolve: Some(ctx.image_view(swapchain)),
+            },
+            None => RenderingAttachment { view: ctx.image_view(swapchain), clear, store: true, resolve: None },
+        };
+        let depth = RenderingAttachment {
+            view: ctx.image_view(depth),
+            clear: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth: ctx.data.camera.far_depth(), stencil: 0 },
+            },
+            store: false,
+            resolve: None,
+        };
+        let scope = RenderingScope { color: vec![color], depth: Some(depth) };
+
+        begin_rendering(ctx.device, ctx.command_buffer, ctx.data, &scope);
+        record_scene(ctx.device, ctx.data, ctx.command_buffer, ctx.frame, ctx.view);
+        ctx.device.cmd_end_rendering(ctx.command_buffer);
+    });
+
+    graph.compile(device, data)?;
+    data.swapchain_target = swapchain;
+    data.render_graph = graph;
+    Ok(())
+}
+
+/// Begins rendering to the attachments of `scope`, clearing all of them.
+/// They must already be in their attachment layouts.
+pub unsafe fn begin_rendering(device: &Device, command_buffer: vk::CommandBuffer, data: &AppData, scope: &RenderingScope) {
+    let color_attachments = scope.color
+        .iter()
+        .map(|a| attachment_info(a, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL))
+        .collect::<Vec<_>>();
+    let depth_attachment = scope.depth
+        .as_ref()
+        .map(|a| attachment_info(a, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL));
+
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(data.swapchain_extent);
+    let mut info = vk::RenderingInfo::builder()
+        .render_area(render_area)
+        .layer_count(1)
+        .color_attachments(&color_attachments);
+    if let Some(depth_attachment) = &depth_attachment {
+        info = info.depth_attachment(depth_attachment);
+    }
+    device.cmd_begin_rendering(command_buffer, &info);
+}
+
+fn attachment_info(attachment: &RenderingAttachment, layout: vk::ImageLayout) -> vk::RenderingAttachmentInfo {
+    let mut info = vk::RenderingAttachmentInfo::builder()
+        .image_view(attachment.view)
+        .image_layout(layout)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(if attachment.store { vk::AttachmentStoreOp::STORE } else { vk::AttachmentStoreOp::DONT_CARE })
+        .clear_value(attachment.clear);
+    if let Some(view) = attachment.resolve {
+        info = info
+            .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+            .resolve_image_view(view)
+            .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+    }
+    info.build()
+}
@@ -0,0 +1,253 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, MutexGuard};
+use log::error;
+use vulkanalia::{vk, Device};
+use vulkanalia::vk::{DeviceV1_0, Handle, KhrSwapchainExtension};
+use crate::memory_util::{Allocation, Allocator};
+
+/// A Vulkan object waiting in a `DeletionQueue`.
+#[derive(Copy, Clone, Debug)]
+pub enum Garbage {
+    Framebuffer(vk::Framebuffer),
+    Pipeline(vk::Pipeline),
+    PipelineCache(vk::PipelineCache),
+    PipelineLayout(vk::PipelineLayout),
+    RenderPass(vk::RenderPass),
+    DescriptorPool(vk::DescriptorPool),
+    DescriptorSetLayout(vk::DescriptorSetLayout),
+    ImageView(vk::ImageView),
+    Sampler(vk::Sampler),
+    Image(vk::Image),
+    Buffer(vk::Buffer),
+    Allocation(Allocation),
+    SwapchainKHR(vk::SwapchainKHR),
+    Semaphore(vk::Semaphore),
+    Fence(vk::Fence),
+    CommandPool(vk::CommandPool),
+}
+
+impl Garbage {
+    /// Objects of a batch are destroyed in this order, users before the
+    /// objects they refer to.
+    fn rank(&self) -> u8 {
+        match self {
+            Garbage::Framebuffer(_) => 0,
+            Garbage::Pipeline(_) => 1,
+            Garbage::PipelineCache(_) => 2,
+            Garbage::PipelineLayout(_) => 3,
+            Garbage::RenderPass(_) => 4,
+            Garbage::DescriptorPool(_) => 5,
+            Garbage::DescriptorSetLayout(_) => 6,
+            Garbage::ImageView(_) => 7,
+            Garbage::Sampler(_) => 8,
+            Garbage::Image(_) => 9,
+            Garbage::Buffer(_) => 10,
+            Garbage::Allocation(_) => 11,
+            Garbage::SwapchainKHR(_) => 12,
+            Garbage::Semaphore(_) => 13,
+            Garbage::Fence(_) => 14,
+            Garbage::CommandPool(_) => 15,
+        }
+    }
+
+    unsafe fn destroy(self, device: &Device, allocator: &Allocator) {
+        match self {
+            Garbage::Framebuffer(h) => device.destroy_framebuffer(h, None),
+            Garbage::Pipeline(h) => device.destroy_pipeline(h, None),
+            Garbage::PipelineCache(h) => device.destroy_pipeline_cache(h, None),
+            Garbage::PipelineLayout(h) => device.destroy_pipeline_layout(h, None),
+            Garbage::RenderPass(h) => device.destroy_render_pass(h, None),
+            Garbage::DescriptorPool(h) => device.destroy_descriptor_pool(h, None),
+            Garbage::DescriptorSetLayout(h) => device.destroy_descriptor_set_layout(h, None),
+            Garbage::ImageView(h) => device.destroy_image_view(h, None),
+            Garbage::Sampler(h) => device.destroy_sampler(h, None),
+            Garbage::Image(h) => device.destroy_image(h, None),
+            Garbage::Buffer(h) => device.destroy_buffer(h, None),
+            Garbage::Allocation(a) => allocator.free(device, a),
+            Garbage::SwapchainKHR(h) => device.destroy_swapchain_khr(h, None),
+            Garbage::Semaphore(h) => device.destroy_semaphore(h, None),
+            Garbage::Fence(h) => device.destroy_fence(h, None),
+            Garbage::CommandPool(h) => device.destroy_command_pool(h, None),
+        }
+    }
+}
+
+/// An object `DeletionQueue` knows how to destroy.
+pub trait Resource: Copy + fmt::Debug {
+    fn null() -> Self;
+    fn is_null(&self) -> bool;
+    fn into_garbage(self) -> Garbage;
+}
+
+macro_rules! resources {
+    ($($alias:ident => $handle:ident),* $(,)?) => {$(
+        impl Resource for vk::$handle {
+            fn null() -> Self {
+                <Self as Handle>::null()
+            }
+
+            fn is_null(&self) -> bool {
+                Handle::is_null(*self)
+            }
+
+            fn into_garbage(self) -> Garbage {
+                Garbage::$handle(self)
+            }
+        }
+
+        pub type $alias = Owned<vk::$handle>;
+    )*};
+}
+
+resources! {
+    Framebuffer => Framebuffer,
+    Pipeline => Pipeline,
+    PipelineCache => PipelineCache,
+    PipelineLayout => PipelineLayout,
+    RenderPass => RenderPass,
+    DescriptorPool => DescriptorPool,
+    DescriptorSetLayout => DescriptorSetLayout,
+    ImageView => ImageView,
+    Sampler => Sampler,
+    Image => Image,
+    Buffer => Buffer,
+    Swapchain => SwapchainKHR,
+    Semaphore => Semaphore,
+    Fence => Fence,
+    CommandPool => CommandPool,
+}
+
+impl Resource for Allocation {
+    fn null() -> Self {
+        Allocation::null()
+    }
+
+    fn is_null(&self) -> bool {
+        Allocation::is_null(self)
+    }
+
+    fn into_garbage(self) -> Garbage {
+        Garbage::Allocation(self)
+    }
+}
+
+/// A range of device memory, returned to the `Allocator` rather than destroyed.
+pub type Memory = Owned<Allocation>;
+
+/// The only owner of a Vulkan object. Dropping or overwriting it hands the
+/// object to the `DeletionQueue` it came from, which destroys it once the
+/// GPU no longer uses it. Derefs to the raw handle for Vulkan calls.
+pub struct Owned<T: Resource> {
+    handle: T,
+    queue: Option<DeletionQueue>,
+}
+
+impl<T: Resource> Owned<T> {
+    pub fn handle(&self) -> T {
+        self.handle
+    }
+}
+
+impl<T: Resource> Default for Owned<T> {
+    /// A null handle, dropping it does nothing.
+    fn default() -> Self {
+        Self { handle: T::null(), queue: None }
+    }
+}
+
+impl<T: Resource> Deref for Owned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.handle
+    }
+}
+
+impl<T: Resource> fmt::Debug for Owned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.handle.fmt(f)
+    }
+}
+
+impl<T: Resource> Drop for Owned<T> {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            if !self.handle.is_null() {
+                queue.retire(self.handle.into_garbage());
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    /// Objects dropped from now on are tagged with this frame.
+    frame: u64,
+    garbage: VecDeque<(u64, Garbage)>,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if !self.garbage.is_empty() {
+            let leaked = self.garbage.iter().map(|(_, g)| g).collect::<Vec<_>>();
+            error!("{} Vulkan objects were never destroyed: {:?}", leaked.len(), leaked);
+        }
+    }
+}
+
+/// Destroys dropped `Owned` objects once the frame that dropped them has
+/// finished on the GPU. Cheap to clone, clones share the queue.
+#[derive(Clone, Default)]
+pub struct DeletionQueue(Arc<Mutex<Pending>>);
+
+impl DeletionQueue {
+    fn lock(&self) -> MutexGuard<'_, Pending> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Takes ownership of a freshly created `handle`.
+    pub fn own<T: Resource>(&self, handle: T) -> Owned<T> {
+        Owned { handle, queue: Some(self.clone()) }
+    }
+
+    fn retire(&self, garbage: Garbage) {
+        let mut pending = self.lock();
+        let frame = pending.frame;
+        pending.garbage.push_back((frame, garbage));
+    }
+
+    /// Starts frame `frame`, objects dropped from now on wait for its commands.
+    pub fn set_frame(&self, frame: u64) {
+        self.lock().frame = frame;
+    }
+
+    /// Destroys the objects dropped up to frame `completed`, whose
+    /// commands have finished executing.
+    pub unsafe fn collect(&self, device: &Device, allocator: &Allocator, completed: u64) {
+        let mut batch = {
+            let mut pending = self.lock();
+            let count = pending.garbage.iter().take_while(|(frame, _)| *frame <= completed).count();
+            pending.garbage.drain(..count).map(|(_, g)| g).collect::<Vec<_>>()
+        };
+        batch.sort_by_key(Garbage::rank);
+        batch.into_iter().for_each(|g| g.destroy(device, allocator));
+    }
+
+    /// Destroys everything that was dropped. The device must be idle.
+    pub unsafe fn flush(&self, device: &Device, allocator: &Allocator) {
+        self.collect(device, allocator, u64::MAX);
+    }
+
+    /// Objects waiting to be destroyed.
+    fn len(&self) -> usize {
+        self.lock().garbage.len()
+    }
+}
+
+impl fmt::Debug for DeletionQueue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DeletionQueue").field("pending", &self.len()).finish()
+    }
+}
@@ -0,0 +1,125 @@
+use std::cmp::Ordering;
+use cgmath::{vec3, vec4, Vector4};
+use crate::lights::Vec4;
+use crate::transforms::Mat4;
+
+/// How the alpha channel of a material is interpreted.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AlphaMode {
+    /// Alpha is ignored.
+    Opaque,
+    /// Fragments with alpha below `cutoff` are discarded. Uses alpha to
+    /// coverage when multisampling is enabled.
+    Mask { cutoff: f32 },
+    /// Blended over what is behind it, drawn after all opaque geometry,
+    /// sorted back to front and without writing depth.
+    Blend,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Material {
+    pub base_color: Vec4,
+    pub alpha_mode: AlphaMode,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self { base_color: vec4(1.0, 1.0, 1.0, 1.0), alpha_mode: AlphaMode::Opaque }
+    }
+}
+
+/// A loaded model: a range of an index buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Mesh {
+    pub first_index: u32,
+    pub index_count: u32,
+    /// The vertex and index buffers holding the model. 0 is shared by the
+    /// models added with `EngineBuilder::model`, each model streamed in with
+    /// `Uploads::load_model` has its own.
+    pub geometry: u32,
+}
+
+/// A drawable instance of a range of an index buffer.
+#[derive(Copy, Clone, Debug)]
+pub struct SceneObject {
+    pub transform: Mat4,
+    pub material: Material,
+    pub first_index: u32,
+    pub index_count: u32,
+    pub geometry: u32,
+}
+
+impl SceneObject {
+    pub fn new(mesh: Mesh, transform: Mat4, material: Material) -> Self {
+        Self {
+            transform,
+            material,
+            first_index: mesh.first_index,
+            index_count: mesh.index_count,
+            geometry: mesh.geometry,
+        }
+    }
+}
+
+/// Per draw data pushed to the object shaders, see `shader.vert`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ObjectPushConstants {
+    pub model: Mat4,
+    pub base_color: Vec4,
+    pub alpha_cutoff: f32,
+    pub _padding: [f32; 3],
+}
+
+impl ObjectPushConstants {
+    pub fn new(object: &SceneObject) -> Self {
+        let alpha_cutoff = match object.material.alpha_mode {
+            AlphaMode::Mask { cutoff } => cutoff,
+            AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+        };
+        Self {
+            model: object.transform,
+            base_color: object.material.base_color,
+            alpha_cutoff,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// `mesh` once, plus two translucent copies next to it.
+pub fn default_scene(mesh: Mesh) -> Vec<SceneObject> {
+    let room = SceneObject::new(mesh, Mat4::from_scale(1.0), Material::default());
+    let ghost = |offset: f32, color: Vec4| SceneObject {
+        transform: Mat4::from_translation(vec3(offset, 0.0, 0.0)) * Mat4::from_scale(0.5),
+        material: Material { base_color: color, alpha_mode: AlphaMode::Blend },
+        ..room
+    };
+    vec![
+        room,
+        ghost(1.2, vec4(0.6, 0.8, 1.0, 0.4)),
+        ghost(-1.2, vec4(1.0, 0.7, 0.6, 0.6)),
+    ]
+}
+
+/// Depth of the object's origin in view space. The view looks down -Z, so
+/// smaller values are further away.
+fn view_depth(object: &SceneObject, view: &Mat4) -> f32 {
+    let origin: Vector4<f32> = *view * object.transform * vec4(0.0, 0.0, 0.0, 1.0);
+    origin.z
+}
+
+/// Opaque and masked objects, in scene order.
+pub fn opaque_objects(objects: &[SceneObject]) -> impl Iterator<Item = &SceneObject> {
+    objects.iter().filter(|o| o.material.alpha_mode != AlphaMode::Blend)
+}
+
+/// Blended objects sorted back to front relative to `view`.
+pub fn transparent_objects(objects: &[SceneObject], view: &Mat4) -> Vec<SceneObject> {
+    let mut transparent = objects
+        .iter()
+        .filter(|o| o.material.alpha_mode == AlphaMode::Blend)
+        .map(|o| (view_depth(o, view), *o))
+        .collect::<Vec<_>>();
+    transparent.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    transparent.into_iter().map(|(_, o)| o).collect()
+}
@@ -0,0 +1,335 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Result};
+#[cfg(feature = "runtime-shaders")]
+use log::{error, info, warn};
+
+/// The pipelines built from a shader, rebuilt when it changes on disk.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderUser {
+    /// The object pipelines of the active render path, including the
+    /// lighting pipelines of the deferred path.
+    Scene,
+    Sky,
+    Debug,
+    DebugLines,
+    /// A compute pass, by index.
+    Compute(usize),
+}
+
+/// A shader of `src/shaders` compiled by `build.rs`, with its defines
+/// sorted by name.
+pub(crate) struct EmbeddedShader {
+    pub(crate) name: &'static str,
+    pub(crate) defines: &'static [(&'static str, &'static str)],
+    pub(crate) spirv: &'static [u8],
+}
+
+pub(crate) static EMBEDDED_SHADERS: &[EmbeddedShader] = include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+
+/// A shader source and the preprocessor defines it is compiled with.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct ShaderKey {
+    path: PathBuf,
+    defines: Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+struct CompiledShader {
+    spirv: Vec<u8>,
+    /// The source and every file it includes, canonicalized, to watch.
+    #[cfg(feature = "runtime-shaders")]
+    dependencies: Vec<PathBuf>,
+    users: HashSet<ShaderUser>,
+}
+
+/// Compiles GLSL and HLSL sources to SPIR-V and remembers which pipelines
+/// use them, so `changed_users` can tell which to rebuild after an edit.
+///
+/// The stage comes from the extension: `.vert`, `.frag`, `.comp`, `.geom`,
+/// `.tesc` or `.tese`, with `.hlsl` appended for HLSL, e.g. `blur.comp.hlsl`.
+/// `#include "file"` is resolved next to the including file first, then in
+/// the shader directory, `#include <file>` only in the shader directory.
+///
+/// The engine's own shaders and their variants are embedded by `build.rs`,
+/// see the `build-shaders` feature. They are used as they are without the `runtime-shaders`
+/// feature, or when the source is missing from the asset root. Other
+/// shaders then have to be compiled up front, `x.vert` is read from
+/// `x_vert.spv` next to it.
+#[derive(Default)]
+pub struct Shaders {
+    compiled: HashMap<ShaderKey, CompiledShader>,
+    /// Where `#include <file>` is looked up.
+    include_dir: PathBuf,
+    #[cfg(feature = "runtime-shaders")]
+    compiler: Option<shaderc::Compiler>,
+    #[cfg(feature = "runtime-shaders")]
+    watcher: Option<ShaderWatcher>,
+}
+
+impl fmt::Debug for Shaders {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Shaders")
+            .field("compiled", &self.compiled.keys().collect::<Vec<_>>())
+            .field("include_dir", &self.include_dir)
+            .finish()
+    }
+}
+
+impl Shaders {
+    pub fn new(include_dir: PathBuf) -> Self {
+        Self { include_dir, ..Default::default() }
+    }
+
+    /// SPIR-V of the shader at `path` compiled with `defines`, which `user`
+    /// is built from. Compiled once per set of defines.
+    pub fn compile(&mut self, path: &Path, defines: &[(&str, &str)], user: ShaderUser) -> Result<Vec<u8>> {
+        let key = ShaderKey {
+            path: path.to_path_buf(),
+            defines: defines.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+        };
+        if let Some(shader) = self.compiled.get_mut(&key) {
+            shader.users.insert(user);
+            return Ok(shader.spirv.clone());
+        }
+
+        let (spirv, dependencies) = match self.embedded(&key) {
+            Some(spirv) if !cfg!(feature = "runtime-shaders") || !key.path.is_file() => (spirv.to_vec(), Vec::new()),
+            _ => self.compile_source(&key)?,
+        };
+        self.watch_dependencies(&dependencies);
+        let users = HashSet::from([user]);
+        self.compiled.insert(key, CompiledShader {
+            spirv: spirv.clone(),
+            #[cfg(feature = "runtime-shaders")]
+            dependencies,
+            users,
+        });
+        Ok(spirv)
+    }
+
+    /// The SPIR-V `build.rs` compiled for `key`, if it names a shader of the
+    /// engine and one of its variants.
+    fn embedded(&self, key: &ShaderKey) -> Option<&'static [u8]> {
+        if key.path.parent() != Some(self.include_dir.as_path()) {
+            return None;
+        }
+        let name = key.path.file_name()?.to_str()?;
+        let mut defines = key.defines.clone();
+        defines.sort();
+        EMBEDDED_SHADERS
+            .iter()
+            .find(|shader| {
+                shader.name == name
+                    && shader.defines.len() == defines.len()
+                    && shader.defines.iter().zip(&defines).all(|(a, b)| a.0 == b.0 && a.1 == b.1)
+            })
+            .map(|shader| shader.spirv)
+    }
+
+    #[cfg(feature = "runtime-shaders")]
+    fn compile_source(&mut self, key: &ShaderKey) -> Result<(Vec<u8>, Vec<PathBuf>)> {
+        use std::cell::RefCell;
+        use shaderc::{CompileOptions, IncludeType, ResolvedInclude, SourceLanguage};
+
+        let path = &key.path;
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read shader `{}`: {}", path.display(), e))?;
+        let (kind, hlsl) = shader_kind(path)?;
+
+        let dependencies = RefCell::new(vec![canonical(path)]);
+        let include_dir = &self.include_dir;
+        let mut options = CompileOptions::new().ok_or_else(|| anyhow!("Failed to create shader compile options."))?;
+        if hlsl {
+            options.set_source_language(SourceLanguage::HLSL);
+        }
+        for (name, value) in &key.defines {
+            options.add_macro_definition(name, Some(value));
+        }
+        options.set_include_callback(|name, include_type, requesting, _depth| {
+            let relative = Path::new(requesting).parent().map(|dir| dir.join(name));
+            let resolved = match include_type {
+                IncludeType::Relative => relative.filter(|p| p.is_file()).unwrap_or_else(|| include_dir.join(name)),
+                IncludeType::Standard => include_dir.join(name),
+            };
+            let content = std::fs::read_to_string(&resolved)
+                .map_err(|e| format!("Failed to include `{}`: {}", resolved.display(), e))?;
+            dependencies.borrow_mut().push(canonical(&resolved));
+            Ok(ResolvedInclude { resolved_name: resolved.to_string_lossy().into_owned(), content })
+        });
+
+        if self.compiler.is_none() {
+            self.compiler = Some(shaderc::Compiler::new().ok_or_else(|| anyhow!("Failed to create the shader compiler."))?);
+        }
+        let artifact = self.compiler
+            .as_ref()
+            .expect("created above")
+            .compile_into_spirv(&source, kind, &path.to_string_lossy(), "main", Some(&options))
+            .map_err(|e| anyhow!("Failed to compile shader `{}`:\n{}", path.display(), e))?;
+        if artifact.get_num_warnings() > 0 {
+            warn!("Shader `{}`:\n{}", path.display(), artifact.get_warning_messages());
+        }
+        let spirv = artifact.as_binary_u8().to_vec();
+        drop(options);
+        Ok((spirv, dependencies.into_inner()))
+    }
+
+    #[cfg(not(feature = "runtime-shaders"))]
+    fn compile_source(&mut self, key: &ShaderKey) -> Result<(Vec<u8>, Vec<PathBuf>)> {
+        let path = &key.path;
+        if !key.defines.is_empty() {
+            return Err(anyhow!("Shader `{}` has defines, which need the `runtime-shaders` feature.", path.display()));
+        }
+        let (Some(stem), Some(stage)) = (path.file_stem(), path.extension()) else {
+            return Err(anyhow!("Shader `{}` has no stage extension.", path.display()));
+        };
+        let spirv_path = path.with_file_name(format!("{}_{}.spv", stem.to_string_lossy(), stage.to_string_lossy()));
+        let spirv = std::fs::read(&spirv_path).map_err(|e| anyhow!(
+            "Failed to read shader `{}`, compile `{}` with glslc or enable the `runtime-shaders` feature: {}",
+            spirv_path.display(),
+            path.display(),
+            e,
+        ))?;
+        Ok((spirv, Vec::new()))
+    }
+
+    /// Starts watching the files of compiled shaders for changes, see
+    /// `changed_users`. Needs the `runtime-shaders` feature.
+    #[cfg(feature = "runtime-shaders")]
+    pub fn watch(&mut self) -> Result<()> {
+        self.watcher = Some(ShaderWatcher::new()?);
+        let dependencies = self.compiled.values().flat_map(|s| s.dependencies.clone()).collect::<Vec<_>>();
+        self.watch_dependencies(&dependencies);
+        info!("Watching shaders for changes.");
+        Ok(())
+    }
+
+    #[cfg(not(feature = "runtime-shaders"))]
+    pub fn watch(&mut self) -> Result<()> {
+        Err(anyhow!("Shader hot reload needs the `runtime-shaders` feature."))
+    }
+
+    #[cfg(feature = "runtime-shaders")]
+    fn watch_dependencies(&mut self, dependencies: &[PathBuf]) {
+        if let Some(watcher) = &mut self.watcher {
+            for directory in dependencies.iter().filter_map(|path| path.parent()) {
+                watcher.watch(directory);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "runtime-shaders"))]
+    fn watch_dependencies(&mut self, _dependencies: &[PathBuf]) {}
+
+    /// Recompiles the shaders depending on files changed since the last
+    /// call and returns the users of those that compiled. Shaders that fail
+    /// are logged and keep their previous SPIR-V, fixing the file retries.
+    #[cfg(feature = "runtime-shaders")]
+    pub fn changed_users(&mut self) -> HashSet<ShaderUser> {
+        let changed = match &self.watcher {
+            Some(watcher) => watcher.changed_files(),
+            None => return HashSet::new(),
+        };
+        let stale = self.compiled
+            .iter()
+            .filter(|(_, shader)| shader.dependencies.iter().any(|path| changed.contains(path)))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        let mut users = HashSet::new();
+        for key in stale {
+            match self.compile_source(&key) {
+                Ok((spirv, dependencies)) => {
+                    info!("Recompiled shader `{}`.", key.path.display());
+                    self.watch_dependencies(&dependencies);
+                    let shader = self.compiled.get_mut(&key).expect("stale shaders are compiled");
+                    shader.spirv = spirv;
+                    shader.dependencies = dependencies;
+                    users.extend(shader.users.iter().copied());
+                }
+                Err(e) => error!("{:#}", e),
+            }
+        }
+        users
+    }
+
+    #[cfg(not(feature = "runtime-shaders"))]
+    pub fn changed_users(&mut self) -> HashSet<ShaderUser> {
+        HashSet::new()
+    }
+}
+
+/// The stage of `path` and whether it is HLSL.
+#[cfg(feature = "runtime-shaders")]
+fn shader_kind(path: &Path) -> Result<(shaderc::ShaderKind, bool)> {
+    use shaderc::ShaderKind;
+
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let (name, hlsl) = match name.strip_suffix(".hlsl") {
+        Some(name) => (name, true),
+        None => (name.as_str(), false),
+    };
+    let kind = match name.rsplit_once('.').map(|(_, stage)| stage) {
+        Some("vert") => ShaderKind::Vertex,
+        Some("frag") => ShaderKind::Fragment,
+        Some("comp") => ShaderKind::Compute,
+        Some("geom") => ShaderKind::Geometry,
+        Some("tesc") => ShaderKind::TessControl,
+        Some("tese") => ShaderKind::TessEvaluation,
+        _ => return Err(anyhow!(
+            "Unknown stage of shader `{}`, expected a vert, frag, comp, geom, tesc or tese extension.",
+            path.display(),
+        )),
+    };
+    Ok((kind, hlsl))
+}
+
+/// Editors save by replacing files, so paths are compared canonicalized.
+#[cfg(feature = "runtime-shaders")]
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Collects file system events of the directories holding shader sources.
+#[cfg(feature = "runtime-shaders")]
+struct ShaderWatcher {
+    watcher: notify::RecommendedWatcher,
+    events: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+    directories: HashSet<PathBuf>,
+}
+
+#[cfg(feature = "runtime-shaders")]
+impl ShaderWatcher {
+    fn new() -> Result<Self> {
+        let (sender, events) = std::sync::mpsc::channel();
+        let watcher = notify::recommended_watcher(sender)
+            .map_err(|e| anyhow!("Failed to watch shaders: {}", e))?;
+        Ok(Self { watcher, events, directories: HashSet::new() })
+    }
+
+    fn watch(&mut self, directory: &Path) {
+        use notify::{RecursiveMode, Watcher};
+
+        if self.directories.insert(directory.to_path_buf()) {
+            if let Err(e) = self.watcher.watch(directory, RecursiveMode::NonRecursive) {
+                warn!("Failed to watch `{}`: {}", directory.display(), e);
+            }
+        }
+    }
+
+    /// Files written or replaced since the last call.
+    fn changed_files(&self) -> HashSet<PathBuf> {
+        let mut changed = HashSet::new();
+        for event in self.events.try_iter() {
+            match event {
+                Ok(event) if event.kind.is_modify() || event.kind.is_create() => {
+                    changed.extend(event.paths.iter().map(|path| canonical(path)));
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Shader watcher: {}", e),
+            }
+        }
+        changed
+    }
+}
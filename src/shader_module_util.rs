@@ -1,6 +1,10 @@
+use std::path::Path;
 use vulkanalia::{vk, Device};
 use vulkanalia::bytecode::Bytecode;
 use vulkanalia::vk::{DeviceV1_0, HasBuilder};
+use crate::render_app::AppData;
+use crate::shader_compiler::ShaderUser;
+use crate::spirv_reflect::{reflect, ShaderInterface};
 
 pub unsafe fn create_shader_module(
     device: &Device,
@@ -12,4 +16,56 @@ pub unsafe fn create_shader_module(
         .code(bytecode.code());
 
     Ok(device.create_shader_module(&info, None)?)
-}
\ No newline at end of file
+}
+
+/// Compiles shader `name` from `shaders/` in the asset root for `user`,
+/// e.g. `gbuffer.vert`. See `Shaders` for what happens without the
+/// `runtime-shaders` feature.
+pub fn load_shader(
+    data: &mut AppData,
+    name: &str,
+    defines: &[(&str, &str)],
+    user: ShaderUser,
+) -> anyhow::Result<Vec<u8>> {
+    let path = data.config.asset(Path::new("shaders").join(name));
+    data.shaders.compile(&path, defines, user)
+}
+
+/// Loads a shader from anywhere, `.spv` files as they are and sources
+/// compiled for `user`.
+pub fn load_shader_file(data: &mut AppData, path: &Path, user: ShaderUser) -> anyhow::Result<Vec<u8>> {
+    if path.extension().is_some_and(|extension| extension == "spv") {
+        load_spirv_file(path)
+    } else {
+        data.shaders.compile(path, &[], user)
+    }
+}
+
+/// Reads a compiled shader from anywhere.
+pub fn load_spirv_file(path: &Path) -> anyhow::Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| anyhow::anyhow!("Failed to read shader `{}`: {}", path.display(), e))
+}
+
+/// The built in forward shaders.
+pub fn load_forward_shaders(data: &mut AppData) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+    Ok((
+        load_shader(data, "shader.vert", &[], ShaderUser::Scene)?,
+        load_shader(data, "shader.frag", &[], ShaderUser::Scene)?,
+    ))
+}
+
+/// The combined interface of pipelines that share a layout, each given by
+/// its vertex and fragment shader names.
+pub fn reflect_pipelines(
+    data: &mut AppData,
+    pipelines: &[(&str, &str)],
+    user: ShaderUser,
+) -> anyhow::Result<ShaderInterface> {
+    let mut interfaces = Vec::with_capacity(pipelines.len());
+    for (vert, frag) in pipelines {
+        let vert = reflect(&load_shader(data, vert, &[], user)?)?;
+        let frag = reflect(&load_shader(data, frag, &[], user)?)?;
+        interfaces.push(ShaderInterface::merge(&[vert, frag])?);
+    }
+    ShaderInterface::union(&interfaces)
+}
@@ -1,2 +1,80 @@
-/usr/local/bin/glslc shader.vert -o vert.spv
-/usr/local/bin/glslc shader.frag -o frag.spv
+#!/bin/sh
+# Regenerates the SPIR-V `build.rs` embeds when the `build-shaders` feature is
+# off. Run it after changing a shader, it needs glslc on the PATH.
+#
+# Variants listed in `variants.toml` get one file per combination of defines,
+# named after them in order, for example:
+# glslc -DALPHA_TEST=1 -DSHADOWS=0 shader.frag -o shader_frag.ALPHA_TEST=1.SHADOWS=0.spv
+cd "$(dirname "$0")" || exit 1
+for source in *.vert *.frag *.comp *.geom *.tesc *.tese; do
+    [ -f "$source" ] || continue
+    glslc "$source" -o "${source%.*}_${source##*.}.spv" || exit 1
+done
+
+# Prints `source flags suffix` for every permutation in `variants.toml`, with
+# the defines sorted by name like `build.rs` does.
+variants() {
+    [ -f variants.toml ] || return 0
+    awk '
+        function flush(    i, j, n, name, count, k, combos, next_combos, v) {
+            if (source == "") return
+            # Sort the defines by name.
+            for (i = 2; i <= defines; i++) {
+                name = names[i]
+                for (j = i - 1; j >= 1 && names[j] > name; j--) names[j + 1] = names[j]
+                names[j + 1] = name
+            }
+            count = 1
+            flags[1] = ""
+            suffix[1] = ""
+            for (i = 1; i <= defines; i++) {
+                n = split(values[names[i]], v, " ")
+                next_combos = 0
+                for (k = 1; k <= count; k++) {
+                    for (j = 1; j <= n; j++) {
+                        next_combos++
+                        next_flags[next_combos] = flags[k] " -D" names[i] "=" v[j]
+                        next_suffix[next_combos] = suffix[k] "." names[i] "=" v[j]
+                    }
+                }
+                count = next_combos
+                for (k = 1; k <= count; k++) {
+                    flags[k] = next_flags[k]
+                    suffix[k] = next_suffix[k]
+                }
+            }
+            if (defines > 0) {
+                for (k = 1; k <= count; k++) print source flags[k] " " suffix[k]
+            }
+            source = ""
+            defines = 0
+        }
+        /^[ \t]*#/ || /^[ \t]*$/ { next }
+        /^\[/ {
+            flush()
+            source = $0
+            gsub(/^\[[ \t]*"|"[ \t]*\]$/, "", source)
+            next
+        }
+        {
+            name = $0
+            sub(/[ \t]*=.*/, "", name)
+            list = $0
+            sub(/^[^=]*=[ \t]*\[/, "", list)
+            sub(/\].*/, "", list)
+            gsub(/[",]/, " ", list)
+            names[++defines] = name
+            values[name] = list
+        }
+        END { flush() }
+    ' variants.toml
+}
+
+variants | while read -r line; do
+    source="${line%% *}"
+    suffix="${line##* }"
+    flags="${line#* }"
+    flags="${flags% *}"
+    # shellcheck disable=SC2086
+    glslc $flags "$source" -o "${source%.*}_${source##*.}$suffix.spv" || exit 1
+done
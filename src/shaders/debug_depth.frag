@@ -0,0 +1,10 @@
+#version 450
+
+layout(location = 2) in float fragViewDepth;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    float brightness = exp(-fragViewDepth * 0.3);
+    outColor = vec4(vec3(brightness), 1.0);
+}
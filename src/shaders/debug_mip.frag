@@ -0,0 +1,24 @@
+#version 450
+
+layout(location = 1) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+layout(binding = 1) uniform sampler2D texSampler;
+
+const vec3 LEVELS[6] = vec3[](
+    vec3(1.0, 1.0, 1.0),
+    vec3(1.0, 0.2, 0.2),
+    vec3(1.0, 0.7, 0.1),
+    vec3(0.9, 1.0, 0.1),
+    vec3(0.2, 0.9, 0.2),
+    vec3(0.2, 0.5, 1.0)
+);
+
+void main() {
+    float lod = textureQueryLod(texSampler, fragTexCoord).x;
+    int level = int(floor(lod));
+    vec3 color = mix(LEVELS[clamp(level, 0, 5)], LEVELS[clamp(level + 1, 0, 5)], fract(lod));
+    vec3 texel = texture(texSampler, fragTexCoord).rgb;
+    outColor = vec4(color * (0.5 + 0.5 * dot(texel, vec3(0.333))), 1.0);
+}
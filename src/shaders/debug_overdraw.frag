@@ -0,0 +1,8 @@
+#version 450
+
+layout(location = 0) out vec4 outColor;
+
+// Accumulated additively, a pixel shaded ten times saturates red.
+void main() {
+    outColor = vec4(0.1, 0.04, 0.02, 1.0);
+}
@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 1) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+const float CHECKERS = 16.0;
+
+void main() {
+    vec2 cell = floor(fragTexCoord * CHECKERS);
+    float checker = mod(cell.x + cell.y, 2.0);
+    // Tint by the UV itself so flipped or mirrored islands stand out.
+    vec3 tint = vec3(fract(fragTexCoord), 0.5);
+    outColor = vec4(mix(tint * 0.4, tint, checker), 1.0);
+}
@@ -0,0 +1,26 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "lights.glsl"
+#include "gbuffer_read.glsl"
+
+layout(location = 0) in vec2 fragUV;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    float depth = subpassLoad(gDepth).r;
+    if (depth == lights.depthRange.x) {
+        discard;
+    }
+
+    vec3 albedo = subpassLoad(gAlbedo).rgb;
+    vec3 normal = subpassLoad(gNormal).xyz;
+    vec4 material = subpassLoad(gMaterial);
+    vec3 position = worldPosition(depth);
+
+    vec3 sun = lights.sun.color.rgb * lights.sun.color.w;
+    vec3 color = albedo * lights.ambient.rgb * material.b;
+    color += shade(albedo, normal, material, position, -lights.sun.direction.xyz, sun);
+    outColor = vec4(color, 1.0);
+}
@@ -0,0 +1,28 @@
+#version 450
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragNormal;
+
+layout(location = 0) out vec4 outAlbedo;
+layout(location = 1) out vec4 outNormal;
+layout(location = 2) out vec4 outMaterial;
+
+layout(binding = 1) uniform sampler2D texSampler;
+
+layout(push_constant) uniform ObjectPushConstants {
+    mat4 model;
+    vec4 baseColor;
+    float alphaCutoff;
+} object;
+
+void main() {
+    vec4 albedo = texture(texSampler, fragTexCoord) * object.baseColor;
+    if (albedo.a < object.alphaCutoff) {
+        discard;
+    }
+    outAlbedo = vec4(albedo.rgb * fragColor, albedo.a);
+    outNormal = vec4(normalize(fragNormal), 1.0);
+    // r: roughness, g: metallic, b: ambient occlusion.
+    outMaterial = vec4(0.8, 0.0, 1.0, 0.0);
+}
@@ -0,0 +1,24 @@
+// G-buffer input attachments of the deferred lighting subpass.
+
+layout(input_attachment_index = 0, binding = 0) uniform subpassInput gAlbedo;
+layout(input_attachment_index = 1, binding = 1) uniform subpassInput gNormal;
+layout(input_attachment_index = 2, binding = 2) uniform subpassInput gMaterial;
+layout(input_attachment_index = 3, binding = 3) uniform subpassInput gDepth;
+
+vec3 worldPosition(float depth) {
+    vec2 uv = gl_FragCoord.xy * lights.viewport.zw;
+    vec4 clip = vec4(uv * 2.0 - 1.0, depth, 1.0);
+    vec4 world = lights.invViewProj * clip;
+    return world.xyz / world.w;
+}
+
+vec3 shade(vec3 albedo, vec3 normal, vec4 material, vec3 position, vec3 toLight, vec3 radiance) {
+    vec3 n = normalize(normal);
+    vec3 l = normalize(toLight);
+    vec3 v = normalize(lights.cameraPosition.xyz - position);
+    vec3 h = normalize(l + v);
+    float shininess = mix(128.0, 4.0, material.r);
+    float diffuse = max(dot(n, l), 0.0);
+    float specular = pow(max(dot(n, h), 0.0), shininess) * (1.0 - material.r);
+    return (albedo * diffuse + vec3(specular)) * radiance;
+}
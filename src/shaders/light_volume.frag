@@ -0,0 +1,34 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "lights.glsl"
+#include "gbuffer_read.glsl"
+
+layout(location = 0) flat in uint lightIndex;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    float depth = subpassLoad(gDepth).r;
+    if (depth == lights.depthRange.x) {
+        discard;
+    }
+
+    PointLight light = lights.pointLights[lightIndex];
+    vec3 position = worldPosition(depth);
+    vec3 toLight = light.position.xyz - position;
+    float distance = length(toLight);
+    float radius = light.position.w;
+    if (distance > radius) {
+        discard;
+    }
+
+    float falloff = clamp(1.0 - pow(distance / radius, 4.0), 0.0, 1.0);
+    float attenuation = falloff * falloff / (distance * distance + 1.0);
+    vec3 radiance = light.color.rgb * light.color.w * attenuation;
+
+    vec3 albedo = subpassLoad(gAlbedo).rgb;
+    vec3 normal = subpassLoad(gNormal).xyz;
+    vec4 material = subpassLoad(gMaterial);
+    outColor = vec4(shade(albedo, normal, material, position, toLight, radiance), 1.0);
+}
@@ -0,0 +1,26 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "lights.glsl"
+
+layout(location = 0) flat out uint lightIndex;
+
+// Draws a camera facing quad bounding the sphere of influence of one point
+// light per instance, covering the whole screen when the camera is inside it.
+void main() {
+    PointLight light = lights.pointLights[gl_InstanceIndex];
+    lightIndex = gl_InstanceIndex;
+
+    vec2 corner = vec2(float(gl_VertexIndex & 1), float(gl_VertexIndex >> 1)) * 2.0 - 1.0;
+    vec3 center = (lights.view * vec4(light.position.xyz, 1.0)).xyz;
+    float radius = light.position.w;
+
+    if (length(center) < radius * 1.5) {
+        gl_Position = vec4(corner, 0.0, 1.0);
+        return;
+    }
+
+    // Pull the quad towards the camera so the front of the sphere is covered.
+    vec3 position = center + normalize(-center) * radius + vec3(corner * radius * 1.5, 0.0);
+    gl_Position = lights.proj * vec4(position, 1.0);
+}
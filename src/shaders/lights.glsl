@@ -0,0 +1,26 @@
+// Shared by the deferred lighting shaders, mirrors `LightUniform` in lights.rs.
+
+#define MAX_POINT_LIGHTS 64
+
+struct PointLight {
+    vec4 position; // w: radius
+    vec4 color;    // w: intensity
+};
+
+struct DirectionalLight {
+    vec4 direction;
+    vec4 color;    // w: intensity
+};
+
+layout(binding = 4) uniform LightUniform {
+    mat4 view;
+    mat4 proj;
+    mat4 invViewProj;
+    vec4 cameraPosition;
+    vec4 viewport; // xy: size, zw: 1 / size
+    vec4 depthRange; // x: depth of the far plane
+    vec4 ambient;
+    DirectionalLight sun;
+    uvec4 pointLightCount;
+    PointLight pointLights[MAX_POINT_LIGHTS];
+} lights;
@@ -1,11 +1,32 @@
 #version 450
 
+// Cutout materials are drawn with `ALPHA_TEST` set to 1, see `variants.toml`.
+#ifndef ALPHA_TEST
+#define ALPHA_TEST 0
+#endif
+
 layout(location = 0) in vec3 fragColor;
 layout(location = 1) in vec2 fragTexCoord;
 
 layout(location = 0) out vec4 outColor;
 
 layout(binding = 1) uniform sampler2D texSampler;
+
+// Set to false through `EngineBuilder::object_constants` to ignore textures.
+layout(constant_id = 0) const bool TEXTURED = true;
+
+layout(push_constant) uniform ObjectPushConstants {
+    mat4 model;
+    vec4 baseColor;
+    float alphaCutoff;
+} object;
+
 void main() {
-    outColor = texture(texSampler, fragTexCoord * 1.0);
-}
\ No newline at end of file
+    vec4 texel = TEXTURED ? texture(texSampler, fragTexCoord) : vec4(1.0);
+    outColor = texel * object.baseColor;
+#if ALPHA_TEST
+    if (outColor.a < object.alphaCutoff) {
+        discard;
+    }
+#endif
+}
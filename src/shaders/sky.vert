@@ -0,0 +1,36 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    vec4 depthRange; // x: depth of the far plane
+} ubo;
+
+layout(location = 0) out vec3 fragDirection;
+
+const vec3 CORNERS[8] = vec3[](
+    vec3(-1.0, -1.0, -1.0), vec3( 1.0, -1.0, -1.0),
+    vec3( 1.0,  1.0, -1.0), vec3(-1.0,  1.0, -1.0),
+    vec3(-1.0, -1.0,  1.0), vec3( 1.0, -1.0,  1.0),
+    vec3( 1.0,  1.0,  1.0), vec3(-1.0,  1.0,  1.0)
+);
+
+const int INDICES[36] = int[](
+    0, 1, 2, 2, 3, 0,
+    4, 6, 5, 6, 4, 7,
+    0, 4, 5, 5, 1, 0,
+    3, 2, 6, 6, 7, 3,
+    0, 3, 7, 7, 4, 0,
+    1, 5, 6, 6, 2, 1
+);
+
+// Draws a unit cube around the camera. Only the rotation of the view is
+// applied and depth is forced to the far plane so the sky is always behind
+// the scene.
+void main() {
+    vec3 position = CORNERS[INDICES[gl_VertexIndex]];
+    fragDirection = position;
+    vec4 clip = ubo.proj * mat4(mat3(ubo.view)) * vec4(position, 1.0);
+    gl_Position = vec4(clip.xy, clip.w * ubo.depthRange.x, clip.w);
+}
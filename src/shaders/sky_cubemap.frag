@@ -0,0 +1,13 @@
+#version 450
+
+layout(location = 0) in vec3 fragDirection;
+
+layout(location = 0) out vec4 outColor;
+
+layout(binding = 1) uniform samplerCube skybox;
+
+void main() {
+    // The engine is Z-up, cubemaps are authored Y-up.
+    vec3 direction = normalize(fragDirection);
+    outColor = vec4(texture(skybox, vec3(direction.x, direction.z, -direction.y)).rgb, 1.0);
+}
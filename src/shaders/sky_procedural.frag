@@ -0,0 +1,48 @@
+#version 450
+
+layout(location = 0) in vec3 fragDirection;
+
+layout(location = 0) out vec4 outColor;
+
+layout(push_constant) uniform SkyPushConstants {
+    vec4 sunDirection; // towards the sun
+    vec4 sunColor;     // w: intensity
+} sky;
+
+const vec3 RAYLEIGH = vec3(5.8e-3, 13.5e-3, 33.1e-3);
+const float MIE = 2.1e-3;
+const float MIE_G = 0.76;
+const float PI = 3.14159265;
+
+// Single scattering approximation of a Z-up atmosphere.
+void main() {
+    vec3 view = normalize(fragDirection);
+    vec3 sun = normalize(sky.sunDirection.xyz);
+
+    // Optical depth grows towards the horizon.
+    float zenith = max(view.z, 0.0) + 0.05;
+    float depth = 1.0 / zenith;
+
+    float mu = dot(view, sun);
+    float rayleighPhase = 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+    float g2 = MIE_G * MIE_G;
+    float miePhase = 3.0 / (8.0 * PI) * ((1.0 - g2) * (1.0 + mu * mu))
+        / ((2.0 + g2) * pow(1.0 + g2 - 2.0 * MIE_G * mu, 1.5));
+
+    vec3 extinction = exp(-(RAYLEIGH + MIE) * depth * 8.0);
+    vec3 scattering = (RAYLEIGH * rayleighPhase + MIE * miePhase) * depth * 8.0;
+
+    // Dim everything as the sun sets.
+    float daylight = smoothstep(-0.2, 0.1, sun.z);
+    vec3 sunLight = sky.sunColor.rgb * sky.sunColor.w * 20.0;
+    vec3 color = sunLight * scattering * mix(vec3(1.0), extinction, 0.5) * daylight;
+
+    // Sun disk.
+    float disk = smoothstep(0.9995, 0.9998, mu);
+    color += sunLight * extinction * disk;
+
+    // Darken the ground below the horizon.
+    color *= mix(0.3, 1.0, smoothstep(-0.1, 0.0, view.z));
+
+    outColor = vec4(1.0 - exp(-color), 1.0);
+}
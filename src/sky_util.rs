@@ -0,0 +1,329 @@
+use std::fs::File;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use vulkanalia::{vk, Device, Instance};
+use vulkanalia::vk::{DeviceV1_0, HasBuilder};
+use crate::image_util::{transition_layouts, LayoutTransition};
+use crate::lights::Vec4;
+use crate::memory_util::{ResourceKind, Strategy};
+use crate::render_app::AppData;
+use crate::resource_util::{DescriptorPool, DescriptorSetLayout, Image, ImageView, Memory, Pipeline, PipelineLayout, Sampler};
+use crate::shader_compiler::ShaderUser;
+use crate::shader_module_util::reflect_pipelines;
+use crate::spirv_reflect::ShaderInterface;
+use crate::transforms::UniformBufferObject;
+
+/// Cubemap faces in Vulkan layer order.
+pub const CUBEMAP_FACES: [&str; 6] = ["px.png", "nx.png", "py.png", "ny.png", "pz.png", "nz.png"];
+
+/// What is drawn behind the scene geometry.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkyMode {
+    /// Nothing, the color attachment is cleared to `SkyData::clear_color`.
+    #[default]
+    ClearColor,
+    /// A cubemap loaded from a directory containing the six `CUBEMAP_FACES`.
+    Cubemap(PathBuf),
+    /// An analytic atmosphere lit by the directional light.
+    Procedural,
+}
+
+/// Push constants of the sky shaders.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SkyPushConstants {
+    /// Direction towards the sun.
+    pub sun_direction: Vec4,
+    pub sun_color: Vec4,
+}
+
+/// The Vulkan objects used to draw the sky.
+#[derive(Debug)]
+pub struct SkyData {
+    pub mode: SkyMode,
+    pub clear_color: [f32; 4],
+
+    pub cubemap_image: Image,
+    pub cubemap_image_memory: Memory,
+    pub cubemap_image_view: ImageView,
+    pub cubemap_sampler: Sampler,
+
+    /// What the sky shaders use, see `create_sky_descriptor_set_layout`.
+    pub interface: ShaderInterface,
+    pub set_layout: DescriptorSetLayout,
+    pub pipeline_layout: PipelineLayout,
+    pub pipeline: Pipeline,
+    pub descriptor_pool: DescriptorPool,
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+impl Default for SkyData {
+    fn default() -> Self {
+        Self {
+            mode: SkyMode::default(),
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            cubemap_image: Image::default(),
+            cubemap_image_memory: Memory::default(),
+            cubemap_image_view: ImageView::default(),
+            cubemap_sampler: Sampler::default(),
+            interface: ShaderInterface::default(),
+            set_layout: DescriptorSetLayout::default(),
+            pipeline_layout: PipelineLayout::default(),
+            pipeline: Pipeline::default(),
+            descriptor_pool: DescriptorPool::default(),
+            descriptor_sets: Vec::new(),
+        }
+    }
+}
+
+impl SkyData {
+    /// Whether a sky pass is drawn at all.
+    pub fn enabled(&self) -> bool {
+        self.mode != SkyMode::ClearColor
+    }
+}
+
+/// Reads one face as tightly packed RGBA8 pixels.
+fn read_face(path: &Path) -> Result<(u32, Vec<u8>)> {
+    let image = File::open(path).map_err(|e| anyhow!("Failed to open `{}`: {}", path.display(), e))?;
+    let decoder = png::Decoder::new(image);
+    let mut reader = decoder.read_info()?;
+
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels)?;
+    pixels.truncate(info.buffer_size());
+
+    if info.width != info.height {
+        return Err(anyhow!("Cubemap face `{}` is not square.", path.display()));
+    }
+
+    let pixels = match (info.color_type, info.bit_depth) {
+        (png::ColorType::Rgba, png::BitDepth::Eight) => pixels,
+        (png::ColorType::Rgb, png::BitDepth::Eight) => pixels
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        (color_type, bit_depth) => return Err(anyhow!(
+            "Cubemap face `{}` has unsupported format {:?} {:?}.", path.display(), color_type, bit_depth)),
+    };
+
+    Ok((info.width, pixels))
+}
+
+/// Loads the six faces in `directory` into a cube compatible image.
+pub unsafe fn create_cubemap(_instance: &Instance, device: &Device, data: &mut AppData, directory: &Path) -> Result<()> {
+    let mut size = 0;
+    let mut pixels = Vec::new();
+    for face in CUBEMAP_FACES {
+        let (face_size, face_pixels) = read_face(&directory.join(face))?;
+        if size != 0 && face_size != size {
+            return Err(anyhow!("Cubemap faces in `{}` differ in size.", directory.display()));
+        }
+        size = face_size;
+        pixels.extend_from_slice(&face_pixels);
+    }
+
+    let face_bytes = (size * size * 4) as u64;
+
+    // Image
+
+    let format = vk::Format::R8G8B8A8_SRGB;
+    let info = vk::ImageCreateInfo::builder()
+        .flags(vk::ImageCreateFlags::CUBE_COMPATIBLE)
+        .image_type(vk::ImageType::_2D)
+        .extent(vk::Extent3D { width: size, height: size, depth: 1 })
+        .mip_levels(1)
+        .array_layers(6)
+        .format(format)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+        .samples(vk::SampleCountFlags::_1)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+    let image = data.deletion_queue.own(device.create_image(&info, None)?);
+    let requirements = device.get_image_memory_requirements(*image);
+    let image_memory = data.deletion_queue.own(data.allocator.allocate(
+        device,
+        requirements,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        ResourceKind::Image,
+        Strategy::FreeList,
+    )?);
+    device.bind_image_memory(*image, image_memory.memory, image_memory.offset)?;
+
+    // Upload
+
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(6);
+
+    let (staging_buffer, staging_offset) = data.uploader.stage(device, &pixels)?;
+    let command_buffer = data.uploader.command_buffer(device)?;
+
+    let faces = |old_layout, new_layout| LayoutTransition::new(*image, old_layout, new_layout).layers(0, 6);
+    transition_layouts(device, command_buffer, &[
+        faces(vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+    ])?;
+
+    let regions = (0..6)
+        .map(|layer| {
+            let subresource = vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(0)
+                .base_array_layer(layer)
+                .layer_count(1);
+            vk::BufferImageCopy::builder()
+                .buffer_offset(staging_offset + layer as u64 * face_bytes)
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(subresource)
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(vk::Extent3D { width: size, height: size, depth: 1 })
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    device.cmd_copy_buffer_to_image(
+        command_buffer,
+        staging_buffer,
+        *image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &regions,
+    );
+
+    data.uploader.transfer_image(device, *image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, *subresource)?;
+    let command_buffer = data.uploader.graphics_command_buffer(device)?;
+
+    transition_layouts(device, command_buffer, &[
+        faces(vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+    ])?;
+
+    // View + Sampler
+
+    let info = vk::ImageViewCreateInfo::builder()
+        .image(*image)
+        .view_type(vk::ImageViewType::CUBE)
+        .format(format)
+        .subresource_range(subresource);
+    let view = data.deletion_queue.own(device.create_image_view(&info, None)?);
+
+    let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .anisotropy_enable(false)
+        .max_anisotropy(1.0)
+        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+        .mip_lod_bias(0.0)
+        .min_lod(0.0)
+        .max_lod(0.0);
+    let sampler = data.deletion_queue.own(device.create_sampler(&info, None)?);
+
+    data.sky.cubemap_image = image;
+    data.sky.cubemap_image_memory = image_memory;
+    data.sky.cubemap_image_view = view;
+    data.sky.cubemap_sampler = sampler;
+
+    Ok(())
+}
+
+/// The shaders of the cubemap and procedural sky pipelines.
+pub(crate) const SKY_SHADERS: [(&str, &str); 2] = [
+    ("sky.vert", "sky_cubemap.frag"),
+    ("sky.vert", "sky_procedural.frag"),
+];
+
+/// Creates the sky set layout from `SKY_SHADERS`, so it fits whichever mode
+/// is used, and keeps their interface for the pipeline layout.
+pub unsafe fn create_sky_descriptor_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    data.sky.interface = reflect_pipelines(data, &SKY_SHADERS, ShaderUser::Sky)?;
+    let bindings = data.sky.interface.set_layout_bindings(0);
+    let info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .bindings(&bindings);
+    data.sky.set_layout = data.deletion_queue.own(device.create_descriptor_set_layout(&info, None)?);
+    Ok(())
+}
+
+/// Allocates one sky descriptor set per frame in flight. The cubemap binding
+/// is only written when a cubemap is loaded.
+pub unsafe fn create_sky_descriptor_sets(device: &Device, data: &mut AppData) -> Result<()> {
+    let count = data.config.frames_in_flight as u32;
+
+    let ubo_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(count);
+
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(count);
+
+    let pool_sizes = &[ubo_size, sampler_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(count);
+    data.sky.descriptor_pool = data.deletion_queue.own(device.create_descriptor_pool(&info, None)?);
+
+    let layouts = vec![*data.sky.set_layout; count as usize];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.sky.descriptor_pool)
+        .set_layouts(&layouts);
+    data.sky.descriptor_sets = device.allocate_descriptor_sets(&info)?;
+
+    for (i, set) in data.sky.descriptor_sets.iter().enumerate() {
+        let info = vk::DescriptorBufferInfo::builder()
+            .buffer(*data.uniform_buffers[i])
+            .offset(0)
+            .range(size_of::<UniformBufferObject>() as u64);
+
+        let buffer_info = &[info];
+        let ubo_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(buffer_info);
+
+        let info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(*data.sky.cubemap_image_view)
+            .sampler(*data.sky.cubemap_sampler);
+
+        let image_info = &[info];
+        let cubemap_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*set)
+            .dst_binding(1)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(image_info);
+
+        if matches!(data.sky.mode, SkyMode::Cubemap(_)) {
+            device.update_descriptor_sets(&[ubo_write, cubemap_write], &[] as &[vk::CopyDescriptorSet]);
+        } else {
+            device.update_descriptor_sets(&[ubo_write], &[] as &[vk::CopyDescriptorSet]);
+        }
+    }
+
+    Ok(())
+}
+
+/// Drops the swapchain sized resources of the sky pass, the deletion queue
+/// destroys them.
+pub fn release_sky_swapchain_objects(data: &mut AppData) {
+    data.sky.descriptor_pool = DescriptorPool::default();
+    data.sky.descriptor_sets.clear();
+    data.sky.pipeline = Pipeline::default();
+    data.sky.pipeline_layout = PipelineLayout::default();
+}
@@ -0,0 +1,653 @@
+use std::collections::{BTreeMap, HashMap};
+use anyhow::{anyhow, Result};
+use vulkanalia::vk;
+use vulkanalia::vk::HasBuilder;
+
+const MAGIC: u32 = 0x0723_0203;
+
+// Opcodes
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_SPEC_CONSTANT_TRUE: u32 = 48;
+const OP_SPEC_CONSTANT_FALSE: u32 = 49;
+const OP_SPEC_CONSTANT: u32 = 50;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+// Decorations
+const DECORATION_SPEC_ID: u32 = 1;
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+// Storage classes
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_OUTPUT: u32 = 3;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+/// A descriptor a shader declares.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub descriptor_type: vk::DescriptorType,
+    /// Product of the array lengths, 1 for a single descriptor.
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+}
+
+/// The scalar type of a specialization constant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ConstantType {
+    Bool,
+    Int,
+    Uint,
+    Float,
+}
+
+/// What a pipeline's shaders expect from its layout and vertex input, read
+/// from their SPIR-V. Built per stage with `reflect` and combined with
+/// `merge`.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderInterface {
+    /// Keyed by set and binding.
+    pub bindings: BTreeMap<(u32, u32), ReflectedBinding>,
+    /// Covers every stage's push constant block, from offset 0.
+    pub push_constants: Option<vk::PushConstantRange>,
+    /// Locations and formats of the vertex shader's inputs.
+    pub vertex_inputs: BTreeMap<u32, vk::Format>,
+    /// Specialization constants by constant ID. Only filled for a single
+    /// stage, `merge` leaves it empty since IDs are per stage.
+    pub specialization: BTreeMap<u32, ConstantType>,
+    /// Locations and formats of the last stage's outputs, checked against
+    /// the inputs of the next stage by `merge`.
+    outputs: BTreeMap<u32, vk::Format>,
+    /// Inputs of a non-vertex stage.
+    inputs: BTreeMap<u32, vk::Format>,
+    stages: vk::ShaderStageFlags,
+}
+
+impl ShaderInterface {
+    /// The layout bindings of descriptor set `set`.
+    pub fn set_layout_bindings(&self, set: u32) -> Vec<vk::DescriptorSetLayoutBinding> {
+        self.bindings
+            .range((set, 0)..=(set, u32::MAX))
+            .map(|(&(_, binding), b)| vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(b.descriptor_type)
+                .descriptor_count(b.count)
+                .stage_flags(b.stages)
+                .build())
+            .collect()
+    }
+
+    /// Combines the interfaces of the stages of one pipeline, in pipeline
+    /// order. Fails when stages declare the same binding differently or a
+    /// stage reads an input the previous one doesn't write.
+    pub fn merge(stages: &[ShaderInterface]) -> Result<ShaderInterface> {
+        let mut merged = ShaderInterface::default();
+        for stage in stages {
+            merged.add_resources(stage)?;
+            if stage.stages == vk::ShaderStageFlags::VERTEX {
+                merged.vertex_inputs = stage.vertex_inputs.clone();
+            } else if !merged.stages.is_empty() {
+                for (location, format) in &stage.inputs {
+                    match merged.outputs.get(location) {
+                        Some(output) if output == format => {}
+                        Some(output) => return Err(anyhow!(
+                            "Location {} is written as {:?} by the {:?} stage but read as {:?} by the {:?} stage.",
+                            location, output, merged.stages, format, stage.stages,
+                        )),
+                        None => return Err(anyhow!(
+                            "The {:?} stage reads location {}, which the {:?} stage doesn't write.",
+                            stage.stages, location, merged.stages,
+                        )),
+                    }
+                }
+            }
+            merged.outputs = stage.outputs.clone();
+            merged.stages |= stage.stages;
+        }
+        Ok(merged)
+    }
+
+    /// Combines the merged interfaces of pipelines that share a layout. Only
+    /// their bindings and push constants have to agree.
+    pub fn union(pipelines: &[ShaderInterface]) -> Result<ShaderInterface> {
+        let mut union = ShaderInterface::default();
+        for pipeline in pipelines {
+            union.add_resources(pipeline)?;
+            union.stages |= pipeline.stages;
+        }
+        Ok(union)
+    }
+
+    /// Adds the bindings and push constants of `other`.
+    fn add_resources(&mut self, other: &ShaderInterface) -> Result<()> {
+        for (&(set, binding), b) in &other.bindings {
+            match self.bindings.get_mut(&(set, binding)) {
+                Some(existing) if existing.descriptor_type != b.descriptor_type || existing.count != b.count => {
+                    return Err(anyhow!(
+                        "Set {} binding {} is {} {:?} in the {:?} stage but {} {:?} in the {:?} stage.",
+                        set, binding, existing.count, existing.descriptor_type, existing.stages,
+                        b.count, b.descriptor_type, b.stages,
+                    ));
+                }
+                Some(existing) => existing.stages |= b.stages,
+                None => {
+                    self.bindings.insert((set, binding), *b);
+                }
+            }
+        }
+
+        self.push_constants = match (self.push_constants, other.push_constants) {
+            (Some(a), Some(b)) => Some(vk::PushConstantRange {
+                stage_flags: a.stage_flags | b.stage_flags,
+                offset: 0,
+                size: a.size.max(b.size),
+            }),
+            (a, b) => a.or(b),
+        };
+        Ok(())
+    }
+
+    /// Checks that every binding and push constant of `shaders` is provided
+    /// by this interface, which a layout was created from.
+    pub fn check_provides(&self, shaders: &ShaderInterface) -> Result<()> {
+        for (&(set, binding), b) in &shaders.bindings {
+            match self.bindings.get(&(set, binding)) {
+                Some(provided) if provided.descriptor_type == b.descriptor_type
+                    && provided.count >= b.count
+                    && provided.stages.contains(b.stages) => {}
+                Some(provided) => return Err(anyhow!(
+                    "Set {} binding {} is {} {:?} for {:?}, the shaders expect {} {:?} for {:?}.",
+                    set, binding, provided.count, provided.descriptor_type, provided.stages,
+                    b.count, b.descriptor_type, b.stages,
+                )),
+                None => return Err(anyhow!("The shaders use set {} binding {}, which the layout lacks.", set, binding)),
+            }
+        }
+        if let Some(used) = shaders.push_constants {
+            match self.push_constants {
+                Some(provided) if provided.size >= used.size && provided.stage_flags.contains(used.stage_flags) => {}
+                _ => return Err(anyhow!(
+                    "The shaders use {} bytes of push constants in {:?}, the layout has {:?}.",
+                    used.size, used.stage_flags, self.push_constants,
+                )),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A type declared by the module, as far as reflection needs it.
+#[derive(Clone, Debug)]
+enum Type {
+    Bool,
+    Scalar { float: bool, signed: bool, width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray,
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+}
+
+#[derive(Default)]
+struct Module {
+    stage: vk::ShaderStageFlags,
+    names: HashMap<u32, String>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    /// Decorations of ids, by decoration.
+    decorations: HashMap<(u32, u32), u32>,
+    member_offsets: HashMap<(u32, u32), u32>,
+    member_matrix_strides: HashMap<(u32, u32), u32>,
+    /// Variables with their pointer type and storage class.
+    variables: Vec<(u32, u32, u32)>,
+    /// Specialization constants with their type.
+    spec_constants: Vec<(u32, u32)>,
+}
+
+/// Reads the interface of a single stage shader module.
+pub fn reflect(spirv: &[u8]) -> Result<ShaderInterface> {
+    if !spirv.len().is_multiple_of(4) || spirv.len() < 20 {
+        return Err(anyhow!("SPIR-V must be a whole number of words, at least the header."));
+    }
+    let mut words = spirv.chunks_exact(4).map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]])).collect::<Vec<_>>();
+    if words[0] == MAGIC.swap_bytes() {
+        words.iter_mut().for_each(|w| *w = w.swap_bytes());
+    } else if words[0] != MAGIC {
+        return Err(anyhow!("Not SPIR-V, the magic number is {:#010x}.", words[0]));
+    }
+
+    let module = parse(&words[5..])?;
+    module.interface()
+}
+
+fn parse(mut words: &[u32]) -> Result<Module> {
+    let mut module = Module::default();
+    while let Some(&first) = words.first() {
+        let (count, opcode) = ((first >> 16) as usize, first & 0xffff);
+        if count == 0 || count > words.len() {
+            return Err(anyhow!("Truncated SPIR-V instruction {}.", opcode));
+        }
+        let operands = &words[1..count];
+        let operand = |i: usize| operands.get(i).copied().ok_or_else(|| anyhow!("Truncated SPIR-V instruction {}.", opcode));
+        match opcode {
+            OP_NAME => {
+                module.names.insert(operand(0)?, string(&operands[1..]));
+            }
+            OP_ENTRY_POINT if module.stage.is_empty() => {
+                module.stage = match operand(0)? {
+                    0 => vk::ShaderStageFlags::VERTEX,
+                    1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+                    2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+                    3 => vk::ShaderStageFlags::GEOMETRY,
+                    4 => vk::ShaderStageFlags::FRAGMENT,
+                    5 => vk::ShaderStageFlags::COMPUTE,
+                    model => return Err(anyhow!("Unsupported execution model {}.", model)),
+                };
+            }
+            OP_TYPE_BOOL => {
+                module.types.insert(operand(0)?, Type::Bool);
+            }
+            OP_TYPE_INT => {
+                let ty = Type::Scalar { float: false, signed: operand(2)? == 1, width: operand(1)? };
+                module.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_FLOAT => {
+                module.types.insert(operand(0)?, Type::Scalar { float: true, signed: true, width: operand(1)? });
+            }
+            OP_TYPE_VECTOR => {
+                module.types.insert(operand(0)?, Type::Vector { component: operand(1)?, count: operand(2)? });
+            }
+            OP_TYPE_MATRIX => {
+                module.types.insert(operand(0)?, Type::Matrix { column: operand(1)?, count: operand(2)? });
+            }
+            OP_TYPE_IMAGE => {
+                module.types.insert(operand(0)?, Type::Image { dim: operand(2)?, sampled: operand(6)? });
+            }
+            OP_TYPE_SAMPLER => {
+                module.types.insert(operand(0)?, Type::Sampler);
+            }
+            OP_TYPE_SAMPLED_IMAGE => {
+                module.types.insert(operand(0)?, Type::SampledImage);
+            }
+            OP_TYPE_ARRAY => {
+                // The length is resolved once all constants are known.
+                module.types.insert(operand(0)?, Type::Array { element: operand(1)?, length: operand(2)? });
+            }
+            OP_TYPE_RUNTIME_ARRAY => {
+                module.types.insert(operand(0)?, Type::RuntimeArray);
+            }
+            OP_TYPE_STRUCT => {
+                module.types.insert(operand(0)?, Type::Struct { members: operands[1..].to_vec() });
+            }
+            OP_TYPE_POINTER => {
+                module.types.insert(operand(0)?, Type::Pointer { pointee: operand(2)? });
+            }
+            OP_CONSTANT => {
+                module.constants.insert(operand(1)?, operand(2)?);
+            }
+            OP_SPEC_CONSTANT_TRUE | OP_SPEC_CONSTANT_FALSE | OP_SPEC_CONSTANT => {
+                module.spec_constants.push((operand(1)?, operand(0)?));
+            }
+            OP_VARIABLE => module.variables.push((operand(1)?, operand(0)?, operand(2)?)),
+            OP_DECORATE => {
+                module.decorations.insert((operand(0)?, operand(1)?), operands.get(2).copied().unwrap_or(0));
+            }
+            OP_MEMBER_DECORATE => {
+                let member = (operand(0)?, operand(1)?);
+                match operand(2)? {
+                    DECORATION_OFFSET => {
+                        module.member_offsets.insert(member, operand(3)?);
+                    }
+                    DECORATION_MATRIX_STRIDE => {
+                        module.member_matrix_strides.insert(member, operand(3)?);
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        words = &words[count..];
+    }
+    if module.stage.is_empty() {
+        return Err(anyhow!("The SPIR-V module has no entry point."));
+    }
+    Ok(module)
+}
+
+/// A nul terminated string packed into words.
+fn string(words: &[u32]) -> String {
+    let bytes = words.iter().flat_map(|w| w.to_le_bytes()).take_while(|&b| b != 0).collect::<Vec<_>>();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+impl Module {
+    fn interface(&self) -> Result<ShaderInterface> {
+        let mut interface = ShaderInterface { stages: self.stage, ..Default::default() };
+        for &(id, pointer, storage) in &self.variables {
+            let Some(Type::Pointer { pointee }) = self.types.get(&pointer) else {
+                return Err(anyhow!("Variable {} is not a pointer.", self.name(id)));
+            };
+            let pointee = *pointee;
+            match storage {
+                STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                    let (descriptor_type, count) = self.descriptor(id, pointee, storage)?;
+                    let set = self.decoration(id, DECORATION_DESCRIPTOR_SET).unwrap_or(0);
+                    let binding = self.decoration(id, DECORATION_BINDING)
+                        .ok_or_else(|| anyhow!("Resource {} has no binding.", self.name(id)))?;
+                    let binding_info = ReflectedBinding { descriptor_type, count, stages: self.stage };
+                    if interface.bindings.insert((set, binding), binding_info).is_some() {
+                        return Err(anyhow!("Set {} binding {} is declared twice.", set, binding));
+                    }
+                }
+                STORAGE_PUSH_CONSTANT => {
+                    interface.push_constants = Some(vk::PushConstantRange {
+                        stage_flags: self.stage,
+                        offset: 0,
+                        size: self.size(pointee, None)?,
+                    });
+                }
+                STORAGE_INPUT | STORAGE_OUTPUT => {
+                    // Built ins have no location.
+                    let Some(location) = self.decoration(id, DECORATION_LOCATION) else {
+                        continue;
+                    };
+                    let format = self.format(pointee)?;
+                    let locations = match storage {
+                        STORAGE_INPUT if self.stage == vk::ShaderStageFlags::VERTEX => &mut interface.vertex_inputs,
+                        STORAGE_INPUT => &mut interface.inputs,
+                        _ => &mut interface.outputs,
+                    };
+                    locations.insert(location, format);
+                }
+                _ => {}
+            }
+        }
+
+        for &(id, ty) in &self.spec_constants {
+            let Some(constant_id) = self.decoration(id, DECORATION_SPEC_ID) else {
+                continue;
+            };
+            let constant_type = match self.ty(ty)? {
+                Type::Bool => ConstantType::Bool,
+                Type::Scalar { float: true, width: 32, .. } => ConstantType::Float,
+                Type::Scalar { float: false, signed: true, width: 32 } => ConstantType::Int,
+                Type::Scalar { float: false, signed: false, width: 32 } => ConstantType::Uint,
+                _ => return Err(anyhow!("Specialization constant {} is not a 32 bit scalar.", self.name(id))),
+            };
+            interface.specialization.insert(constant_id, constant_type);
+        }
+        Ok(interface)
+    }
+
+    fn name(&self, id: u32) -> String {
+        self.names.get(&id).filter(|n| !n.is_empty()).cloned().unwrap_or_else(|| format!("%{}", id))
+    }
+
+    fn decoration(&self, id: u32, decoration: u32) -> Option<u32> {
+        self.decorations.get(&(id, decoration)).copied()
+    }
+
+    fn ty(&self, id: u32) -> Result<&Type> {
+        self.types.get(&id).ok_or_else(|| anyhow!("Unknown SPIR-V type %{}.", id))
+    }
+
+    fn descriptor(&self, variable: u32, mut ty: u32, storage: u32) -> Result<(vk::DescriptorType, u32)> {
+        let mut count = 1;
+        loop {
+            match self.ty(ty)? {
+                Type::Array { element, length } => {
+                    count *= self.constants.get(length).copied().unwrap_or(1);
+                    ty = *element;
+                }
+                Type::RuntimeArray => {
+                    return Err(anyhow!("Resource {} is a runtime array, which is unsupported.", self.name(variable)));
+                }
+                _ => break,
+            }
+        }
+
+        let descriptor_type = match (storage, self.ty(ty)?) {
+            (STORAGE_UNIFORM_CONSTANT, Type::SampledImage) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (STORAGE_UNIFORM_CONSTANT, Type::Sampler) => vk::DescriptorType::SAMPLER,
+            (STORAGE_UNIFORM_CONSTANT, Type::Image { dim: DIM_SUBPASS_DATA, .. }) => vk::DescriptorType::INPUT_ATTACHMENT,
+            (STORAGE_UNIFORM_CONSTANT, Type::Image { dim: DIM_BUFFER, sampled: 2 }) => {
+                vk::DescriptorType::STORAGE_TEXEL_BUFFER
+            }
+            (STORAGE_UNIFORM_CONSTANT, Type::Image { dim: DIM_BUFFER, .. }) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            (STORAGE_UNIFORM_CONSTANT, Type::Image { sampled: 2, .. }) => vk::DescriptorType::STORAGE_IMAGE,
+            (STORAGE_UNIFORM_CONSTANT, Type::Image { .. }) => vk::DescriptorType::SAMPLED_IMAGE,
+            (STORAGE_UNIFORM, _) if self.decoration(ty, DECORATION_BUFFER_BLOCK).is_some() => {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            (STORAGE_UNIFORM, _) if self.decoration(ty, DECORATION_BLOCK).is_some() => {
+                vk::DescriptorType::UNIFORM_BUFFER
+            }
+            (STORAGE_STORAGE_BUFFER, _) => vk::DescriptorType::STORAGE_BUFFER,
+            _ => return Err(anyhow!("Unsupported resource {}.", self.name(variable))),
+        };
+        Ok((descriptor_type, count))
+    }
+
+    /// Size in bytes of a block member of type `ty`. `member` locates a
+    /// matrix's stride decoration.
+    fn size(&self, ty: u32, member: Option<(u32, u32)>) -> Result<u32> {
+        Ok(match self.ty(ty)? {
+            Type::Scalar { width, .. } => width / 8,
+            Type::Vector { component, count } => self.size(*component, None)? * count,
+            Type::Matrix { column, count } => {
+                let stride = member.and_then(|m| self.member_matrix_strides.get(&m).copied());
+                match stride {
+                    Some(stride) => stride * count,
+                    None => self.size(*column, None)?.next_multiple_of(16) * count,
+                }
+            }
+            Type::Array { element, length } => {
+                let length = self.constants.get(length).copied().unwrap_or(1);
+                let stride = match self.decoration(ty, DECORATION_ARRAY_STRIDE) {
+                    Some(stride) => stride,
+                    None => self.size(*element, member)?,
+                };
+                stride * length
+            }
+            Type::Struct { members } => {
+                let mut size = 0;
+                for (index, &member_ty) in members.iter().enumerate() {
+                    let offset = self.member_offsets.get(&(ty, index as u32)).copied().unwrap_or(size);
+                    size = size.max(offset + self.size(member_ty, Some((ty, index as u32)))?);
+                }
+                size
+            }
+            other => return Err(anyhow!("{:?} has no size in a block.", other)),
+        })
+    }
+
+    /// The vertex attribute format matching an input or output of type `ty`.
+    fn format(&self, ty: u32) -> Result<vk::Format> {
+        let (component, count) = match self.ty(ty)? {
+            Type::Vector { component, count } => (*component, *count),
+            Type::Scalar { .. } => (ty, 1),
+            other => return Err(anyhow!("Unsupported stage input or output type {:?}.", other)),
+        };
+        let Type::Scalar { float, signed, width: 32 } = *self.ty(component)? else {
+            return Err(anyhow!("Only 32 bit stage inputs and outputs are supported."));
+        };
+        use vk::Format as F;
+        let formats = match (float, signed) {
+            (true, _) => [F::R32_SFLOAT, F::R32G32_SFLOAT, F::R32G32B32_SFLOAT, F::R32G32B32A32_SFLOAT],
+            (false, true) => [F::R32_SINT, F::R32G32_SINT, F::R32G32B32_SINT, F::R32G32B32A32_SINT],
+            (false, false) => [F::R32_UINT, F::R32G32_UINT, F::R32G32B32_UINT, F::R32G32B32A32_UINT],
+        };
+        formats.get(count as usize - 1).copied().ok_or_else(|| anyhow!("Vectors have at most 4 components."))
+    }
+}
+
+/// The attributes of `available` the vertex shader reads, failing when it
+/// reads a location the vertex type lacks or with a different format.
+pub fn vertex_attributes(
+    available: &[vk::VertexInputAttributeDescription],
+    interface: &ShaderInterface,
+) -> Result<Vec<vk::VertexInputAttributeDescription>> {
+    interface.vertex_inputs
+        .iter()
+        .map(|(&location, &format)| match available.iter().find(|a| a.location == location) {
+            Some(attribute) if attribute.format == format => Ok(*attribute),
+            Some(attribute) => Err(anyhow!(
+                "The vertex shader reads location {} as {:?}, the vertex provides {:?}.",
+                location, format, attribute.format,
+            )),
+            None => Err(anyhow!("The vertex shader reads location {}, which the vertex lacks.", location)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader_compiler::EMBEDDED_SHADERS;
+
+    fn embedded(name: &str) -> ShaderInterface {
+        embedded_variant(name, &[])
+    }
+
+    fn embedded_variant(name: &str, defines: &[(&str, &str)]) -> ShaderInterface {
+        let shader = EMBEDDED_SHADERS
+            .iter()
+            .find(|shader| shader.name == name && shader.defines == defines)
+            .unwrap();
+        reflect(shader.spirv).unwrap()
+    }
+
+    #[test]
+    fn object_shaders() {
+        let vert = embedded("shader.vert");
+        let frag = embedded("shader.frag");
+        assert_eq!(vert.bindings, BTreeMap::from([((0, 0), ReflectedBinding {
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            count: 1,
+            stages: vk::ShaderStageFlags::VERTEX,
+        })]));
+        assert_eq!(frag.bindings, BTreeMap::from([((0, 1), ReflectedBinding {
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            count: 1,
+            stages: vk::ShaderStageFlags::FRAGMENT,
+        })]));
+        assert_eq!(vert.vertex_inputs, BTreeMap::from([
+            (0, vk::Format::R32G32B32_SFLOAT),
+            (1, vk::Format::R32G32B32_SFLOAT),
+            (2, vk::Format::R32G32_SFLOAT),
+        ]));
+
+        let merged = ShaderInterface::merge(&[vert, frag]).unwrap();
+        assert_eq!(merged.bindings.len(), 2);
+        // A matrix, a color and the alpha cutoff.
+        let push_constants = merged.push_constants.unwrap();
+        assert_eq!(push_constants.size, 64 + 16 + 4);
+        assert_eq!(push_constants.stage_flags, vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+    }
+
+    #[test]
+    fn cutout_variant_fits_the_object_layout() {
+        let object = ShaderInterface::merge(&[embedded("shader.vert"), embedded("shader.frag")]).unwrap();
+        let cutout = ShaderInterface::merge(&[
+            embedded("shader.vert"),
+            embedded_variant("shader.frag", &[("ALPHA_TEST", "1")]),
+        ]).unwrap();
+        object.check_provides(&cutout).unwrap();
+    }
+
+    /// The interface of pipelines sharing a layout, like `reflect_pipelines`.
+    fn pipelines(pipelines: &[(&str, &str)]) -> ShaderInterface {
+        let merged = pipelines
+            .iter()
+            .map(|(vert, frag)| ShaderInterface::merge(&[embedded(vert), embedded(frag)]).unwrap())
+            .collect::<Vec<_>>();
+        ShaderInterface::union(&merged).unwrap()
+    }
+
+    #[test]
+    fn lighting_shaders() {
+        let lighting = pipelines(&crate::deferred_util::LIGHTING_SHADERS);
+        let types = lighting.bindings.values().map(|b| (b.descriptor_type, b.stages)).collect::<Vec<_>>();
+        assert_eq!(types, [
+            (vk::DescriptorType::INPUT_ATTACHMENT, vk::ShaderStageFlags::FRAGMENT),
+            (vk::DescriptorType::INPUT_ATTACHMENT, vk::ShaderStageFlags::FRAGMENT),
+            (vk::DescriptorType::INPUT_ATTACHMENT, vk::ShaderStageFlags::FRAGMENT),
+            (vk::DescriptorType::INPUT_ATTACHMENT, vk::ShaderStageFlags::FRAGMENT),
+            (vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT),
+        ]);
+        assert_eq!(lighting.push_constants, None);
+    }
+
+    #[test]
+    fn sky_shaders() {
+        let sky = pipelines(&crate::sky_util::SKY_SHADERS);
+        assert_eq!(sky.set_layout_bindings(0).len(), 2);
+        let push_constants = sky.push_constants.unwrap();
+        assert_eq!(push_constants.size as usize, size_of::<crate::sky_util::SkyPushConstants>());
+        assert_eq!(push_constants.stage_flags, vk::ShaderStageFlags::FRAGMENT);
+    }
+
+    #[test]
+    fn object_layout_fits_the_shaders_sharing_it() {
+        let object = ShaderInterface::merge(&[embedded("shader.vert"), embedded("shader.frag")]).unwrap();
+        let mut shared = vec![("gbuffer.vert", "gbuffer.frag"), ("debug_line.vert", "debug_line.frag")];
+        for view in crate::debug_view::DebugView::ALL {
+            if let Some(frag) = view.fragment_shader() {
+                shared.push(("debug.vert", frag));
+            }
+        }
+        for (vert, frag) in shared {
+            let shaders = ShaderInterface::merge(&[embedded(vert), embedded(frag)]).unwrap();
+            object.check_provides(&shaders).unwrap_or_else(|e| panic!("{} and {}: {}", vert, frag, e));
+        }
+    }
+
+    #[test]
+    fn merge_rejects_stages_that_disagree() {
+        let frag = embedded("shader.frag");
+        let mut vert = embedded("shader.vert");
+        vert.bindings.insert((0, 1), ReflectedBinding {
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            count: 1,
+            stages: vk::ShaderStageFlags::VERTEX,
+        });
+        let error = ShaderInterface::merge(&[vert, frag.clone()]).unwrap_err().to_string();
+        assert!(error.starts_with("Set 0 binding 1 is 1 UNIFORM_BUFFER"), "{}", error);
+
+        let mut vert = embedded("shader.vert");
+        vert.outputs.remove(&1);
+        let error = ShaderInterface::merge(&[vert, frag]).unwrap_err().to_string();
+        assert!(error.contains("reads location 1"), "{}", error);
+    }
+}
@@ -1,5 +1,5 @@
 use vulkanalia::{vk, Device, Instance};
-use vulkanalia::vk::{DeviceV1_0, Handle, HasBuilder, KhrSurfaceExtension, KhrSwapchainExtension};
+use vulkanalia::vk::{Handle, HasBuilder, KhrSurfaceExtension, KhrSwapchainExtension};
 use winit::window::Window;
 use crate::{AppData, QueueFamilyIndices};
 use crate::image_util::create_image_view;
@@ -17,13 +17,15 @@ fn get_swapchain_surface_format(
         .unwrap_or_else(|| formats[0])
 }
 
+/// The configured present mode, or FIFO which is always available.
 fn get_swapchain_present_mode(
     present_modes: &[vk::PresentModeKHR],
+    preferred: vk::PresentModeKHR,
 ) -> vk::PresentModeKHR {
     present_modes
         .iter()
         .cloned()
-        .find(|m| *m == vk::PresentModeKHR::IMMEDIATE) // Immediate seems to work the best
+        .find(|m| *m == preferred)
         .unwrap_or(vk::PresentModeKHR::FIFO)
 }
 
@@ -57,7 +59,7 @@ pub unsafe fn create_swapchain(
     let support = SwapchainSupport::get(instance, data, data.physical_device)?;
 
     let surface_format = get_swapchain_surface_format(&support.formats);
-    let present_mode = get_swapchain_present_mode(&support.present_modes);
+    let present_mode = get_swapchain_present_mode(&support.present_modes, data.config.present_mode.to_vk());
     let extent = get_swapchain_extent(window, support.capabilities);
 
     let mut image_count = support.capabilities.min_image_count + 1;
@@ -92,8 +94,8 @@ pub unsafe fn create_swapchain(
         .clipped(true)
         .old_swapchain(vk::SwapchainKHR::null());
 
-    data.swapchain = device.create_swapchain_khr(&info, None)?;
-    data.swapchain_images = device.get_swapchain_images_khr(data.swapchain)?;
+    data.swapchain = data.deletion_queue.own(device.create_swapchain_khr(&info, None)?);
+    data.swapchain_images = device.get_swapchain_images_khr(*data.swapchain)?;
 
     data.swapchain_format = surface_format.format;
     data.swapchain_extent = extent;
@@ -114,7 +116,7 @@ pub unsafe fn create_swapchain_image_views(device: &Device, data: &mut AppData)
     data.swapchain_image_views = data
         .swapchain_images
         .iter()
-        .map(|i| create_image_view(device, *i, data.swapchain_format, vk::ImageAspectFlags::COLOR, 1/* u32 */))
+        .map(|i| create_image_view(device, data, *i, data.swapchain_format, vk::ImageAspectFlags::COLOR, 1/* u32 */))
         .collect::<Result<Vec<_>, _>>()?;
 
     Ok(())
@@ -1,25 +1,81 @@
+use anyhow::Result;
 use vulkanalia::{vk, Device};
-use vulkanalia::vk::{DeviceV1_0, Handle, HasBuilder};
-use crate::MAX_FRAMES_IN_FLIGHT;
+use vulkanalia::vk::{DeviceV1_0, DeviceV1_2, Handle, HasBuilder};
 use crate::render_app::AppData;
+use crate::resource_util::{DeletionQueue, Semaphore};
 
+/// Creates the per frame semaphores, and the fences or the frame timeline
+/// that pace the CPU.
 pub unsafe fn create_sync_objects(device: &Device, data: &mut AppData) -> anyhow::Result<()> {
     let semaphore_info = vk::SemaphoreCreateInfo::builder();
     let fence_info = vk::FenceCreateInfo::builder()
         .flags(vk::FenceCreateFlags::SIGNALED);
 
-    for _ in 0..MAX_FRAMES_IN_FLIGHT {
+    for _ in 0..data.config.frames_in_flight {
+        let queue = &data.deletion_queue;
         data.image_available_semaphores
-            .push(device.create_semaphore(&semaphore_info, None)?);
+            .push(queue.own(device.create_semaphore(&semaphore_info, None)?));
         data.render_finished_semaphores
-            .push(device.create_semaphore(&semaphore_info, None)?);
+            .push(queue.own(device.create_semaphore(&semaphore_info, None)?));
 
-        data.in_flight_fences.push(device.create_fence(&fence_info, None)?);
+        if !data.timeline_semaphores {
+            data.in_flight_fences.push(queue.own(device.create_fence(&fence_info, None)?));
+        }
     }
     data.images_in_flight = data.swapchain_images
         .iter()
         .map(|_| vk::Fence::null())
         .collect();
 
+    if data.timeline_semaphores {
+        data.frame_timeline = Timeline::new(device, &data.deletion_queue)?;
+    }
+
     Ok(())
-}
\ No newline at end of file
+}
+
+/// A timeline semaphore. Submissions signal increasing values on it, and
+/// the CPU can ask which value the GPU has reached or wait for one. Only
+/// available with `AppData::timeline_semaphores`.
+#[derive(Debug, Default)]
+pub struct Timeline {
+    semaphore: Semaphore,
+}
+
+impl Timeline {
+    /// A timeline starting at 0.
+    pub unsafe fn new(device: &Device, deletion_queue: &DeletionQueue) -> Result<Self> {
+        let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let info = vk::SemaphoreCreateInfo::builder()
+            .push_next(&mut type_info);
+        let semaphore = deletion_queue.own(device.create_semaphore(&info, None)?);
+        Ok(Self { semaphore })
+    }
+
+    pub fn semaphore(&self) -> vk::Semaphore {
+        *self.semaphore
+    }
+
+    /// The highest value the GPU has signaled.
+    pub unsafe fn value(&self, device: &Device) -> Result<u64> {
+        Ok(device.get_semaphore_counter_value(*self.semaphore)?)
+    }
+
+    /// Whether the GPU has reached `value`.
+    pub unsafe fn reached(&self, device: &Device, value: u64) -> Result<bool> {
+        Ok(self.value(device)? >= value)
+    }
+
+    /// Blocks until the GPU has reached `value`.
+    pub unsafe fn wait(&self, device: &Device, value: u64) -> Result<()> {
+        let semaphores = &[*self.semaphore];
+        let values = &[value];
+        let info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(semaphores)
+            .values(values);
+        device.wait_semaphores(&info, u64::MAX)?;
+        Ok(())
+    }
+}
@@ -1,6 +1,5 @@
-use cgmath::{BaseFloat, Deg, Matrix4, Rad};
-use vulkanalia::vk;
-use vulkanalia::vk::HasBuilder;
+use cgmath::{Deg, Matrix4};
+use crate::lights::Vec4;
 
 pub type Mat4 = cgmath::Matrix4<f32>;
 pub type Vec3 = cgmath::Vector3<f32>;
@@ -10,7 +9,9 @@ pub type Vec2 = cgmath::Vector2<f32>;
 pub struct UniformBufferObject {
     pub model: Mat4,
     pub view: Mat4,
-    pub proj: Mat4
+    pub proj: Mat4,
+    /// x: depth of the far plane, 0 with reverse-Z and 1 otherwise.
+    pub depth_range: Vec4,
 }
 
 
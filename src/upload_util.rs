@@ -0,0 +1,780 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem::size_of_val;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::ptr::copy_nonoverlapping as memcpy;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use vulkanalia::{vk, Device, Instance};
+use vulkanalia::vk::{DeviceV1_0, Handle, HasBuilder};
+use crate::buffer_util::create_buffer;
+use crate::image_util::{create_texture_image_view, create_texture_sampler, read_texture, upload_texture, TextureData};
+use crate::memory_util::{Allocator, ResourceKind, Strategy};
+use crate::queue_family_indices::QueueFamilyIndices;
+use crate::render_app::AppData;
+use crate::resource_util::{Buffer, DeletionQueue, Fence, Image, Memory, Semaphore};
+use crate::scene::Mesh;
+use crate::sync_util::Timeline;
+use crate::vertexbuffer_util::{create_geometry, read_model, Vertex};
+
+/// Bytes of the persistently mapped staging ring. Larger uploads get a
+/// staging buffer of their own.
+pub const STAGING_RING_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Offset alignment of staged data, enough for any texel size.
+const STAGING_ALIGNMENT: u64 = 16;
+
+/// Identifies a submitted batch, see `Uploader::is_complete`.
+pub type BatchId = u64;
+
+/// The values a batch waits for and signals on the upload timeline.
+///
+/// The copies of batch `n` signal `2n - 1` and the graphics side `2n`. Both
+/// queues signal the same semaphore, so the copies also wait for the graphics
+/// side of the previous batch to keep the signaled values increasing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct TimelineValues {
+    transfer_wait: u64,
+    transfer_signal: u64,
+    graphics_signal: u64,
+}
+
+impl TimelineValues {
+    fn new(ticket: BatchId) -> Self {
+        Self {
+            transfer_wait: ticket * 2 - 2,
+            transfer_signal: ticket * 2 - 1,
+            graphics_signal: ticket * 2,
+        }
+    }
+}
+
+/// Identifies a model or texture requested from `Uploads`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UploadTicket(u64);
+
+/// An asset read and decoded on a loader thread.
+#[derive(Debug)]
+enum Asset {
+    Model { vertices: Vec<Vertex>, indices: Vec<u32> },
+    Texture(TextureData),
+}
+
+impl Asset {
+    /// The sizes the upload stages, see `Uploader::can_stage`.
+    fn staged_sizes(&self) -> Vec<u64> {
+        match self {
+            Self::Model { vertices, indices } => vec![size_of_val(&vertices[..]) as u64, size_of_val(&indices[..]) as u64],
+            Self::Texture(texture) => vec![texture.pixels.len() as u64],
+        }
+    }
+}
+
+/// An asset whose upload was submitted.
+#[derive(Debug)]
+enum Uploading {
+    Mesh(Mesh),
+    Texture { image: Image, memory: Memory, mip_levels: u32 },
+}
+
+/// Models and textures streamed in while the game runs, see
+/// `Context::uploads`. Files are read and decoded on a thread of their own,
+/// then uploaded at the start of a frame once the staging ring has room.
+/// Rendering never waits for either, poll `is_complete` or `mesh`.
+#[derive(Debug)]
+pub struct Uploads {
+    next_ticket: u64,
+    sender: Sender<(UploadTicket, Result<Asset>)>,
+    loaded: Receiver<(UploadTicket, Result<Asset>)>,
+    /// Read but not uploaded yet, in request order.
+    requested: VecDeque<(UploadTicket, Asset)>,
+    /// Uploading in the batch with the given id.
+    in_flight: Vec<(UploadTicket, BatchId, Uploading)>,
+    complete: HashSet<UploadTicket>,
+    meshes: HashMap<UploadTicket, Mesh>,
+    failed: HashMap<UploadTicket, anyhow::Error>,
+}
+
+impl Default for Uploads {
+    fn default() -> Self {
+        let (sender, loaded) = channel();
+        Self {
+            next_ticket: 0,
+            sender,
+            loaded,
+            requested: VecDeque::new(),
+            in_flight: Vec::new(),
+            complete: HashSet::new(),
+            meshes: HashMap::new(),
+            failed: HashMap::new(),
+        }
+    }
+}
+
+impl Uploads {
+    /// Streams in the OBJ model at `path`, drawn with the `mesh` of the
+    /// returned ticket.
+    pub fn load_model(&mut self, path: impl Into<PathBuf>) -> UploadTicket {
+        let path = path.into();
+        self.load(move || {
+            let mut vertices = Vec::new();
+            let mut indices = Vec::new();
+            read_model(&path, &mut vertices, &mut indices)?;
+            if indices.is_empty() {
+                return Err(anyhow!("Model `{}` has no triangles.", path.display()));
+            }
+            Ok(Asset::Model { vertices, indices })
+        })
+    }
+
+    /// Streams in the PNG at `path` and makes it the texture shared by all
+    /// objects once uploaded, see `EngineBuilder::texture`.
+    pub fn load_texture(&mut self, path: impl Into<PathBuf>) -> UploadTicket {
+        let path = path.into();
+        self.load(move || read_texture(&path).map(Asset::Texture))
+    }
+
+    /// Reads an asset with `read` on a loader thread.
+    fn load(&mut self, read: impl FnOnce() -> Result<Asset> + Send + 'static) -> UploadTicket {
+        let ticket = UploadTicket(self.next_ticket);
+        self.next_ticket += 1;
+        let sender = self.sender.clone();
+        std::thread::spawn(move || {
+            // Only fails once the engine is gone.
+            let _ = sender.send((ticket, read()));
+        });
+        ticket
+    }
+
+    /// The mesh of model `ticket` once it can be drawn, `None` while loading.
+    pub fn mesh(&self, ticket: UploadTicket) -> Option<Mesh> {
+        self.meshes.get(&ticket).copied()
+    }
+
+    /// Whether the upload of `ticket` has finished.
+    pub fn is_complete(&self, ticket: UploadTicket) -> bool {
+        self.complete.contains(&ticket)
+    }
+
+    /// Why `ticket` failed to load, it never completes then.
+    pub fn error(&self, ticket: UploadTicket) -> Option<&anyhow::Error> {
+        self.failed.get(&ticket)
+    }
+}
+
+/// Uploads the assets read since the last frame into resources of their own
+/// and publishes those whose batch has finished. Never waits for the GPU,
+/// assets that don't fit in the staging ring wait for a later frame.
+pub unsafe fn stream_uploads(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    while let Ok((ticket, asset)) = data.uploads.loaded.try_recv() {
+        match asset {
+            Ok(asset) => data.uploads.requested.push_back((ticket, asset)),
+            Err(e) => {
+                warn!("{:#}", e);
+                data.uploads.failed.insert(ticket, e);
+            }
+        }
+    }
+
+    let mut uploading = Vec::new();
+    while let Some((_, asset)) = data.uploads.requested.front() {
+        if !data.uploader.can_stage(&asset.staged_sizes()) {
+            break;
+        }
+        let (ticket, asset) = data.uploads.requested.pop_front().unwrap();
+        let upload = match asset {
+            Asset::Model { vertices, indices } => {
+                let geometry = create_geometry(instance, device, data, &vertices, &indices)?;
+                data.geometry.push(geometry);
+                Uploading::Mesh(Mesh { first_index: 0, index_count: indices.len() as u32, geometry: data.geometry.len() as u32 })
+            }
+            Asset::Texture(texture) => {
+                let (image, memory) = upload_texture(instance, device, data, &texture)?;
+                Uploading::Texture { image, memory, mip_levels: texture.mip_levels() }
+            }
+        };
+        uploading.push((ticket, upload));
+    }
+    if !uploading.is_empty() {
+        let batch = data.uploader.submit(device)?;
+        data.uploads.in_flight.extend(uploading.into_iter().map(|(ticket, upload)| (ticket, batch, upload)));
+    }
+
+    let mut index = 0;
+    while index < data.uploads.in_flight.len() {
+        if !data.uploader.is_complete(data.uploads.in_flight[index].1) {
+            index += 1;
+            continue;
+        }
+        let (ticket, _, upload) = data.uploads.in_flight.remove(index);
+        match upload {
+            Uploading::Mesh(mesh) => {
+                data.uploads.meshes.insert(ticket, mesh);
+            }
+            Uploading::Texture { image, memory, mip_levels } => {
+                // Frames in flight may still sample the old texture, the
+                // deletion queue waits for them.
+                data.texture_image = image;
+                data.texture_image_memory = memory;
+                data.mip_levels = mip_levels;
+                create_texture_image_view(device, data)?;
+                create_texture_sampler(device, data)?;
+                data.stale_texture_descriptors.fill(true);
+            }
+        }
+        data.uploads.complete.insert(ticket);
+    }
+    Ok(())
+}
+
+/// Uploads recorded together and submitted with one fence. With a transfer
+/// queue the copies run there, and the graphics queue waits for them on a
+/// semaphore before acquiring the resources.
+#[derive(Debug)]
+struct Batch {
+    ticket: BatchId,
+    /// Records the copies.
+    transfer: vk::CommandBuffer,
+    /// Acquires the resources and finishes the uploads on the graphics
+    /// queue. The same as `transfer` without a transfer queue.
+    graphics: vk::CommandBuffer,
+    /// Signaled by the transfer submission, null without a transfer queue
+    /// or with a timeline.
+    semaphore: Semaphore,
+    /// Null with a timeline.
+    fence: Fence,
+    /// Ring position up to which this batch staged data.
+    ring_end: u64,
+    /// Staging buffers too large for the ring, dropped once the batch completes.
+    oversized: Vec<(Buffer, Memory)>,
+}
+
+/// The queue family a queue belongs to, and a transient pool for it.
+#[derive(Copy, Clone, Debug, Default)]
+struct UploadQueue {
+    queue: vk::Queue,
+    family: u32,
+    command_pool: vk::CommandPool,
+}
+
+/// Batches uploads to device local memory. Data is written into a staging
+/// ring and the copies are recorded into a shared command buffer, which is
+/// submitted with a fence by `submit` or when the ring runs full. Nothing
+/// waits for the GPU until ring space or the uploaded resources are needed.
+///
+/// Copies run on a dedicated transfer queue when the device has one. The
+/// uploaded resources then change queue family, see `transfer_buffer` and
+/// `transfer_image`.
+#[derive(Debug, Default)]
+pub struct Uploader {
+    ring: Buffer,
+    ring_memory: Memory,
+    /// Monotonic ring positions, the byte offset is the position modulo
+    /// `STAGING_RING_SIZE`. Bytes between `tail` and `head` are in use.
+    head: u64,
+    tail: u64,
+    recording: Option<Batch>,
+    in_flight: VecDeque<Batch>,
+    free_fences: Vec<Fence>,
+    free_semaphores: Vec<Semaphore>,
+    /// Replaces the fences and semaphores with timeline semaphores enabled.
+    timeline: Option<Timeline>,
+    next_ticket: BatchId,
+    /// Every batch up to this ticket has completed.
+    completed: BatchId,
+
+    graphics: UploadQueue,
+    /// `None` when uploads run on the graphics queue.
+    transfer: Option<UploadQueue>,
+    deletion_queue: DeletionQueue,
+    allocator: Allocator,
+}
+
+/// Creates the staging ring. Uploads are recorded from the transient
+/// command pools and run on the transfer queue if there is one.
+pub unsafe fn create_uploader(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+    let (ring, ring_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        STAGING_RING_SIZE,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    let transfer = indices.transfer.map(|family| UploadQueue {
+        queue: data.transfer_queue,
+        family,
+        command_pool: *data.transfer_command_pool,
+    });
+    if let Some(transfer) = transfer {
+        info!("Uploading on transfer queue family {}.", transfer.family);
+    }
+
+    data.uploader = Uploader {
+        ring,
+        ring_memory,
+        next_ticket: 1,
+        graphics: UploadQueue {
+            queue: data.graphics_queue,
+            family: indices.graphics,
+            command_pool: *data.transient_command_pool,
+        },
+        transfer,
+        timeline: if data.timeline_semaphores { Some(Timeline::new(device, &data.deletion_queue)?) } else { None },
+        deletion_queue: data.deletion_queue.clone(),
+        allocator: data.allocator.clone(),
+        ..Default::default()
+    };
+    Ok(())
+}
+
+impl Uploader {
+    /// Copies `items` into staging memory, returns the buffer and offset to
+    /// copy from in the current batch.
+    pub unsafe fn stage<T: Copy>(&mut self, device: &Device, items: &[T]) -> Result<(vk::Buffer, vk::DeviceSize)> {
+        let size = size_of_val(items) as u64;
+        if size > STAGING_RING_SIZE {
+            let (buffer, memory) = self.create_staging_buffer(device, size)?;
+            memcpy(items.as_ptr(), memory.mapped_ptr()?.cast(), items.len());
+            let handle = *buffer;
+            self.batch(device)?.oversized.push((buffer, memory));
+            return Ok((handle, 0));
+        }
+
+        let position = self.reserve(device, size)?;
+        let offset = position % STAGING_RING_SIZE;
+        let memory = self.ring_memory.mapped_ptr()?.cast::<u8>().add(offset as usize);
+        memcpy(items.as_ptr(), memory.cast(), items.len());
+        Ok((*self.ring, offset))
+    }
+
+    /// Stages `items`, records a copy to the start of `destination` and
+    /// hands `destination` to the graphics queue.
+    pub unsafe fn upload_buffer<T: Copy>(&mut self, device: &Device, items: &[T], destination: vk::Buffer) -> Result<()> {
+        let (source, offset) = self.stage(device, items)?;
+        let region = vk::BufferCopy::builder()
+            .src_offset(offset)
+            .dst_offset(0)
+            .size(size_of_val(items) as u64);
+        device.cmd_copy_buffer(self.command_buffer(device)?, source, destination, &[region]);
+        self.transfer_buffer(device, destination)
+    }
+
+    /// The command buffer of the current batch that records the copies.
+    /// Only transfer commands are allowed, it may run on a transfer queue.
+    pub unsafe fn command_buffer(&mut self, device: &Device) -> Result<vk::CommandBuffer> {
+        Ok(self.batch(device)?.transfer)
+    }
+
+    /// The command buffer of the current batch that runs on the graphics
+    /// queue after the copies, for blits and final layout transitions.
+    /// Resources written by `command_buffer` must be handed over with
+    /// `transfer_buffer` or `transfer_image` before they are used here.
+    pub unsafe fn graphics_command_buffer(&mut self, device: &Device) -> Result<vk::CommandBuffer> {
+        Ok(self.batch(device)?.graphics)
+    }
+
+    /// Moves `buffer` from the transfer to the graphics queue family once its
+    /// copies are recorded. Does nothing without a transfer queue.
+    pub unsafe fn transfer_buffer(&mut self, device: &Device, buffer: vk::Buffer) -> Result<()> {
+        let Some(transfer) = self.transfer else {
+            return Ok(());
+        };
+        let graphics_family = self.graphics.family;
+        let batch = self.batch(device)?;
+        let barrier = vk::BufferMemoryBarrier::builder()
+            .src_queue_family_index(transfer.family)
+            .dst_queue_family_index(graphics_family)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE as u64)
+            .build();
+
+        let release = vk::BufferMemoryBarrier { src_access_mask: vk::AccessFlags::TRANSFER_WRITE, ..barrier };
+        device.cmd_pipeline_barrier(
+            batch.transfer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[release],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        let acquire = vk::BufferMemoryBarrier {
+            dst_access_mask: vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+            ..barrier
+        };
+        device.cmd_pipeline_barrier(
+            batch.graphics,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[acquire],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+        Ok(())
+    }
+
+    /// Moves `range` of `image`, left in `layout` by the copies, from the
+    /// transfer to the graphics queue family. Does nothing without a
+    /// transfer queue.
+    pub unsafe fn transfer_image(
+        &mut self,
+        device: &Device,
+        image: vk::Image,
+        layout: vk::ImageLayout,
+        range: vk::ImageSubresourceRange,
+    ) -> Result<()> {
+        let Some(transfer) = self.transfer else {
+            return Ok(());
+        };
+        let graphics_family = self.graphics.family;
+        let batch = self.batch(device)?;
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(layout)
+            .new_layout(layout)
+            .src_queue_family_index(transfer.family)
+            .dst_queue_family_index(graphics_family)
+            .image(image)
+            .subresource_range(range)
+            .build();
+
+        let release = vk::ImageMemoryBarrier { src_access_mask: vk::AccessFlags::TRANSFER_WRITE, ..barrier };
+        device.cmd_pipeline_barrier(
+            batch.transfer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[release],
+        );
+
+        let acquire = vk::ImageMemoryBarrier {
+            dst_access_mask: vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+            ..barrier
+        };
+        device.cmd_pipeline_barrier(
+            batch.graphics,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[acquire],
+        );
+        Ok(())
+    }
+
+    /// Submits the current batch without waiting for it. The uploaded
+    /// resources may be used once `is_complete` returns true for the ticket.
+    pub unsafe fn submit(&mut self, device: &Device) -> Result<BatchId> {
+        let Some(batch) = self.recording.take() else {
+            return Ok(self.next_ticket - 1);
+        };
+
+        // Make the uploads visible to whatever is submitted after them.
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ);
+        device.cmd_pipeline_barrier(
+            batch.graphics,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        if self.transfer.is_some() {
+            device.end_command_buffer(batch.transfer)?;
+        }
+        device.end_command_buffer(batch.graphics)?;
+        match &self.timeline {
+            Some(timeline) => self.submit_timeline(device, &batch, timeline)?,
+            None => self.submit_binary(device, &batch)?,
+        }
+
+        let ticket = batch.ticket;
+        self.in_flight.push_back(batch);
+        self.poll(device)?;
+        Ok(ticket)
+    }
+
+    /// Submits a batch synchronized with a binary semaphore and a fence.
+    unsafe fn submit_binary(&self, device: &Device, batch: &Batch) -> Result<()> {
+        if let Some(transfer) = self.transfer {
+            let command_buffers = &[batch.transfer];
+            let signal_semaphores = &[*batch.semaphore];
+            let info = vk::SubmitInfo::builder()
+                .command_buffers(command_buffers)
+                .signal_semaphores(signal_semaphores);
+            device.queue_submit(transfer.queue, &[info], vk::Fence::null())?;
+        }
+
+        let command_buffers = &[batch.graphics];
+        let wait_semaphores = &[*batch.semaphore];
+        let wait_stages = &[vk::PipelineStageFlags::ALL_COMMANDS];
+        let mut info = vk::SubmitInfo::builder().command_buffers(command_buffers);
+        if self.transfer.is_some() {
+            info = info.wait_semaphores(wait_semaphores).wait_dst_stage_mask(wait_stages);
+        }
+        device.queue_submit(self.graphics.queue, &[info], *batch.fence)?;
+        Ok(())
+    }
+
+    /// Submits a batch synchronized with `timeline`, see [`TimelineValues`].
+    unsafe fn submit_timeline(&self, device: &Device, batch: &Batch, timeline: &Timeline) -> Result<()> {
+        let values = TimelineValues::new(batch.ticket);
+        let semaphores = &[timeline.semaphore()];
+        let transfer_values = &[values.transfer_signal];
+        if let Some(transfer) = self.transfer {
+            let command_buffers = &[batch.transfer];
+            let wait_values = &[values.transfer_wait];
+            let wait_stages = &[vk::PipelineStageFlags::TRANSFER];
+            let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+                .wait_semaphore_values(wait_values)
+                .signal_semaphore_values(transfer_values);
+            let info = vk::SubmitInfo::builder()
+                .wait_semaphores(semaphores)
+                .wait_dst_stage_mask(wait_stages)
+                .command_buffers(command_buffers)
+                .signal_semaphores(semaphores)
+                .push_next(&mut timeline_info);
+            device.queue_submit(transfer.queue, &[info], vk::Fence::null())?;
+        }
+
+        let command_buffers = &[batch.graphics];
+        let wait_stages = &[vk::PipelineStageFlags::ALL_COMMANDS];
+        let graphics_values = &[values.graphics_signal];
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .signal_semaphore_values(graphics_values);
+        if self.transfer.is_some() {
+            timeline_info = timeline_info.wait_semaphore_values(transfer_values);
+        }
+        let mut info = vk::SubmitInfo::builder()
+            .command_buffers(command_buffers)
+            .signal_semaphores(semaphores);
+        if self.transfer.is_some() {
+            info = info.wait_semaphores(semaphores).wait_dst_stage_mask(wait_stages);
+        }
+        let info = info.push_next(&mut timeline_info);
+        device.queue_submit(self.graphics.queue, &[info], vk::Fence::null())?;
+        Ok(())
+    }
+
+    /// Submits the current batch and waits for all uploads to finish.
+    pub unsafe fn wait(&mut self, device: &Device) -> Result<()> {
+        self.submit(device)?;
+        while !self.in_flight.is_empty() {
+            self.retire(device, true)?;
+        }
+        Ok(())
+    }
+
+    /// Releases the batches that have finished, without waiting.
+    pub unsafe fn poll(&mut self, device: &Device) -> Result<()> {
+        self.retire(device, false)
+    }
+
+    /// Whether the batch of `ticket` has finished, as of the last `poll`.
+    pub fn is_complete(&self, ticket: BatchId) -> bool {
+        ticket <= self.completed
+    }
+
+    /// Releases finished batches, waiting for the oldest one if `wait`.
+    unsafe fn retire(&mut self, device: &Device, wait: bool) -> Result<()> {
+        while let Some(batch) = self.in_flight.front() {
+            let done = match (&self.timeline, wait) {
+                (Some(timeline), true) => {
+                    timeline.wait(device, batch.ticket * 2)?;
+                    true
+                }
+                (Some(timeline), false) => timeline.reached(device, batch.ticket * 2)?,
+                (None, true) => {
+                    device.wait_for_fences(&[*batch.fence], true, u64::MAX)?;
+                    true
+                }
+                (None, false) => device.get_fence_status(*batch.fence)? == vk::SuccessCode::SUCCESS,
+            };
+            if !done {
+                break;
+            }
+
+            let batch = self.in_flight.pop_front().unwrap();
+            self.tail = batch.ring_end;
+            self.completed = batch.ticket;
+            if let Some(transfer) = self.transfer {
+                device.free_command_buffers(transfer.command_pool, &[batch.transfer]);
+            }
+            if !batch.semaphore.is_null() {
+                self.free_semaphores.push(batch.semaphore);
+            }
+            device.free_command_buffers(self.graphics.command_pool, &[batch.graphics]);
+            if !batch.fence.is_null() {
+                device.reset_fences(&[*batch.fence])?;
+                self.free_fences.push(batch.fence);
+            }
+            if wait {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether staging data of `sizes` now wouldn't have to wait for the GPU
+    /// to free ring space.
+    pub fn can_stage(&self, sizes: &[u64]) -> bool {
+        // Idle, `reserve` would start over at the beginning of the ring.
+        let (mut head, tail) = if self.recording.is_none() && self.in_flight.is_empty() {
+            (0, 0)
+        } else {
+            (self.head, self.tail)
+        };
+        for &size in sizes.iter().filter(|&&size| size <= STAGING_RING_SIZE) {
+            match ring_position(head, tail, size) {
+                Some(start) => head = start + size,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Claims `size` contiguous bytes of the ring, submitting the current
+    /// batch and waiting for older ones while the ring is full.
+    unsafe fn reserve(&mut self, device: &Device, size: u64) -> Result<u64> {
+        loop {
+            if let Some(start) = ring_position(self.head, self.tail, size) {
+                self.head = start + size;
+                let head = self.head;
+                self.batch(device)?.ring_end = head;
+                return Ok(start);
+            }
+
+            if self.recording.is_some() {
+                self.submit(device)?;
+            } else if !self.in_flight.is_empty() {
+                self.retire(device, true)?;
+            } else {
+                // Idle, start over at the beginning of the ring.
+                self.head = 0;
+                self.tail = 0;
+            }
+        }
+    }
+
+    /// The batch being recorded, started on first use.
+    unsafe fn batch(&mut self, device: &Device) -> Result<&mut Batch> {
+        if self.recording.is_none() {
+            let graphics = begin_command_buffer(device, self.graphics.command_pool)?;
+            let transfer = match self.transfer {
+                Some(transfer) => begin_command_buffer(device, transfer.command_pool)?,
+                None => graphics,
+            };
+            // A timeline replaces the semaphore and fence.
+            let semaphore = if self.transfer.is_none() || self.timeline.is_some() {
+                Semaphore::default()
+            } else {
+                match self.free_semaphores.pop() {
+                    Some(semaphore) => semaphore,
+                    None => self.deletion_queue.own(device.create_semaphore(&vk::SemaphoreCreateInfo::builder(), None)?),
+                }
+            };
+            let fence = if self.timeline.is_some() {
+                Fence::default()
+            } else {
+                match self.free_fences.pop() {
+                    Some(fence) => fence,
+                    None => self.deletion_queue.own(device.create_fence(&vk::FenceCreateInfo::builder(), None)?),
+                }
+            };
+            self.recording = Some(Batch {
+                ticket: self.next_ticket,
+                transfer,
+                graphics,
+                semaphore,
+                fence,
+                ring_end: self.head,
+                oversized: Vec::new(),
+            });
+            self.next_ticket += 1;
+        }
+        Ok(self.recording.as_mut().unwrap())
+    }
+
+    unsafe fn create_staging_buffer(&self, device: &Device, size: u64) -> Result<(Buffer, Memory)> {
+        let info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = self.deletion_queue.own(device.create_buffer(&info, None)?);
+
+        let requirements = device.get_buffer_memory_requirements(*buffer);
+        let memory = self.deletion_queue.own(self.allocator.allocate(
+            device,
+            requirements,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+            ResourceKind::Buffer,
+            Strategy::Linear,
+        )?);
+        device.bind_buffer_memory(*buffer, memory.memory, memory.offset)?;
+        Ok((buffer, memory))
+    }
+}
+
+/// Where `size` bytes would be staged with the ring in use between `tail`
+/// and `head`, `None` if they don't fit until older batches finish.
+fn ring_position(head: u64, tail: u64, size: u64) -> Option<u64> {
+    let mut start = head.next_multiple_of(STAGING_ALIGNMENT);
+    if start % STAGING_RING_SIZE + size > STAGING_RING_SIZE {
+        start = start.next_multiple_of(STAGING_RING_SIZE);
+    }
+    (start + size - tail <= STAGING_RING_SIZE).then_some(start)
+}
+
+unsafe fn begin_command_buffer(device: &Device, command_pool: vk::CommandPool) -> Result<vk::CommandBuffer> {
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_pool(command_pool)
+        .command_buffer_count(1);
+    let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+    let info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(command_buffer, &info)?;
+    Ok(command_buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeline_values_increase_across_queues() {
+        let first = TimelineValues::new(1);
+        let second = TimelineValues::new(2);
+        assert_eq!(first.transfer_wait, 0);
+        // The second copy cannot start before the first batch is done on the
+        // graphics queue, so the signals happen in this order.
+        assert_eq!(second.transfer_wait, first.graphics_signal);
+        let signals = [first.transfer_signal, first.graphics_signal, second.transfer_signal, second.graphics_signal];
+        assert!(signals.windows(2).all(|pair| pair[0] < pair[1]), "{signals:?}");
+    }
+
+    #[test]
+    fn ring_position_waits_instead_of_overwriting() {
+        assert_eq!(ring_position(0, 0, 100), Some(0));
+        assert_eq!(ring_position(100, 0, 100), Some(112));
+        // Data that would straddle the end starts over at the beginning.
+        let almost_full = STAGING_RING_SIZE - 8;
+        assert_eq!(ring_position(almost_full, 64, 32), Some(STAGING_RING_SIZE));
+        // The start of the ring is still in use.
+        assert_eq!(ring_position(almost_full, 16, 32), None);
+        assert_eq!(ring_position(STAGING_RING_SIZE, 0, 1), None);
+    }
+}
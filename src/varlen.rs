@@ -1,4 +0,0 @@
-use varlen::prelude::*;
-
-// Define a variable-length tuple:
-
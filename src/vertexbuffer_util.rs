@@ -1,42 +1,32 @@
-use std::mem::size_of;
-use anyhow::{Result};
+use std::mem::{size_of, size_of_val};
+use anyhow::{anyhow, Result};
 use cgmath::{vec2, vec3};
 use vulkanalia::{vk, Device, Instance};
-use vulkanalia::vk::{DeviceV1_0, HasBuilder};
+use vulkanalia::vk::HasBuilder;
 use crate::render_app::AppData;
-use std::ptr::copy_nonoverlapping as memcpy;
-use crate::buffer_util::{copy_buffer, create_buffer};
-use varlen::*;
-use varlen_macro::define_varlen;
+use crate::buffer_util::create_buffer;
+use crate::resource_util::{Buffer, Memory};
 use std::collections::HashMap;
 use std::fs::File;
 use std::hash::{Hash, Hasher};
 use std::io::BufReader;
+use std::path::Path;
+use crate::scene::Mesh;
 
 type Vec2 = cgmath::Vector2<f32>;
 type Vec3 = cgmath::Vector3<f32>;
 
-#[repr(C)]
-#[derive(Debug)]
-/// texture coordinates and paths to texture file
-pub struct Texture { pub tex_string : String, pub tex_coords : Vec<Vec2> }
-/// color is either encoded as RGB triplets or texture coordinates and paths to texture file
-#[repr(C)]
-#[derive(Debug)]
-pub enum Colors { RGB(Vec<Vec3>), Texture(Texture) }
-
-
-
- #[derive(Clone, Debug, Default)]
- pub struct VertexData {
-     pub vertices: Vec<Vertex>,
-     pub indices: Vec<u32>,
-     pub vertex_buffer: vk::Buffer,
-     pub vertex_buffer_memory: vk::DeviceMemory,
- }
+/// Appends the OBJ model at `path` to the shared vertex and index data.
+pub fn load_model(data: &mut AppData, path: &Path) -> Result<Mesh> {
+    let first_index = data.indices.len() as u32;
+    read_model(path, &mut data.vertices, &mut data.indices)?;
+    Ok(Mesh { first_index, index_count: data.indices.len() as u32 - first_index, geometry: 0 })
+}
 
-pub fn load_model(data: &mut AppData) -> Result<()> {
-    let mut reader = BufReader::new(File::open("src/resources/viking_room.obj")?);
+/// Appends the OBJ model at `path` to `vertices` and `indices`.
+pub fn read_model(path: &Path, vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>) -> Result<()> {
+    let file = File::open(path).map_err(|e| anyhow!("Failed to open model `{}`: {}", path.display(), e))?;
+    let mut reader = BufReader::new(file);
 
     let (models,_) = tobj::load_obj_buf(
         &mut reader,
@@ -47,7 +37,7 @@ pub fn load_model(data: &mut AppData) -> Result<()> {
     let mut unique_vertices = HashMap::new();
 
     for model in &models {
-        for index in &model.mesh.indices {
+        for (i, index) in model.mesh.indices.iter().enumerate() {
             let pos_offset = (3 * index) as usize;
             let tex_coord_offset = (2 * index) as usize;
             let vertex = Vertex {
@@ -61,17 +51,17 @@ pub fn load_model(data: &mut AppData) -> Result<()> {
                     model.mesh.texcoords[tex_coord_offset],
                     1.0 - model.mesh.texcoords[tex_coord_offset + 1],
                 ),
-
+                normal: vertex_normal(&model.mesh, i),
             };
 
 
             if let Some(index) = unique_vertices.get(&vertex) {
-                data.indices.push(*index as u32);
+                indices.push(*index as u32);
             } else {
-                let index = data.vertices.len();
+                let index = vertices.len();
                 unique_vertices.insert(vertex, index);
-                data.vertices.push(vertex);
-                data.indices.push(index as u32);
+                vertices.push(vertex);
+                indices.push(index as u32);
             }
 
 
@@ -80,69 +70,20 @@ pub fn load_model(data: &mut AppData) -> Result<()> {
     Ok(())
 }
 
-#[repr(C)]
-#[define_varlen]
-pub struct MeshData {
-    #[controls_layout]
-    pub s: usize,
-    #[varlen_array]
-    pub positions: [Vec3;*s],
-    #[varlen_array]
-    pub normals:  [Vec3;*s],
-    pub indices: Option<Vec<u16>>,
-    pub colors : Colors
-}
-/*
-
-impl MeshData {
-    pub fn new(vertices: Vec<Vec3>, indices: &[u16], colors: &[Colors]) -> Result<MeshData> {
-
-        let v = vec![9];
+/// Normal of the `i`th index of `mesh`, falling back to +Z for meshes
+/// exported without normals.
+fn vertex_normal(mesh: &tobj::Mesh, i: usize) -> Vec3 {
+    if mesh.normals.is_empty() {
+        return vec3(0.0, 0.0, 1.0);
     }
-
-    }
-
-*/
-
-pub static VERTICES: [Vertex; 8] = [
-    Vertex::new(vec3(-0.5, -0.5, 0.0), vec3(1.0, 0.0, 0.0), vec2(1.0, 0.0)),
-    Vertex::new(vec3(0.5, -0.5, 0.0), vec3(0.0, 1.0, 0.0), vec2(0.0, 0.0)),
-    Vertex::new(vec3(0.5, 0.5, 0.0), vec3(0.0, 0.0, 1.0), vec2(0.0, 1.0)),
-    Vertex::new(vec3(-0.5, 0.5, 0.0), vec3(1.0, 1.0, 1.0), vec2(1.0, 1.0)),
-    Vertex::new(vec3(-0.5, -0.5, -0.5), vec3(1.0, 0.0, 0.0), vec2(1.0, 0.0)),
-    Vertex::new(vec3(0.5, -0.5, -0.5), vec3(0.0, 1.0, 0.0), vec2(0.0, 0.0)),
-    Vertex::new(vec3(0.5, 0.5, -0.5), vec3(0.0, 0.0, 1.0), vec2(0.0, 1.0)),
-    Vertex::new(vec3(-0.5, 0.5, -0.5), vec3(1.0, 1.0, 1.0), vec2(1.0, 1.0)),
-];
-
-pub const INDICES: &[u16] = &[
-    0, 1, 2, 2, 3, 0,
-    4, 5, 6, 6, 7, 4,
-];
-/*
-pub fn test_mesh1() -> MeshData{ MeshData {
-    positions: [vec3(-0.5, -0.5, 0.0),
-        vec3(0.5, -0.5, 0.0),
-        vec3(0.5, 0.5, 0.0),
-        vec3(-0.5, 0.5, 0.0),
-        vec3(-0.5, -0.5, -0.5),
-        vec3(0.5, -0.5, -0.5),
-        vec3(0.5, 0.5, -0.5),
-        vec3(-0.5, 0.5, -0.5)],
-    normals: None,
-    indices: Some(Vec::from([0u16, 1u16, 2u16, 2u16, 3u16, 0u16,
-        4u16, 5u16, 6u16, 6u16, 7u16, 4u16])),
-    vertex_count: 8,
-    colors: Colors::Texture(Texture {
-        tex_string: "src/resources/birk.png".to_string(),
-        tex_coords: Vec::from([vec2(1.0, 0.0), vec2(0.0, 0.0),
-            vec2(0.0, 1.0), vec2(1.0, 1.0),
-            vec2(1.0, 0.0), vec2(0.0, 0.0),
-            vec2(0.0, 1.0), vec2(1.0, 1.0, )]
-        ),
-    })
+    let index = mesh.normal_indices.get(i).copied().unwrap_or(mesh.indices[i]);
+    let offset = (3 * index) as usize;
+    vec3(
+        mesh.normals[offset],
+        mesh.normals[offset + 1],
+        mesh.normals[offset + 2],
+    )
 }
-}*/
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
@@ -150,14 +91,10 @@ pub struct Vertex {
     pub pos: Vec3,
     pub color:Vec3,
     pub tex_coord: Vec2,
+    pub normal: Vec3,
 }
 
 impl Vertex {
-    pub const fn new(pos: Vec3,
-                     color: Vec3,
-                     tex_coord: Vec2, ) -> Self { Self { pos, color, tex_coord}
-    }
-
     pub fn binding_description() -> vk::VertexInputBindingDescription {
         vk::VertexInputBindingDescription::builder()
             .binding(0)
@@ -166,7 +103,7 @@ impl Vertex {
             .build()
     }
 
-    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 4] {
     let pos = vk::VertexInputAttributeDescription::builder()
     .binding(0)
     .location(0)
@@ -185,7 +122,13 @@ impl Vertex {
     .format(vk::Format::R32G32_SFLOAT)
     .offset((size_of::<Vec3>() + size_of::<Vec3>()) as u32)
     .build();
-    [pos, color, tex_coord]
+    let normal = vk::VertexInputAttributeDescription::builder()
+    .binding(0)
+    .location(3)
+    .format(vk::Format::R32G32B32_SFLOAT)
+    .offset((size_of::<Vec3>() + size_of::<Vec3>() + size_of::<Vec2>()) as u32)
+    .build();
+    [pos, color, tex_coord, normal]
     }
 }
 
@@ -195,27 +138,7 @@ pub(crate) unsafe fn create_vertex_buffer(
     device: &Device,
     data: &mut AppData,
 ) -> Result<()> {
-    let size = (size_of::<Vertex>() * data.vertices.len()/*VERTICES.len()*/) as u64;
-
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
-        instance,
-        device,
-        data,
-        size,
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
-    )?;
-
-    let memory = device.map_memory(
-        staging_buffer_memory,
-        0,
-        size,
-        vk::MemoryMapFlags::empty(),
-    )?;
-
-    memcpy(data.vertices.as_ptr()/*VERTICES.as_ptr()*/, memory.cast(), data.vertices.len());
-
-    device.unmap_memory(staging_buffer_memory);
+    let size = (size_of::<Vertex>() * data.vertices.len()) as u64;
 
     let (vertex_buffer, vertex_buffer_memory) = create_buffer(
         instance,
@@ -225,10 +148,7 @@ pub(crate) unsafe fn create_vertex_buffer(
         vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
-    copy_buffer(device, data, staging_buffer, vertex_buffer, size)?;
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
-
+    data.uploader.upload_buffer(device, &data.vertices, *vertex_buffer)?;
 
     data.vertex_buffer = vertex_buffer;
     data.vertex_buffer_memory = vertex_buffer_memory;
@@ -241,27 +161,7 @@ pub unsafe fn create_index_buffer(
     device: &Device,
     data: &mut AppData,
 ) -> Result<()> {
-    let size = (size_of::<u32>() * data.indices.len()/*INDICES.len()*/) as u64;
-
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
-        instance,
-        device,
-        data,
-        size,
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
-    )?;
-
-    let memory = device.map_memory(
-        staging_buffer_memory,
-        0,
-        size,
-        vk::MemoryMapFlags::empty(),
-    )?;
-
-    memcpy(data.indices.as_ptr(), memory.cast(), data.indices.len());
-
-    device.unmap_memory(staging_buffer_memory);
+    let size = (size_of::<u32>() * data.indices.len()) as u64;
 
     let (index_buffer, index_buffer_memory) = create_buffer(
         instance,
@@ -272,14 +172,11 @@ pub unsafe fn create_index_buffer(
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
+    data.uploader.upload_buffer(device, &data.indices, *index_buffer)?;
+
     data.index_buffer = index_buffer;
     data.index_buffer_memory = index_buffer_memory;
 
-    copy_buffer(device, data, staging_buffer, index_buffer, size)?;
-
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
-
     Ok(())
 }
 
@@ -295,6 +192,7 @@ impl PartialEq for Vertex {
         self.pos == other.pos
             && self.color == other.color
             && self.tex_coord == other.tex_coord
+            && self.normal == other.normal
     }
 }
 
@@ -310,7 +208,67 @@ impl Hash for Vertex {
         self.color[2].to_bits().hash(state);
         self.tex_coord[0].to_bits().hash(state);
         self.tex_coord[1].to_bits().hash(state);
+        self.normal[0].to_bits().hash(state);
+        self.normal[1].to_bits().hash(state);
+        self.normal[2].to_bits().hash(state);
     }
 }
 
 
+
+/// Vertex and index buffers of a model streamed in after startup, see
+/// `Mesh::geometry`.
+#[derive(Debug, Default)]
+pub struct Geometry {
+    pub vertex_buffer: Buffer,
+    /// Freed with the geometry.
+    _vertex_buffer_memory: Memory,
+    pub index_buffer: Buffer,
+    _index_buffer_memory: Memory,
+}
+
+/// Creates buffers for `vertices` and `indices` and records their upload
+/// into the current batch, without submitting it.
+pub(crate) unsafe fn create_geometry(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    vertices: &[Vertex],
+    indices: &[u32],
+) -> Result<Geometry> {
+    let (vertex_buffer, vertex_buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        size_of_val(vertices) as u64,
+        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+    let (index_buffer, index_buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        size_of_val(indices) as u64,
+        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+    data.uploader.upload_buffer(device, vertices, *vertex_buffer)?;
+    data.uploader.upload_buffer(device, indices, *index_buffer)?;
+    Ok(Geometry {
+        vertex_buffer,
+        _vertex_buffer_memory: vertex_buffer_memory,
+        index_buffer,
+        _index_buffer_memory: index_buffer_memory,
+    })
+}
+
+/// The vertex and index buffers holding meshes with `geometry`.
+pub(crate) fn geometry_buffers(data: &AppData, geometry: u32) -> (vk::Buffer, vk::Buffer) {
+    match geometry.checked_sub(1) {
+        None => (*data.vertex_buffer, *data.index_buffer),
+        Some(i) => {
+            let geometry = &data.geometry[i as usize];
+            (*geometry.vertex_buffer, *geometry.index_buffer)
+        }
+    }
+}